@@ -0,0 +1,50 @@
+use amethyst::ecs::prelude::{Component, DenseVecStorage, Entity};
+
+// a team's flag stand, where its flag is kept and where the team scores
+pub struct Base {
+    pub team: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Component for Base {
+    type Storage = DenseVecStorage<Self>;
+}
+
+// a team's flag
+pub struct Flag {
+    pub team: usize,
+    // the tile of the flag stand the flag is returned to
+    pub home: (usize, usize),
+    // the tile the flag is on, which is its carrier's tile while it's carried
+    pub x: usize,
+    pub y: usize,
+    pub carrier: Option<Entity>,
+}
+
+impl Flag {
+    pub fn new(team: usize, home: (usize, usize)) -> Flag {
+        Flag {
+            team,
+            home,
+            x: home.0,
+            y: home.1,
+            carrier: None,
+        }
+    }
+
+    pub fn is_home(&self) -> bool {
+        self.carrier.is_none() && (self.x, self.y) == self.home
+    }
+
+    // sends the flag back to its stand
+    pub fn return_home(&mut self) {
+        self.carrier = None;
+        self.x = self.home.0;
+        self.y = self.home.1;
+    }
+}
+
+impl Component for Flag {
+    type Storage = DenseVecStorage<Self>;
+}
\ No newline at end of file
@@ -1,3 +1,9 @@
+mod ctf;
+mod object;
 mod tile;
+mod unit;
 
-pub use self::tile::{TerrainTile, Tile, TileUIElement, TileUIElementType};
\ No newline at end of file
+pub use self::ctf::{Base, Flag};
+pub use self::object::{MapObject, ObjectKind, ObjectSlot, OBJECT_SLOTS};
+pub use self::tile::{Region, Slope, SlopeTiles, TerrainTile, Tile, TileUIElement, TileUIElementType, VariantMode};
+pub use self::unit::{Selected, Unit};
\ No newline at end of file
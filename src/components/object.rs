@@ -0,0 +1,129 @@
+use amethyst::ecs::prelude::{Component, DenseVecStorage};
+use serde::{Serialize, Deserialize};
+
+use crate::util::TileLayer;
+
+// what an object placed on the map is
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    // where a team's units appear
+    Spawn,
+    // where a team's flag is kept, and where the team scores
+    FlagStand,
+    // blocks movement through its tile
+    Wall,
+    // only for show, drawn with a sprite from the terrain pack
+    Decoration(usize),
+}
+
+impl ObjectKind {
+    // checks if the object belongs to a team
+    pub fn has_team(&self) -> bool {
+        match self {
+            ObjectKind::Spawn | ObjectKind::FlagStand => true,
+            _ => false,
+        }
+    }
+
+    // checks if units can't walk onto the object's tile
+    pub fn blocks_movement(&self) -> bool {
+        *self == ObjectKind::Wall
+    }
+}
+
+// where on its tile an object is placed, from the back of the tile to the front
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectSlot {
+    UL, UR, CU,
+    CLU, CRU,
+    LU, RU,
+    C,
+    LD, RD,
+    CLD, CRD,
+    DL, DR, CD,
+}
+
+pub const OBJECT_SLOTS: [ObjectSlot; 15] = [
+    ObjectSlot::UL, ObjectSlot::UR, ObjectSlot::CU,
+    ObjectSlot::CLU, ObjectSlot::CRU,
+    ObjectSlot::LU, ObjectSlot::RU,
+    ObjectSlot::C,
+    ObjectSlot::LD, ObjectSlot::RD,
+    ObjectSlot::CLD, ObjectSlot::CRD,
+    ObjectSlot::DL, ObjectSlot::DR, ObjectSlot::CD,
+];
+
+impl ObjectSlot {
+    // the layer the slot is drawn on, so objects in front cover the ones behind
+    pub fn layer(&self) -> TileLayer {
+        match self {
+            ObjectSlot::UL => TileLayer::ObjectUL,
+            ObjectSlot::UR => TileLayer::ObjectUR,
+            ObjectSlot::CU => TileLayer::ObjectCU,
+            ObjectSlot::CLU => TileLayer::ObjectCLU,
+            ObjectSlot::CRU => TileLayer::ObjectCRU,
+            ObjectSlot::LU => TileLayer::ObjectLU,
+            ObjectSlot::RU => TileLayer::ObjectRU,
+            ObjectSlot::C => TileLayer::ObjectC,
+            ObjectSlot::LD => TileLayer::ObjectLD,
+            ObjectSlot::RD => TileLayer::ObjectRD,
+            ObjectSlot::CLD => TileLayer::ObjectCLD,
+            ObjectSlot::CRD => TileLayer::ObjectCRD,
+            ObjectSlot::DL => TileLayer::ObjectDL,
+            ObjectSlot::DR => TileLayer::ObjectDR,
+            ObjectSlot::CD => TileLayer::ObjectCD,
+        }
+    }
+
+    // how far the slot is from the middle of the tile, as (across, down) the screen
+    // both go from -1 to 1, and stay inside the tile's diamond
+    pub fn offset(&self) -> (f32, f32) {
+        match self {
+            ObjectSlot::UL => (-0.3, -0.6),
+            ObjectSlot::UR => (0.3, -0.6),
+            ObjectSlot::CU => (0., -0.6),
+            ObjectSlot::CLU => (-0.3, -0.4),
+            ObjectSlot::CRU => (0.3, -0.4),
+            ObjectSlot::LU => (-0.6, -0.2),
+            ObjectSlot::RU => (0.6, -0.2),
+            ObjectSlot::C => (0., 0.),
+            ObjectSlot::LD => (-0.6, 0.2),
+            ObjectSlot::RD => (0.6, 0.2),
+            ObjectSlot::CLD => (-0.3, 0.4),
+            ObjectSlot::CRD => (0.3, 0.4),
+            ObjectSlot::DL => (-0.3, 0.6),
+            ObjectSlot::DR => (0.3, 0.6),
+            ObjectSlot::CD => (0., 0.6),
+        }
+    }
+
+    // where the slot is in OBJECT_SLOTS, which goes from back to front
+    pub fn index(&self) -> usize {
+        OBJECT_SLOTS.iter().position(|slot| slot == self).unwrap_or(0)
+    }
+
+    // the slot after this one, wrapping around
+    pub fn next(&self) -> ObjectSlot {
+        OBJECT_SLOTS[(self.index() + 1) % OBJECT_SLOTS.len()]
+    }
+}
+
+// an object placed on the map
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapObject {
+    pub kind: ObjectKind,
+    pub x: usize,
+    pub y: usize,
+    #[serde(default = "slot_centre")]
+    pub slot: ObjectSlot,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<usize>,
+}
+
+impl Component for MapObject {
+    type Storage = DenseVecStorage<Self>;
+}
+
+fn slot_centre() -> ObjectSlot {
+    ObjectSlot::C
+}
\ No newline at end of file
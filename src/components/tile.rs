@@ -1,13 +1,27 @@
 use amethyst::ecs::prelude::{Component, DenseVecStorage};
-use rand::{Rng, thread_rng};
 use serde::{Serialize, Deserialize};
-use crate::util::TILE_SIZE;
+use crate::util::{tile_hash, TILE_SIZE};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Slope {
     None, NW, NE, SE, SW,
 }
 
+// marks a tile as part of a team's spawn area or flag base
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Spawn(usize),
+    Base(usize),
+}
+
+impl Region {
+    pub fn team(&self) -> usize {
+        match self {
+            Region::Spawn(team) | Region::Base(team) => *team,
+        }
+    }
+}
+
 // serves as a tile "generator", from the Terrain resource pack
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TerrainTile {
@@ -18,20 +32,110 @@ pub struct TerrainTile {
     pub height: usize,
     #[serde(default = "slope_none")]
     pub slope: Slope,
+    #[serde(default)]
+    pub variants: VariantMode,
+    #[serde(default)]
+    pub slope_tiles: SlopeTiles,
+    // whether tiles of this terrain can be turned into ramps by slope inference
+    #[serde(default = "default_true")]
+    pub ramps: bool,
+    // how much it costs to move onto a tile of this terrain
+    #[serde(default = "default_one")]
+    pub move_cost: f32,
+    // whether units can walk on it
+    #[serde(default = "default_true")]
+    pub passable: bool,
+    // whether units can see past it
+    #[serde(default)]
+    pub blocks_sight: bool,
+    // how much of an attack a unit on it avoids, from 0 to 1
+    #[serde(default)]
+    pub cover: f32,
+    // multiplies the speed of units moving across it
+    #[serde(default = "default_one")]
+    pub speed_modifier: f32,
+}
+
+// the sprites of a terrain when it's sloped, for each direction the slope rises towards
+// a direction without sprites falls back to the flat sprites
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SlopeTiles {
+    #[serde(default)]
+    pub nw: Vec<usize>,
+    #[serde(default)]
+    pub ne: Vec<usize>,
+    #[serde(default)]
+    pub se: Vec<usize>,
+    #[serde(default)]
+    pub sw: Vec<usize>,
+}
+
+impl SlopeTiles {
+    pub fn get(&self, slope: Slope) -> &[usize] {
+        match slope {
+            Slope::None => &[],
+            Slope::NW => &self.nw,
+            Slope::NE => &self.ne,
+            Slope::SE => &self.se,
+            Slope::SW => &self.sw,
+        }
+    }
+}
+
+// how a TerrainTile picks a sprite when it has more than one
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariantMode {
+    // pseudo-randomly, from the map seed and the tile's position
+    Seeded,
+    // cycling through the sprites along each row, shifted by one each row
+    Pattern,
+}
+
+impl Default for VariantMode {
+    fn default() -> VariantMode {
+        VariantMode::Seeded
+    }
 }
 
 impl TerrainTile {
     // generates a Tile from the parameters and its own data
-    // randomly assigns a sprite to the tile if there are multiple sprites for this TerrainTile
-    // possibly add option for tiling by index instead of randomly
-    pub fn create_tile(self, x: usize, y: usize, e: usize) -> Tile {
-        return Tile{
-            sprite_index: self.tiles[thread_rng().gen_range(0, self.tiles.len())],
+    // picks the sprite for the tile if there are multiple sprites for this TerrainTile,
+    // so the same map and seed always look the same
+    pub fn create_tile(&self, x: usize, y: usize, e: usize, seed: u64) -> Tile {
+        self.create_tile_variant(x, y, e, self.pick_variant(x, y, seed))
+    }
+
+    // picks which of the sprites a tile at (x,y) uses
+    pub fn pick_variant(&self, x: usize, y: usize, seed: u64) -> usize {
+        match self.variants {
+            VariantMode::Seeded => (tile_hash(seed, x, y) % self.tiles.len() as u64) as usize,
+            VariantMode::Pattern => (x + y) % self.tiles.len(),
+        }
+    }
+
+    // finds the sprite of a variant, using the sloped sprites if the terrain has them
+    pub fn sprite_for(&self, variant: usize, slope: Slope) -> usize {
+        let sloped = self.slope_tiles.get(slope);
+        if sloped.is_empty() {
+            self.tiles[variant % self.tiles.len()]
+        } else {
+            sloped[variant % sloped.len()]
+        }
+    }
+
+    // generates a Tile using a specific sprite from this TerrainTile
+    pub fn create_tile_variant(&self, x: usize, y: usize, e: usize, variant: usize) -> Tile {
+        let variant = variant % self.tiles.len();
+        Tile{
+            sprite_index: self.sprite_for(variant, self.slope),
+            terrain: self.char_code,
+            variant,
             x,
             y,
             height: self.height,
             elevation: e,
-            slope: self.slope
+            slope: self.slope,
+            region: None,
         }
     }
 }
@@ -40,6 +144,12 @@ impl TerrainTile {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Tile {
     pub sprite_index: usize,
+    // the char code of the TerrainTile this tile was created from
+    #[serde(default)]
+    pub terrain: char,
+    // the index of the sprite in the TerrainTile's sprite list
+    #[serde(default)]
+    pub variant: usize,
     pub x: usize,
     pub y: usize,
     #[serde(default)]
@@ -48,17 +158,31 @@ pub struct Tile {
     pub elevation: usize,
     #[serde(default = "slope_none")]
     pub slope: Slope,
+    #[serde(default)]
+    pub region: Option<Region>,
 }
 
 impl Tile {
+    // the elevation of the middle of the tile's top face
+    // a sloped tile rises half a level to the middle, and a full level to its upper edge
+    pub fn surface_elevation(&self) -> f32 {
+        match self.slope {
+            Slope::None => self.elevation as f32,
+            _ => self.elevation as f32 + 0.5,
+        }
+    }
+
     pub fn new() -> Tile {
         Tile{
             sprite_index: 0,
+            terrain: ' ',
+            variant: 0,
             x: 0,
             y: 0,
             height: 0,
             elevation: 0,
             slope: Slope::None,
+            region: None,
         }
     }
 }
@@ -71,6 +195,14 @@ fn slope_none() -> Slope {
     Slope::None
 }
 
+fn default_true() -> bool {
+    true
+}
+
+fn default_one() -> f32 {
+    1.
+}
+
 // a ui element for a tile
 pub struct TileUIElement {
     pub tile_x: usize,
@@ -78,8 +210,11 @@ pub struct TileUIElement {
     pub el_type: TileUIElementType,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TileUIElementType {
     EditorMouseOver,
+    // shows which team's spawn area or base a tile is part of
+    Region(Region),
 }
 
 impl Component for TileUIElement {
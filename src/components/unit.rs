@@ -0,0 +1,52 @@
+use amethyst::ecs::prelude::{Component, DenseVecStorage, NullStorage};
+use std::collections::VecDeque;
+
+// a unit on the map, owned by a team
+pub struct Unit {
+    pub team: usize,
+    // tiles per second, before the terrain's speed modifier
+    pub speed: f32,
+    pub health: f32,
+    pub max_health: f32,
+    // the tile the unit is on
+    pub x: usize,
+    pub y: usize,
+    // the tiles still to move through, and how far the unit is towards the first one
+    pub path: VecDeque<(usize, usize)>,
+    pub progress: f32,
+}
+
+impl Unit {
+    pub fn new(team: usize, speed: f32, health: f32, x: usize, y: usize) -> Unit {
+        Unit {
+            team,
+            speed,
+            health,
+            max_health: health,
+            x,
+            y,
+            path: VecDeque::new(),
+            progress: 0.,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.health > 0.
+    }
+}
+
+impl Component for Unit {
+    type Storage = DenseVecStorage<Self>;
+}
+
+// marks the units the player has selected
+#[derive(Default)]
+pub struct Selected;
+
+impl Component for Selected {
+    type Storage = NullStorage<Self>;
+}
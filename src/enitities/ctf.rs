@@ -0,0 +1,70 @@
+use amethyst::{
+    core::Transform,
+    ecs::{Entities, Entity, Join, LazyUpdate, ReadExpect, ReadStorage},
+    renderer::{palette::Srgba, resources::Tint, SpriteRender},
+};
+use std::collections::HashSet;
+
+use crate::components::{Flag, MapObject, ObjectKind};
+use crate::util::{team_colour, tile_ui_translation};
+
+// the ui sprite flags are drawn with, in their team's colour
+const FLAG_SPRITE: usize = 2;
+
+// creates a team's flag, sitting on its flag stand
+pub fn create_flag(
+    entities: &Entities,
+    sprite: SpriteRender,
+    team: usize,
+    tile_x: usize,
+    tile_y: usize,
+    tile_elevation: f32,
+    lazy_update: &ReadExpect<LazyUpdate>,
+) -> Entity {
+    let flag_entity = entities.create();
+    let (world_x, world_y, world_z) = tile_ui_translation(tile_x, tile_y, tile_elevation);
+    let mut transform = Transform::default();
+    transform.set_translation_xyz(world_x, world_y, world_z);
+
+    let [r, g, b, a] = team_colour(team);
+    lazy_update.insert(flag_entity, Tint(Srgba::new(r, g, b, a)));
+    lazy_update.insert(flag_entity, Flag::new(team, (tile_x, tile_y)));
+    lazy_update.insert(flag_entity, transform);
+    lazy_update.insert(flag_entity, sprite);
+    flag_entity
+}
+
+// picks the sprite flags are drawn with
+pub fn flag_sprite(ui_sprites: &[SpriteRender]) -> SpriteRender {
+    ui_sprites.get(FLAG_SPRITE).unwrap_or(&ui_sprites[0]).clone()
+}
+
+// keeps a single flag for each team with a flag stand, after stands were placed or removed in the editor
+// takes the objects now on the map, with the surface elevation of their tiles
+// a flag whose stand is gone is removed, and put back on another of its team's stands if there is one
+pub fn update_flags(
+    entities: &Entities,
+    flags: &ReadStorage<Flag>,
+    objects: &[(&MapObject, f32)],
+    ui_sprites: &[SpriteRender],
+    lazy_update: &ReadExpect<LazyUpdate>,
+) {
+    let mut flagged = HashSet::new();
+    for (entity, flag) in (&**entities, flags).join() {
+        let home = objects.iter().any(|(object, _)| object.kind == ObjectKind::FlagStand
+            && object.team == Some(flag.team)
+            && (object.x, object.y) == flag.home);
+        if home {
+            flagged.insert(flag.team);
+        } else {
+            entities.delete(entity).unwrap();
+        }
+    }
+    for (object, elevation) in objects.iter() {
+        if let (ObjectKind::FlagStand, Some(team)) = (object.kind, object.team) {
+            if flagged.insert(team) {
+                create_flag(entities, flag_sprite(ui_sprites), team, object.x, object.y, *elevation, lazy_update);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -1,3 +1,9 @@
+mod ctf;
+mod object;
 mod tile;
+mod unit;
 
-pub use self::tile::create_tile_ui;
\ No newline at end of file
+pub use self::ctf::{create_flag, flag_sprite, update_flags};
+pub use self::object::{create_object, object_sprite};
+pub use self::tile::{create_region_ui, create_tile_ui};
+pub use self::unit::{create_unit, unit_sprite};
\ No newline at end of file
@@ -0,0 +1,48 @@
+use amethyst::{
+    core::Transform,
+    ecs::{Entities, Entity, LazyUpdate, ReadExpect},
+    renderer::{palette::Srgba, resources::Tint, SpriteRender},
+};
+
+use crate::components::{Base, MapObject, ObjectKind};
+use crate::util::{object_translation, team_colour};
+
+// creates an object placed on the map, in its team's colour
+// flag stands are also bases for the capture the flag rules
+pub fn create_object(
+    entities: &Entities,
+    object: MapObject,
+    sprite: SpriteRender,
+    tile_elevation: f32,
+    lazy_update: &ReadExpect<LazyUpdate>,
+) -> Entity {
+    let object_entity = entities.create();
+    let (world_x, world_y, world_z) = object_translation(&object, tile_elevation);
+    let mut transform = Transform::default();
+    transform.set_translation_xyz(world_x, world_y, world_z);
+
+    if let Some(team) = object.team {
+        if object.kind == ObjectKind::FlagStand {
+            lazy_update.insert(object_entity, Base { team, x: object.x, y: object.y });
+        }
+        let [r, g, b, a] = team_colour(team);
+        lazy_update.insert(object_entity, Tint(Srgba::new(r, g, b, a)));
+    }
+    lazy_update.insert(object_entity, object);
+    lazy_update.insert(object_entity, transform);
+    lazy_update.insert(object_entity, sprite);
+    object_entity
+}
+
+// the ui sprite walls are drawn with
+const WALL_SPRITE: usize = 1;
+
+// picks the sprite an object is drawn with, or the first ui sprite if it's missing
+pub fn object_sprite(kind: ObjectKind, ui_sprites: &[SpriteRender], terrain_sprites: &[SpriteRender]) -> SpriteRender {
+    let sprite = match kind {
+        ObjectKind::Decoration(i) => terrain_sprites.get(i),
+        ObjectKind::Wall => ui_sprites.get(WALL_SPRITE),
+        _ => None,
+    };
+    sprite.unwrap_or(&ui_sprites[0]).clone()
+}
\ No newline at end of file
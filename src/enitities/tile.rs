@@ -1,11 +1,11 @@
 use amethyst::{
     core::Transform,
     ecs::{Entities, Entity, LazyUpdate, ReadExpect},
-    renderer::SpriteRender,
+    renderer::{palette::Srgba, resources::Tint, SpriteRender},
 };
 
-use crate::components::{TileUIElementType, TileUIElement};
-use crate::util::{map_to_world_iso, z_value_iso, TileLayer, TILE_SIZE};
+use crate::components::{Region, TileUIElementType, TileUIElement};
+use crate::util::{team_colour, tile_ui_translation};
 
 // creates a ui element for a tile
 pub fn create_tile_ui(
@@ -14,14 +14,14 @@ pub fn create_tile_ui(
     tile_height: usize,
     tile_x: usize,
     tile_y: usize,
-    tile_elevation: usize,
+    tile_elevation: f32,
     el_type: TileUIElementType,
     lazy_update: &ReadExpect<LazyUpdate>,
-) {
+) -> Entity {
     let ui_entity = entities.create();
-    let (world_x, world_y) = map_to_world_iso(tile_x as f32, tile_y as f32, tile_elevation as f32, TILE_SIZE * 0.25);
+    let (world_x, world_y, world_z) = tile_ui_translation(tile_x, tile_y, tile_elevation);
     let mut transform = Transform::default();
-    transform.set_translation_xyz(world_x, -world_y, z_value_iso(tile_x as f32, tile_y as f32, 0., TileLayer::BaseUI));
+    transform.set_translation_xyz(world_x, world_y, world_z);
 
     let ui_element = TileUIElement {
         tile_x,
@@ -32,4 +32,26 @@ pub fn create_tile_ui(
     lazy_update.insert(ui_entity, ui_element);
     lazy_update.insert(ui_entity, transform);
     lazy_update.insert(ui_entity, sprite);
+    ui_entity
+}
+
+// creates the overlay that shows which team's spawn area or base a tile is in
+// spawn areas are drawn fainter than bases
+pub fn create_region_ui(
+    entities: &Entities,
+    sprite: SpriteRender,
+    tile_x: usize,
+    tile_y: usize,
+    tile_elevation: f32,
+    region: Region,
+    lazy_update: &ReadExpect<LazyUpdate>,
+) -> Entity {
+    let ui_entity = create_tile_ui(entities, sprite, 0, tile_x, tile_y, tile_elevation, TileUIElementType::Region(region), lazy_update);
+    let [r, g, b, a] = team_colour(region.team());
+    let alpha = match region {
+        Region::Spawn(_) => a * 0.5,
+        Region::Base(_) => a,
+    };
+    lazy_update.insert(ui_entity, Tint(Srgba::new(r, g, b, alpha)));
+    ui_entity
 }
\ No newline at end of file
@@ -0,0 +1,37 @@
+use amethyst::{
+    core::Transform,
+    ecs::{Entities, Entity, LazyUpdate, ReadExpect},
+    renderer::{palette::Srgba, resources::Tint, SpriteRender},
+};
+
+use crate::components::Unit;
+use crate::util::{team_colour, unit_translation};
+
+// the ui sprite units are drawn with, in their team's colour
+const UNIT_SPRITE: usize = 3;
+
+// creates a unit standing on its tile
+pub fn create_unit(
+    entities: &Entities,
+    sprite: SpriteRender,
+    unit: Unit,
+    tile_elevation: f32,
+    lazy_update: &ReadExpect<LazyUpdate>,
+) -> Entity {
+    let unit_entity = entities.create();
+    let (world_x, world_y, world_z) = unit_translation(unit.x as f32, unit.y as f32, tile_elevation);
+    let mut transform = Transform::default();
+    transform.set_translation_xyz(world_x, world_y, world_z);
+
+    let [r, g, b, a] = team_colour(unit.team);
+    lazy_update.insert(unit_entity, Tint(Srgba::new(r, g, b, a)));
+    lazy_update.insert(unit_entity, unit);
+    lazy_update.insert(unit_entity, transform);
+    lazy_update.insert(unit_entity, sprite);
+    unit_entity
+}
+
+// picks the sprite units are drawn with
+pub fn unit_sprite(ui_sprites: &[SpriteRender]) -> SpriteRender {
+    ui_sprites.get(UNIT_SPRITE).unwrap_or(&ui_sprites[0]).clone()
+}
\ No newline at end of file
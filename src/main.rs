@@ -10,20 +10,34 @@ use amethyst::{
     ui::{RenderUi, UiBundle},
     utils::application_root_dir,
 };
-use crate::systems::{CameraSystem,EditorTileSystem,WindowResizeSystem};
+use log::error;
+use crate::systems::{CameraSystem,CaptureFlagSystem,EditorTileSystem,UnitMovementSystem,UnitSelectSystem,WindowResizeSystem};
 
 mod components;
 mod enitities;
 mod resources;
 mod states;
 mod systems;
+mod tools;
 mod util;
 
 fn main() -> amethyst::Result<()> {
     amethyst::start_logger(Default::default());
 
+    // cap_flag <tool> <args> runs a tool instead of the game
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(tool) = args.first() {
+        return tools::run_tool(tool, &args[1..]);
+    }
+
     let app_root = application_root_dir()?;
 
+    // the editor systems can't run without a map, so there's nothing to fall back to
+    let (map, terrain) = states::load_editor_map().map_err(|e| {
+        error!("could not load map: {}", e);
+        amethyst::Error::new(e)
+    })?;
+
     let resources = app_root.join("resources");
     let config = app_root.join("config");
     let display_config = config.clone().join("display.ron");
@@ -33,7 +47,7 @@ fn main() -> amethyst::Result<()> {
         .with_bundle(
             InputBundle::<StringBindings>::new().with_bindings_from_file(&input_config)?,
         )?
-//        .with_bundle(UiBundle::<StringBindings>::new())?
+        .with_bundle(UiBundle::<StringBindings>::new())?
         .with_bundle(TransformBundle::new())?
         .with_bundle(
             RenderingBundle::<DefaultBackend>::new()
@@ -41,14 +55,17 @@ fn main() -> amethyst::Result<()> {
                     RenderToWindow::from_config_path(display_config)
                         .with_clear([0., 0., 0., 1.0]),
                 )
-                .with_plugin(RenderFlat2D::default()),
-//                .with_plugin(RenderUi::default()),
+                .with_plugin(RenderFlat2D::default())
+                .with_plugin(RenderUi::default()),
         )?
         .with(CameraSystem, "camera", &[])
         .with(WindowResizeSystem::new(), "window_resize", &[])
-        .with(EditorTileSystem, "editor_tiles", &[]);
+        .with(EditorTileSystem::default(), "editor_tiles", &[])
+        .with(UnitSelectSystem::default(), "unit_select", &["editor_tiles"])
+        .with(UnitMovementSystem, "unit_movement", &["unit_select"])
+        .with(CaptureFlagSystem, "capture_flag", &["unit_movement"]);
 
-    let mut game = Application::new(resources, states::MapEditorState, game_data)?;
+    let mut game = Application::new(resources, states::MapEditorState::new(map, terrain), game_data)?;
     game.run();
 
     Ok(())
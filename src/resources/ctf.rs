@@ -0,0 +1,67 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+// how a capture the flag match is won, from config/match.ron
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchRules {
+    // the first team to reach this many captures wins
+    #[serde(default)]
+    pub score_limit: Option<usize>,
+    // after this many seconds the team with the most captures wins
+    #[serde(default)]
+    pub time_limit: Option<f32>,
+    // how many units each team starts with, standing on its spawn area
+    #[serde(default = "default_units_per_team")]
+    pub units_per_team: usize,
+}
+
+fn default_units_per_team() -> usize {
+    3
+}
+
+impl Default for MatchRules {
+    fn default() -> MatchRules {
+        MatchRules {
+            score_limit: Some(3),
+            time_limit: None,
+            units_per_team: default_units_per_team(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchResult {
+    Win(usize),
+    Draw,
+}
+
+// the score of the match being played
+#[derive(Default, Debug, Clone)]
+pub struct MatchState {
+    // captures by team
+    pub scores: BTreeMap<usize, usize>,
+    // seconds since the match started
+    pub elapsed: f32,
+    // set once the match is over
+    pub result: Option<MatchResult>,
+}
+
+impl MatchState {
+    pub fn score(&self, team: usize) -> usize {
+        self.scores.get(&team).cloned().unwrap_or(0)
+    }
+
+    pub fn add_capture(&mut self, team: usize) {
+        *self.scores.entry(team).or_insert(0) += 1;
+    }
+
+    // the team with the most captures, or a draw if the lead is shared
+    pub fn leader(&self) -> MatchResult {
+        let best = self.scores.values().cloned().max().unwrap_or(0);
+        let mut leaders = self.scores.iter().filter(|(_, score)| **score == best);
+        match (leaders.next(), leaders.next()) {
+            (Some((team, _)), None) if best > 0 => MatchResult::Win(*team),
+            _ => MatchResult::Draw,
+        }
+    }
+}
\ No newline at end of file
@@ -1,22 +1,260 @@
-use amethyst::{
-    ecs::Entity,
-    renderer::SpriteRender,
-};
-use crate::components::TerrainTile;
+use amethyst::ecs::Entity;
+use serde::{Serialize, Deserialize};
+use crate::components::{ObjectKind, ObjectSlot, Region, TerrainTile};
+use crate::resources::MapDimensions;
+use crate::util::tile_exists;
 
+// what clicking on the map does in the editor
 pub enum EditorMode {
     None,
-    Terrain(TerrainTile)
+    Terrain(TerrainTile),
+    Raise,
+    Lower,
+    // slopes tiles up towards a neighbour one level higher
+    Slope,
+    // replaces the connected tiles of the same terrain (and elevation, if match_elevation is set)
+    Fill { terrain: TerrainTile, match_elevation: bool },
+    // replaces the tiles in a box dragged out with the mouse
+    RectFill(TerrainTile),
+    // marks tiles as part of a team's spawn area or base, or clears them
+    Region(Option<Region>),
+    // places an object in a slot of the clicked tile, or removes it if it's already there
+    Object { kind: ObjectKind, slot: ObjectSlot, team: usize },
+    // picks out a box dragged out with the mouse
+    Select,
+    // places the stamp in the clipboard with its top corner on the clicked tile
+    Paste,
 }
 
+impl EditorMode {
+    // the terrain the current tool paints with
+    pub fn terrain(&self) -> Option<&TerrainTile> {
+        match self {
+            EditorMode::Terrain(terrain)
+            | EditorMode::Fill { terrain, .. }
+            | EditorMode::RectFill(terrain) => Some(terrain),
+            _ => None,
+        }
+    }
+
+    // the same tool, painting with a different terrain
+    pub fn with_terrain(&self, terrain: TerrainTile) -> EditorMode {
+        match self {
+            EditorMode::Fill { match_elevation, .. } => EditorMode::Fill { terrain, match_elevation: *match_elevation },
+            EditorMode::RectFill(_) => EditorMode::RectFill(terrain),
+            _ => EditorMode::Terrain(terrain),
+        }
+    }
+}
+
+impl Default for EditorMode {
+    fn default() -> EditorMode {
+        EditorMode::None
+    }
+}
+
+// a box of tiles, from its top corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl TileRect {
+    // the box with a and b at opposite corners
+    pub fn from_corners(a: (usize, usize), b: (usize, usize)) -> TileRect {
+        let (x, y) = (a.0.min(b.0), a.1.min(b.1));
+        TileRect { x, y, width: a.0.max(b.0) - x + 1, height: a.1.max(b.1) - y + 1 }
+    }
+
+    pub fn tiles(&self) -> Vec<(usize, usize)> {
+        let (x, y, w, h) = (self.x, self.y, self.width, self.height);
+        (y..y + h)
+            .flat_map(|ty| (x..x + w).map(move |tx| (tx, ty)))
+            .collect()
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+// the tiles picked out with the select tool
+#[derive(Default)]
+pub struct Selection {
+    pub rect: Option<TileRect>,
+}
+
+// the shape of the area the editor tools affect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    Square,
+    // a diamond on the map grid, which looks like a circle in the isometric view
+    Diamond,
+    // a line along the map's x axis
+    Line,
+}
+
+pub struct Brush {
+    pub shape: BrushShape,
+    pub size: usize,
+}
+
+impl Brush {
+    // the tiles the brush covers when centered on (x,y), clipped to the map
+    pub fn footprint(&self, x: usize, y: usize, dimensions: &MapDimensions) -> Vec<(usize, usize)> {
+        let (w, h) = (dimensions.width as isize, dimensions.height as isize);
+        let size = self.size.max(1) as isize;
+        // squares and lines are size tiles across, diamonds have a radius of size - 1
+        let (low, high) = match self.shape {
+            BrushShape::Diamond => (1 - size, size - 1),
+            _ => (-(size - 1) / 2, size / 2),
+        };
+        let mut tiles = vec![];
+        for dy in low..=high {
+            for dx in low..=high {
+                let inside = match self.shape {
+                    BrushShape::Square => true,
+                    BrushShape::Diamond => dx.abs() + dy.abs() < size,
+                    BrushShape::Line => dy == 0,
+                };
+                let (tx, ty) = (x as isize + dx, y as isize + dy);
+                if inside && tile_exists(tx, ty, w, h) {
+                    tiles.push((tx as usize, ty as usize));
+                }
+            }
+        }
+        tiles
+    }
+
+    // switches to the next brush shape
+    pub fn cycle_shape(&mut self) {
+        self.shape = match self.shape {
+            BrushShape::Square => BrushShape::Diamond,
+            BrushShape::Diamond => BrushShape::Line,
+            BrushShape::Line => BrushShape::Square,
+        };
+    }
+}
+
+impl Default for Brush {
+    fn default() -> Brush {
+        Brush {
+            shape: BrushShape::Square,
+            size: 1,
+        }
+    }
+}
+
+// editor settings, from config/editor.ron
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EditorConfig {
+    #[serde(default)]
+    pub min_elevation: usize,
+    #[serde(default = "default_max_elevation")]
+    pub max_elevation: usize,
+    // how many tile edits the undo history keeps
+    #[serde(default = "default_history_budget")]
+    pub history_budget: usize,
+    #[serde(default = "default_max_brush_size")]
+    pub max_brush_size: usize,
+}
+
+impl Default for EditorConfig {
+    fn default() -> EditorConfig {
+        EditorConfig {
+            min_elevation: 0,
+            max_elevation: default_max_elevation(),
+            history_budget: default_history_budget(),
+            max_brush_size: default_max_brush_size(),
+        }
+    }
+}
+
+fn default_max_elevation() -> usize {
+    15
+}
+
+fn default_history_budget() -> usize {
+    100_000
+}
+
+fn default_max_brush_size() -> usize {
+    9
+}
+
+// the most teams a map can have
+pub const MAX_TEAMS: usize = 4;
+
 pub const EDITOR_WIDTH: f32 = 160.;
 //pub const MODE_SELECT_HEIGHT: f32 = 256.;
 
+// the ui entities of the editor side panel
+// outside of the editor the panel is empty and has no width
+#[derive(Default)]
 pub struct EditorPanel {
+    pub width: f32,
     pub terrain_panel: TerrainPanel,
 }
 
+impl EditorPanel {
+    // checks if the mouse is over the panel
+    pub fn contains(&self, mouse_x: f32) -> bool {
+        mouse_x < self.width
+    }
+}
+
+#[derive(Default)]
 pub struct TerrainPanel {
     pub background: Vec<Entity>,
+    // marks the selected terrain
     pub border: Vec<Entity>,
-}
\ No newline at end of file
+    // the clickable sprites and names, and the char code of the terrain they select
+    pub buttons: Vec<(Entity, char)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn footprint(shape: BrushShape, size: usize, x: usize, y: usize) -> Vec<(usize, usize)> {
+        Brush { shape, size }.footprint(x, y, &MapDimensions { width: 32, height: 32 })
+    }
+
+    #[test]
+    fn brushes_cover_their_shape() {
+        for size in 1..=EditorConfig::default().max_brush_size {
+            let (low, high) = (16 - (size - 1) / 2, 16 + size / 2);
+
+            let square = footprint(BrushShape::Square, size, 16, 16);
+            assert_eq!(square.len(), size * size);
+            assert!(square.iter().all(|(x, y)| (low..=high).contains(x) && (low..=high).contains(y)));
+
+            let diamond = footprint(BrushShape::Diamond, size, 16, 16);
+            assert_eq!(diamond.len(), 2 * size * size - 2 * size + 1);
+            assert!(diamond.iter().all(|(x, y)| (*x as isize - 16).abs() + (*y as isize - 16).abs() < size as isize));
+
+            let line = footprint(BrushShape::Line, size, 16, 16);
+            assert_eq!(line.len(), size);
+            assert!(line.iter().all(|(x, y)| (low..=high).contains(x) && *y == 16));
+
+            for tiles in [square, diamond, line].iter() {
+                assert!(tiles.contains(&(16, 16)));
+                assert_eq!(tiles.iter().collect::<HashSet<_>>().len(), tiles.len());
+            }
+        }
+    }
+
+    #[test]
+    fn brushes_are_clipped_to_the_map() {
+        assert_eq!(footprint(BrushShape::Square, 3, 0, 0), vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert_eq!(footprint(BrushShape::Diamond, 2, 31, 31), vec![(31, 30), (30, 31), (31, 31)]);
+        assert_eq!(footprint(BrushShape::Line, 4, 31, 5), vec![(30, 5), (31, 5)]);
+        // a size of 0 is treated as 1
+        assert_eq!(footprint(BrushShape::Square, 0, 4, 4), vec![(4, 4)]);
+    }
+}
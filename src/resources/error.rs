@@ -0,0 +1,143 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use crate::resources::{MAP_VERSION, MAX_TEAMS};
+
+// errors that can occur while loading a terrain pack
+#[derive(Debug)]
+pub enum TerrainPackError {
+    // the file couldn't be read
+    Io { path: PathBuf, source: io::Error },
+    // the file isn't valid RON
+    Syntax { path: PathBuf, line: usize, col: usize, msg: String },
+    // a terrain or transition uses a sprite the sprite sheet doesn't have
+    BadSprite { owner: String, sprite: usize, count: usize },
+    // a terrain has no sprites to draw it with
+    NoSprites(String),
+}
+
+impl fmt::Display for TerrainPackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TerrainPackError::Io { path, source } =>
+                write!(f, "could not read terrain pack {}: {}", path.display(), source),
+            TerrainPackError::Syntax { path, line, col, msg } =>
+                write!(f, "syntax error in terrain pack {} at {}:{}: {}", path.display(), line, col, msg),
+            TerrainPackError::BadSprite { owner, sprite, count } =>
+                write!(f, "{} uses sprite {}, but the terrain pack only has {} sprites", owner, sprite, count),
+            TerrainPackError::NoSprites(name) =>
+                write!(f, "terrain {} has no sprites", name),
+        }
+    }
+}
+
+impl Error for TerrainPackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TerrainPackError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// errors that can occur while loading a map
+#[derive(Debug)]
+pub enum MapLoadError {
+    // the file couldn't be read
+    Io { path: PathBuf, source: io::Error },
+    // the file isn't valid RON
+    Syntax { path: PathBuf, line: usize, col: usize, msg: String },
+    // the map was saved by a newer version of the game
+    UnsupportedVersion(u32),
+    // the map data doesn't match the map dimensions
+    Dimensions { width: usize, height: usize, found: usize },
+    // a tile uses a char code the terrain pack doesn't have
+    UnknownTerrain { code: char, x: usize, y: usize },
+    // a tile's elevation isn't a digit
+    BadElevation { code: char, x: usize, y: usize },
+    // an object is placed off the map
+    ObjectOffMap { index: usize, x: usize, y: usize },
+    // a tile refers to an object that doesn't exist or is on another tile
+    BadObjectReference { index: usize, x: usize, y: usize },
+    // the map isn't made for 2 to 4 teams
+    TeamCount(usize),
+    // an object belongs to a team the map doesn't have
+    ObjectTeam { index: usize, team: usize },
+    // a tile is in the region of a team the map doesn't have
+    RegionTeam { team: usize, x: usize, y: usize },
+    // a team has nowhere to keep its flag
+    NoFlagStand(usize),
+    // a team has nowhere to spawn
+    NoSpawn(usize),
+    // a spawn tile can't be walked on
+    ImpassableSpawn { team: usize, x: usize, y: usize },
+    // resizing would leave the map without any tiles
+    Resize { width: isize, height: isize },
+    // a stamp was made with a different terrain pack from the map it's placed on
+    StampTerrain { stamp: String, map: String },
+    // the map's terrain pack couldn't be loaded
+    Terrain(TerrainPackError),
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapLoadError::Io { path, source } =>
+                write!(f, "could not read map {}: {}", path.display(), source),
+            MapLoadError::Syntax { path, line, col, msg } =>
+                write!(f, "syntax error in map {} at {}:{}: {}", path.display(), line, col, msg),
+            MapLoadError::UnsupportedVersion(version) =>
+                write!(f, "map version {} is newer than the supported version {}", version, MAP_VERSION),
+            MapLoadError::Dimensions { width, height, found } =>
+                write!(f, "map is {}x{} and needs {} tiles, but the data has {}", width, height, width * height, found),
+            MapLoadError::UnknownTerrain { code, x, y } =>
+                write!(f, "unknown terrain '{}' at ({}, {})", code, x, y),
+            MapLoadError::BadElevation { code, x, y } =>
+                write!(f, "bad elevation '{}' at ({}, {})", code, x, y),
+            MapLoadError::ObjectOffMap { index, x, y } =>
+                write!(f, "object {} at ({}, {}) is off the map", index, x, y),
+            MapLoadError::BadObjectReference { index, x, y } =>
+                write!(f, "tile ({}, {}) refers to object {}, which isn't on that tile", x, y, index),
+            MapLoadError::TeamCount(teams) =>
+                write!(f, "map has {} teams, but needs 2 to {}", teams, MAX_TEAMS),
+            MapLoadError::ObjectTeam { index, team } =>
+                write!(f, "object {} belongs to team {}, which the map doesn't have", index, team),
+            MapLoadError::RegionTeam { team, x, y } =>
+                write!(f, "tile ({}, {}) is in a region of team {}, which the map doesn't have", x, y, team),
+            MapLoadError::NoFlagStand(team) =>
+                write!(f, "team {} has no flag stand", team),
+            MapLoadError::NoSpawn(team) =>
+                write!(f, "team {} has no spawn tiles", team),
+            MapLoadError::ImpassableSpawn { team, x, y } =>
+                write!(f, "spawn tile ({}, {}) of team {} can't be walked on", x, y, team),
+            MapLoadError::Resize { width, height } =>
+                write!(f, "can't resize the map to {}x{}", width, height),
+            MapLoadError::StampTerrain { stamp, map } =>
+                write!(f, "the stamp is made for terrain pack {}, but the map uses {}", stamp, map),
+            MapLoadError::Terrain(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for MapLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MapLoadError::Io { source, .. } => Some(source),
+            MapLoadError::Terrain(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TerrainPackError> for MapLoadError {
+    fn from(e: TerrainPackError) -> MapLoadError {
+        MapLoadError::Terrain(e)
+    }
+}
+
+// pulls the position and message out of a RON error
+pub fn syntax_position(e: &ron::de::Error) -> (usize, usize, String) {
+    match e {
+        ron::de::Error::Parser(code, position) => (position.line, position.col, format!("{:?}", code)),
+        _ => (0, 0, e.to_string()),
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::components::{MapObject, Tile};
+use crate::resources::{EditorConfig, Map};
+
+// a change to a single tile
+#[derive(Debug, Clone)]
+pub struct TileEdit {
+    pub x: usize,
+    pub y: usize,
+    pub before: Tile,
+    pub after: Tile,
+}
+
+// a single undoable step in the editor
+#[derive(Debug, Clone)]
+pub enum EditCommand {
+    // terrain painting, elevation changes and fills
+    Tiles(Vec<TileEdit>),
+    // placing and removing objects
+    Objects { added: Vec<MapObject>, removed: Vec<MapObject> },
+    // resizing, cropping, shifting and pasting, which replace the whole map
+    Reshape { before: Box<Map>, after: Box<Map> },
+}
+
+impl EditCommand {
+    // roughly how much of the history budget the command uses
+    pub fn size(&self) -> usize {
+        match self {
+            EditCommand::Tiles(edits) => edits.len(),
+            EditCommand::Objects { added, removed } => added.len() + removed.len(),
+            EditCommand::Reshape { before, after } => before.records.len() + after.records.len(),
+        }
+    }
+}
+
+// the undo/redo history of the editor
+// only holds the edits, applying them to the tile entities is up to the editor
+pub struct EditorHistory {
+    undo: VecDeque<EditCommand>,
+    redo: Vec<EditCommand>,
+    // the edits of a stroke that hasn't ended yet, and where each tile is in it
+    group: Option<Vec<TileEdit>>,
+    group_index: HashMap<(usize, usize), usize>,
+    // the most tile edits the undo history keeps before dropping the oldest steps
+    budget: usize,
+    size: usize,
+}
+
+impl EditorHistory {
+    pub fn new(budget: usize) -> EditorHistory {
+        EditorHistory {
+            undo: VecDeque::new(),
+            redo: vec![],
+            group: None,
+            group_index: HashMap::new(),
+            budget,
+            size: 0,
+        }
+    }
+
+    // starts collecting edits into a single step, if it isn't already
+    pub fn begin_group(&mut self) {
+        if self.group.is_none() {
+            self.group = Some(vec![]);
+        }
+    }
+
+    // stops collecting edits, and adds them as a single step
+    pub fn end_group(&mut self) {
+        self.group_index.clear();
+        if let Some(edits) = self.group.take() {
+            self.push_tiles(edits);
+        }
+    }
+
+    // adds a tile edit, either to the open group or as its own step
+    pub fn record(&mut self, edit: TileEdit) {
+        match &mut self.group {
+            Some(edits) => {
+                // a tile edited twice in one step only needs its first before and last after
+                match self.group_index.get(&(edit.x, edit.y)) {
+                    Some(i) => edits[*i].after = edit.after,
+                    _ => {
+                        self.group_index.insert((edit.x, edit.y), edits.len());
+                        edits.push(edit);
+                    },
+                }
+            },
+            _ => self.push(EditCommand::Tiles(vec![edit])),
+        }
+    }
+
+    // adds a set of tile edits as a single step
+    pub fn push_tiles(&mut self, edits: Vec<TileEdit>) {
+        if !edits.is_empty() {
+            self.push(EditCommand::Tiles(edits));
+        }
+    }
+
+    // adds a step to the history, which clears anything that could be redone
+    pub fn push(&mut self, command: EditCommand) {
+        self.redo.clear();
+        self.size += command.size();
+        self.undo.push_back(command);
+        self.trim();
+    }
+
+    // drops the oldest steps until the history is within its budget, always keeping the last one
+    fn trim(&mut self) {
+        while self.size > self.budget && self.undo.len() > 1 {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.size -= oldest.size();
+            }
+        }
+    }
+
+    // takes the last step, to be reverted
+    pub fn undo(&mut self) -> Option<EditCommand> {
+        self.end_group();
+        let command = self.undo.pop_back()?;
+        self.size -= command.size();
+        self.redo.push(command.clone());
+        Some(command)
+    }
+
+    // takes the last undone step, to be applied again
+    pub fn redo(&mut self) -> Option<EditCommand> {
+        self.end_group();
+        let command = self.redo.pop()?;
+        self.size += command.size();
+        self.undo.push_back(command.clone());
+        self.trim();
+        Some(command)
+    }
+}
+
+impl Default for EditorHistory {
+    fn default() -> EditorHistory {
+        EditorHistory::new(EditorConfig::default().history_budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an edit that changes a tile's elevation from one level to another
+    fn edit(x: usize, y: usize, from: usize, to: usize) -> TileEdit {
+        let tile = |elevation| Tile { x, y, elevation, ..Tile::new() };
+        TileEdit { x, y, before: tile(from), after: tile(to) }
+    }
+
+    fn tile_edits(command: EditCommand) -> Vec<TileEdit> {
+        match command {
+            EditCommand::Tiles(edits) => edits,
+            other => panic!("expected tile edits, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_group_is_one_step() {
+        let mut history = EditorHistory::new(100);
+        history.begin_group();
+        history.record(edit(0, 0, 0, 1));
+        history.record(edit(1, 0, 0, 1));
+        // beginning again while a group is open doesn't start another one
+        history.begin_group();
+        history.record(edit(2, 0, 0, 1));
+        history.end_group();
+
+        let edits = tile_edits(history.undo().unwrap());
+        assert_eq!(edits.len(), 3);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn a_tile_edited_twice_in_a_group_keeps_its_first_before_and_last_after() {
+        let mut history = EditorHistory::new(100);
+        history.begin_group();
+        history.record(edit(3, 4, 0, 1));
+        history.record(edit(3, 4, 1, 2));
+        history.end_group();
+
+        let edits = tile_edits(history.undo().unwrap());
+        assert_eq!(edits.len(), 1);
+        assert_eq!((edits[0].before.elevation, edits[0].after.elevation), (0, 2));
+    }
+
+    #[test]
+    fn edits_outside_a_group_are_steps_of_their_own() {
+        let mut history = EditorHistory::new(100);
+        history.record(edit(0, 0, 0, 1));
+        history.record(edit(0, 0, 1, 2));
+        // an empty group adds nothing
+        history.begin_group();
+        history.end_group();
+
+        assert_eq!(tile_edits(history.undo().unwrap())[0].after.elevation, 2);
+        assert_eq!(tile_edits(history.undo().unwrap())[0].after.elevation, 1);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn undoing_ends_an_open_group() {
+        let mut history = EditorHistory::new(100);
+        history.begin_group();
+        history.record(edit(0, 0, 0, 1));
+        assert_eq!(tile_edits(history.undo().unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn the_oldest_steps_are_dropped_over_budget() {
+        let mut history = EditorHistory::new(4);
+        history.push_tiles(vec![edit(0, 0, 0, 1), edit(1, 0, 0, 1)]);
+        history.push_tiles(vec![edit(2, 0, 0, 1)]);
+        history.push_tiles(vec![edit(3, 0, 0, 1), edit(4, 0, 0, 1)]);
+
+        assert_eq!(tile_edits(history.undo().unwrap())[0].x, 3);
+        assert_eq!(tile_edits(history.undo().unwrap())[0].x, 2);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn a_step_bigger_than_the_budget_is_still_kept() {
+        let mut history = EditorHistory::new(1);
+        history.push_tiles(vec![edit(0, 0, 0, 1)]);
+        history.push_tiles(vec![edit(1, 0, 0, 1), edit(2, 0, 0, 1), edit(3, 0, 0, 1)]);
+
+        assert_eq!(tile_edits(history.undo().unwrap()).len(), 3);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn undo_and_redo_take_turns() {
+        let mut history = EditorHistory::new(100);
+        history.push_tiles(vec![edit(0, 0, 0, 1)]);
+        history.push_tiles(vec![edit(1, 0, 0, 1)]);
+
+        assert_eq!(tile_edits(history.undo().unwrap())[0].x, 1);
+        assert_eq!(tile_edits(history.undo().unwrap())[0].x, 0);
+        assert_eq!(tile_edits(history.redo().unwrap())[0].x, 0);
+        assert_eq!(tile_edits(history.redo().unwrap())[0].x, 1);
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn redoing_keeps_to_the_budget() {
+        let mut history = EditorHistory::new(4);
+        history.push_tiles(vec![edit(0, 0, 0, 1), edit(1, 0, 0, 1)]);
+        history.push_tiles(vec![edit(2, 0, 0, 1), edit(3, 0, 0, 1)]);
+        history.undo().unwrap();
+        // a smaller budget only takes effect as steps come back
+        history.budget = 3;
+        assert_eq!(tile_edits(history.redo().unwrap())[0].x, 2);
+        assert_eq!(history.size, 2);
+        assert_eq!(tile_edits(history.undo().unwrap())[0].x, 2);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn pushing_clears_redo() {
+        let mut history = EditorHistory::new(100);
+        history.push_tiles(vec![edit(0, 0, 0, 1)]);
+        history.undo().unwrap();
+        history.push(EditCommand::Objects { added: vec![], removed: vec![] });
+
+        assert!(history.redo().is_none());
+        match history.undo() {
+            Some(EditCommand::Objects { .. }) => {},
+            other => panic!("expected the object step, got {:?}", other),
+        }
+        assert!(history.undo().is_none());
+    }
+}
@@ -1,8 +1,10 @@
-use amethyst::ecs::Entity;
+use amethyst::ecs::{Entity, Join, ReadStorage};
 use serde::{Serialize, Deserialize};
 
-use crate::components::Tile;
-use crate::resources::TerrainSet;
+use crate::components::{MapObject, ObjectKind, Region, Slope, Tile};
+use crate::resources::{MapLoadError, TerrainSet, MAX_TEAMS, NEIGHBOUR_OFFSETS};
+use crate::tools::GeneratorSettings;
+use crate::util::{infer_slopes, tile_exists};
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct MapDimensions {
@@ -22,42 +24,645 @@ impl TileMap {
     }
 }
 
+// the current version of the .cfmap format
+pub const MAP_VERSION: u32 = 2;
+
 // a map from a file (not used during play)
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Map {
+    // maps without a version are legacy (v1) maps
+    #[serde(default = "legacy_version")]
+    pub version: u32,
     pub dimensions: MapDimensions,
     pub terrain_file: String,
+    // used to pick sprites for tiles that don't have a variant
+    #[serde(default)]
+    pub seed: u64,
+    // turns steps of one elevation level into ramps, when loading and while editing
+    #[serde(default)]
+    pub auto_slopes: bool,
+    // how many teams the map is made for, or 0 if it isn't set up for matches yet
+    #[serde(default)]
+    pub teams: usize,
+    // the settings the map was generated with, which make the same map again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generator: Option<GeneratorSettings>,
+    // v1 tile data, a terrain char code and an elevation digit per tile
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub data: String,
+    // v2 tile data, one record per tile, row by row
     #[serde(default)]
+    pub records: Vec<TileRecord>,
+    // spawns, flag stands, walls and decorations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<MapObject>,
+    #[serde(default, skip_serializing)]
     pub tiles: Vec<Vec<Tile>>,
+    // the file the map was loaded from, used when saving
+    #[serde(skip)]
+    pub filename: String,
+}
+
+// a single tile as stored in a v2 map
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TileRecord {
+    pub terrain: char,
+    #[serde(default)]
+    pub elevation: usize,
+    // replaces the slope of the terrain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slope: Option<Slope>,
+    // index into the terrain's list of sprites
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<usize>,
+    // the team spawn area or base the tile is part of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    // indices into the map's objects, for the objects placed on the tile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<usize>,
+}
+
+impl TileRecord {
+    pub fn new(terrain: char, elevation: usize) -> TileRecord {
+        TileRecord {
+            terrain,
+            elevation,
+            slope: None,
+            variant: None,
+            region: None,
+            objects: vec![],
+        }
+    }
 }
 
 impl Map {
+    // upgrades an older map to the current version
+    pub fn migrate(&mut self) -> Result<(), MapLoadError> {
+        if self.version > MAP_VERSION {
+            return Err(MapLoadError::UnsupportedVersion(self.version));
+        }
+        if self.version < 2 {
+            self.records = legacy_records(&self.data, self.dimensions.width, self.dimensions.height)?;
+            self.data = "".to_owned();
+            self.version = 2;
+        }
+        Ok(())
+    }
+
     // converts the map into a set of proper tiles
-    pub fn build_tiles(&mut self, terrain: &TerrainSet) {
-        assert_ne!(self.dimensions.width, 0);
-        assert_ne!(self.dimensions.height, 0);
-        let chars: Vec<char> = self.data.chars().collect();
-        assert_eq!(self.dimensions.width * self.dimensions.height, chars.len() / 2);
-        self.tiles = vec![vec![Tile::new(); self.dimensions.width]; self.dimensions.height];
-        for i in 0..(chars.len() / 2) {
-            let t = chars[i*2];
-            let ec = chars[i*2+1];
-            let e = ec.to_digit(10).unwrap() as usize;
-            let y = (i)/self.dimensions.width;
-            let x = (i)%self.dimensions.width;
-            self.tiles[y][x] = terrain.create_tile(t, x, y, e).unwrap();
+    pub fn build_tiles(&mut self, terrain: &TerrainSet) -> Result<(), MapLoadError> {
+        let (width, height) = (self.dimensions.width, self.dimensions.height);
+        if width == 0 || height == 0 || width * height != self.records.len() {
+            return Err(MapLoadError::Dimensions { width, height, found: self.records.len() });
+        }
+        self.tiles = vec![vec![Tile::new(); width]; height];
+        for (i, record) in self.records.iter().enumerate() {
+            let y = (i)/width;
+            let x = (i)%width;
+            let terrain_tile = match terrain.terrain(record.terrain) {
+                Some(terrain_tile) => terrain_tile,
+                _ => return Err(MapLoadError::UnknownTerrain { code: record.terrain, x, y }),
+            };
+            let mut tile = match record.variant {
+                Some(variant) => terrain_tile.create_tile_variant(x, y, record.elevation, variant),
+                _ => terrain_tile.create_tile(x, y, record.elevation, self.seed),
+            };
+            if let Some(slope) = record.slope {
+                tile.slope = slope;
+                tile.sprite_index = terrain_tile.sprite_for(tile.variant, slope);
+            }
+            tile.region = record.region;
+            for index in record.objects.iter() {
+                match self.objects.get(*index) {
+                    Some(object) if (object.x, object.y) == (x, y) => {},
+                    _ => return Err(MapLoadError::BadObjectReference { index: *index, x, y }),
+                }
+            }
+            self.tiles[y][x] = tile;
+        }
+        if let Some((index, object)) = self.objects.iter().enumerate().find(|(_, o)| o.x >= width || o.y >= height) {
+            return Err(MapLoadError::ObjectOffMap { index, x: object.x, y: object.y });
+        }
+        if self.auto_slopes {
+            self.infer_slopes(terrain);
+        }
+        self.apply_transitions(terrain);
+        Ok(())
+    }
+
+    // checks the map is ready for a match: every team has a flag stand and somewhere passable to spawn
+    pub fn validate_teams(&self, terrain: &TerrainSet) -> Result<(), MapLoadError> {
+        if self.teams < 2 || self.teams > MAX_TEAMS {
+            return Err(MapLoadError::TeamCount(self.teams));
+        }
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(team) = object.team.filter(|team| *team >= self.teams) {
+                return Err(MapLoadError::ObjectTeam { index, team });
+            }
+        }
+        let mut spawns = vec![0; self.teams];
+        for tile in self.tiles.iter().flat_map(|row| row.iter()) {
+            let region = match tile.region {
+                Some(region) => region,
+                _ => continue,
+            };
+            let team = region.team();
+            if team >= self.teams {
+                return Err(MapLoadError::RegionTeam { team, x: tile.x, y: tile.y });
+            }
+            if let Region::Spawn(_) = region {
+                let walled = self.objects.iter()
+                    .any(|o| o.kind.blocks_movement() && (o.x, o.y) == (tile.x, tile.y));
+                if walled || !terrain.is_passable(tile) {
+                    return Err(MapLoadError::ImpassableSpawn { team, x: tile.x, y: tile.y });
+                }
+                spawns[team] += 1;
+            }
+        }
+        for team in 0..self.teams {
+            if !self.objects.iter().any(|o| o.kind == ObjectKind::FlagStand && o.team == Some(team)) {
+                return Err(MapLoadError::NoFlagStand(team));
+            }
+            if spawns[team] == 0 {
+                return Err(MapLoadError::NoSpawn(team));
+            }
+        }
+        Ok(())
+    }
+
+    // picks the edge sprites for tiles that border other terrain
+    pub fn apply_transitions(&mut self, terrain: &TerrainSet) {
+        if terrain.transitions.is_empty() {
+            return;
+        }
+        let (width, height) = (self.dimensions.width as isize, self.dimensions.height as isize);
+        let sprites: Vec<usize> = self.tiles.iter()
+            .flat_map(|row| row.iter())
+            .map(|tile| {
+                let mut neighbours = [None; 8];
+                for (i, (dx, dy)) in NEIGHBOUR_OFFSETS.iter().enumerate() {
+                    let (nx, ny) = (tile.x as isize + dx, tile.y as isize + dy);
+                    if tile_exists(nx, ny, width, height) {
+                        neighbours[i] = Some(self.tiles[ny as usize][nx as usize].terrain);
+                    }
+                }
+                terrain.tile_sprite(tile, &neighbours)
+            })
+            .collect();
+        for (tile, sprite) in self.tiles.iter_mut().flat_map(|row| row.iter_mut()).zip(sprites.into_iter()) {
+            tile.sprite_index = sprite;
+        }
+    }
+
+    // turns every tile one elevation level below a neighbour into a ramp, if its terrain allows it
+    // and the record doesn't already give it a slope
+    pub fn infer_slopes(&mut self, terrain: &TerrainSet) {
+        let (width, height) = (self.dimensions.width, self.dimensions.height);
+        let elevations: Vec<usize> = self.tiles.iter().flat_map(|row| row.iter().map(|t| t.elevation)).collect();
+        let can_ramp: Vec<bool> = self.tiles.iter()
+            .flat_map(|row| row.iter().map(|t| terrain.terrain(t.terrain).map_or(false, |tt| tt.ramps)))
+            .collect();
+        let slopes = infer_slopes(&elevations, &can_ramp, width, height);
+        for (i, slope) in slopes.into_iter().enumerate() {
+            if !can_ramp[i] || self.records.get(i).map_or(false, |r| r.slope.is_some()) {
+                continue;
+            }
+            let tile = &mut self.tiles[i / width][i % width];
+            if let Some(terrain_tile) = terrain.terrain(tile.terrain) {
+                tile.slope = slope;
+                tile.sprite_index = terrain_tile.sprite_for(tile.variant, slope);
+            }
+        }
+    }
+
+    // pulls the current state of every tile from the tile entities
+    pub fn read_tiles(&mut self, tile_map: &TileMap, tiles: &ReadStorage<Tile>) {
+        let (width, height) = (self.dimensions.width, self.dimensions.height);
+        self.tiles = (0..height)
+            .map(|y| (0..width)
+                .map(|x| match tiles.get(tile_map.get(x, y, width)) {
+                    Some(tile) => tile.clone(),
+                    _ => Tile::new(),
+                })
+                .collect())
+            .collect();
+    }
+
+    // pulls the placed objects from the object entities, in a stable order
+    pub fn read_objects(&mut self, objects: &ReadStorage<MapObject>) {
+        self.objects = objects.join().cloned().collect();
+        self.objects.sort_by_key(|o| (o.y, o.x, o.slot.index()));
+    }
+
+    // grows or shrinks the map by a number of tiles on each side, named by the direction it faces
+    // new tiles are copies of fill, and objects on removed tiles are removed with them
+    // works on the records, so the tiles have to be built again after
+    pub fn resize(&mut self, nw: isize, ne: isize, se: isize, sw: isize, fill: &TileRecord) -> Result<(), MapLoadError> {
+        let (width, height) = (self.dimensions.width as isize, self.dimensions.height as isize);
+        let (new_width, new_height) = (width + nw + se, height + ne + sw);
+        if new_width < 1 || new_height < 1 {
+            return Err(MapLoadError::Resize { width: new_width, height: new_height });
+        }
+        let mut records = Vec::with_capacity((new_width * new_height) as usize);
+        for y in 0..new_height {
+            for x in 0..new_width {
+                let (old_x, old_y) = (x - nw, y - ne);
+                let mut record = if tile_exists(old_x, old_y, width, height) {
+                    self.records[(old_y * width + old_x) as usize].clone()
+                } else {
+                    fill.clone()
+                };
+                record.objects.clear();
+                records.push(record);
+            }
         }
+        let mut objects = vec![];
+        for object in self.objects.iter() {
+            let (x, y) = (object.x as isize + nw, object.y as isize + ne);
+            if tile_exists(x, y, new_width, new_height) {
+                records[(y * new_width + x) as usize].objects.push(objects.len());
+                objects.push(MapObject { x: x as usize, y: y as usize, ..object.clone() });
+            }
+        }
+        self.records = records;
+        self.objects = objects;
+        self.dimensions = MapDimensions { width: new_width as usize, height: new_height as usize };
+        Ok(())
+    }
+
+    // cuts the map down to the box with its top corner at (x,y), or the part of it that's on the map
+    pub fn crop(&mut self, x: usize, y: usize, width: usize, height: usize) -> Result<(), MapLoadError> {
+        let (old_width, old_height) = (self.dimensions.width, self.dimensions.height);
+        let (x, y) = (x.min(old_width), y.min(old_height));
+        let (width, height) = (width.min(old_width - x), height.min(old_height - y));
+        let (nw, ne) = (-(x as isize), -(y as isize));
+        let (se, sw) = ((x + width) as isize - old_width as isize, (y + height) as isize - old_height as isize);
+        // the box is on the map, so no tiles are added and the fill isn't used
+        self.resize(nw, ne, se, sw, &TileRecord::new(' ', 0))
+    }
+
+    // moves everything on the map by (dx,dy), filling the tiles left behind
+    pub fn shift(&mut self, dx: isize, dy: isize, fill: &TileRecord) -> Result<(), MapLoadError> {
+        self.resize(dx, dy, -dx, -dy, fill)
+    }
+
+    // converts the set of tiles back into tile records
+    pub fn build_records(&mut self, terrain: &TerrainSet) {
+        let width = self.dimensions.width;
+        let mut references = vec![vec![]; self.tiles.iter().map(|row| row.len()).sum()];
+        for (i, object) in self.objects.iter().enumerate() {
+            if let Some(tile_objects) = references.get_mut(object.y * width + object.x) {
+                tile_objects.push(i);
+            }
+        }
+        self.records = self.tiles.iter()
+            .flat_map(|row| row.iter())
+            .zip(references.into_iter())
+            .map(|(tile, objects)| {
+                let terrain_slope = terrain.terrain(tile.terrain).map(|t| t.slope);
+                TileRecord {
+                    terrain: tile.terrain,
+                    elevation: tile.elevation,
+                    slope: if terrain_slope == Some(tile.slope) { None } else { Some(tile.slope) },
+                    variant: Some(tile.variant),
+                    region: tile.region,
+                    objects,
+                }
+            })
+            .collect();
+        self.data = "".to_owned();
+        self.version = MAP_VERSION;
     }
 }
 
 impl Default for Map {
     fn default() -> Map {
         Map{
+            version: MAP_VERSION,
             dimensions: MapDimensions{ width: 0, height: 0},
             terrain_file: "".to_owned(),
+            seed: 0,
+            auto_slopes: false,
+            teams: 0,
+            generator: None,
             data: "".to_owned(),
-            tiles: vec![vec![]]
+            records: vec![],
+            objects: vec![],
+            tiles: vec![vec![]],
+            filename: "".to_owned(),
+        }
+    }
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+// reads the tile records out of a v1 data string
+// the data is usually a char code and an elevation digit per tile, but some older maps skip the elevation
+fn legacy_records(data: &str, width: usize, height: usize) -> Result<Vec<TileRecord>, MapLoadError> {
+    let chars: Vec<char> = data.chars().collect();
+    let size = width * height;
+    if size == 0 {
+        return Err(MapLoadError::Dimensions { width, height, found: chars.len() });
+    }
+    if chars.len() == size {
+        return Ok(chars.into_iter().map(|t| TileRecord::new(t, 0)).collect());
+    }
+    if chars.len() != size * 2 {
+        return Err(MapLoadError::Dimensions { width, height, found: chars.len() / 2 });
+    }
+    let mut records = Vec::with_capacity(size);
+    for i in 0..size {
+        let t = chars[i*2];
+        let ec = chars[i*2+1];
+        let e = match ec.to_digit(10) {
+            Some(e) => e as usize,
+            _ => return Err(MapLoadError::BadElevation { code: ec, x: i % width, y: i / width }),
+        };
+        records.push(TileRecord::new(t, e));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::utils::application_root_dir;
+    use ron::{de::from_str, ser::{to_string_pretty, PrettyConfig}};
+    use std::fs;
+
+    use super::*;
+    use crate::components::ObjectSlot;
+    use crate::resources::{load_map, load_terrain_pack};
+
+    // a shipped map as it is in its file, before migrating
+    fn shipped_map(name: &str) -> Map {
+        let path = application_root_dir().unwrap().join(format!("maps/{}.cfmap", name));
+        from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn shipped_maps_migrate_to_v2() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        for name in ["test_map", "test_map_large"].iter() {
+            let v1 = shipped_map(name);
+            assert_eq!(v1.version, 1, "{}", name);
+            let (width, height) = (v1.dimensions.width, v1.dimensions.height);
+            let chars: Vec<char> = v1.data.chars().collect();
+            // a char code and an elevation digit per tile, or only a char code
+            let per_tile = chars.len() / (width * height);
+            assert!(per_tile == 1 || per_tile == 2, "{}", name);
+
+            let mut map = v1.clone();
+            map.migrate().unwrap();
+            assert_eq!(map.version, MAP_VERSION);
+            assert!(map.data.is_empty());
+            assert_eq!(map.records.len(), width * height);
+            for (i, record) in map.records.iter().enumerate() {
+                let elevation = match per_tile {
+                    2 => chars[i * 2 + 1].to_digit(10).unwrap() as usize,
+                    _ => 0,
+                };
+                assert_eq!((record.terrain, record.elevation), (chars[i * per_tile], elevation), "{} tile {}", name, i);
+                assert_eq!(record.slope, None);
+                assert_eq!(record.variant, None);
+                assert!(record.objects.is_empty());
+            }
+
+            map.build_tiles(&terrain).unwrap();
+            for (i, record) in map.records.iter().enumerate() {
+                let tile = &map.tiles[i / width][i % width];
+                assert_eq!((tile.x, tile.y), (i % width, i / width));
+                assert_eq!((tile.terrain, tile.elevation), (record.terrain, record.elevation));
+            }
+        }
+    }
+
+    #[test]
+    fn loading_migrates_v1_maps() {
+        let loaded = load_map("test_map".to_owned()).unwrap();
+        assert_eq!(loaded.version, MAP_VERSION);
+        assert_eq!(loaded.records[0], TileRecord::new('g', 2));
+        assert_eq!(loaded.records[1], TileRecord::new('g', 1));
+        assert_eq!(loaded.records[2], TileRecord::new('d', 1));
+        assert_eq!(loaded.filename, "test_map.cfmap");
+    }
+
+    #[test]
+    fn bad_v1_data_is_reported() {
+        match legacy_records("g0g0g0", 2, 2) {
+            Err(MapLoadError::Dimensions { width: 2, height: 2, .. }) => {},
+            other => panic!("expected a dimensions error, got {:?}", other),
+        }
+        match legacy_records("g0gxg0g0", 2, 2) {
+            Err(MapLoadError::BadElevation { code: 'x', x: 1, y: 0 }) => {},
+            other => panic!("expected a bad elevation, got {:?}", other),
+        }
+        let mut newer = Map { version: MAP_VERSION + 1, ..Map::default() };
+        assert!(newer.migrate().is_err());
+    }
+
+    #[test]
+    fn saved_map_loads_back_the_same() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        let mut map = load_map("test_map".to_owned()).unwrap();
+        map.build_tiles(&terrain).unwrap();
+        map.tiles[1][2].slope = Slope::SE;
+        map.tiles[6][0].slope = Slope::NW;
+        map.tiles[3][3].region = Some(Region::Spawn(0));
+        map.tiles[4][5].region = Some(Region::Base(1));
+        map.objects = vec![
+            MapObject { kind: ObjectKind::FlagStand, x: 5, y: 4, slot: ObjectSlot::C, team: Some(1) },
+            MapObject { kind: ObjectKind::Wall, x: 0, y: 7, slot: ObjectSlot::UL, team: None },
+            MapObject { kind: ObjectKind::Decoration(2), x: 0, y: 7, slot: ObjectSlot::DR, team: None },
+        ];
+        map.build_records(&terrain);
+
+        let saved = to_string_pretty(&map, PrettyConfig::default()).unwrap();
+        let mut loaded: Map = from_str(&saved).unwrap();
+        loaded.migrate().unwrap();
+        loaded.build_tiles(&terrain).unwrap();
+
+        assert_eq!(loaded.version, MAP_VERSION);
+        assert_eq!((loaded.dimensions.width, loaded.dimensions.height), (8, 8));
+        assert_eq!(loaded.terrain_file, map.terrain_file);
+        assert_eq!(loaded.records, map.records);
+        assert_eq!(loaded.objects, map.objects);
+        assert_eq!(loaded.records[7 * 8].objects, vec![1, 2]);
+        let summary = |m: &Map| m.tiles.iter()
+            .flat_map(|row| row.iter())
+            .map(|t| (t.terrain, t.elevation, t.variant, t.slope, t.region, t.sprite_index))
+            .collect::<Vec<_>>();
+        assert_eq!(summary(&loaded), summary(&map));
+        assert_eq!(loaded.tiles[1][2].slope, Slope::SE);
+        assert_eq!(loaded.tiles[4][5].region, Some(Region::Base(1)));
+    }
+
+    #[test]
+    fn explicit_slopes_survive_inference() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        let mut inferred = load_map("test_map".to_owned()).unwrap();
+        inferred.auto_slopes = true;
+        inferred.build_tiles(&terrain).unwrap();
+        let width = inferred.dimensions.width;
+        let ramps: Vec<(usize, Slope)> = inferred.tiles.iter()
+            .flat_map(|row| row.iter())
+            .filter(|t| t.slope != Slope::None)
+            .map(|t| (t.y * width + t.x, t.slope))
+            .collect();
+        assert!(ramps.len() >= 2);
+
+        // turn one inferred ramp around by hand and flatten another
+        let (turned, slope) = ramps[0];
+        let turned_to = if slope == Slope::NW { Slope::SE } else { Slope::NW };
+        let (flattened, _) = ramps[1];
+        let mut map = load_map("test_map".to_owned()).unwrap();
+        map.auto_slopes = true;
+        map.records[turned].slope = Some(turned_to);
+        map.records[flattened].slope = Some(Slope::None);
+
+        let saved = to_string_pretty(&map, PrettyConfig::default()).unwrap();
+        let mut loaded: Map = from_str(&saved).unwrap();
+        loaded.migrate().unwrap();
+        loaded.build_tiles(&terrain).unwrap();
+
+        assert_eq!(loaded.tiles[turned / width][turned % width].slope, turned_to);
+        assert_eq!(loaded.tiles[flattened / width][flattened % width].slope, Slope::None);
+        for (i, slope) in ramps.iter().skip(2) {
+            assert_eq!(loaded.tiles[i / width][i % width].slope, *slope, "tile {}", i);
+        }
+    }
+
+    // a 3x3 map where each tile's elevation is its index, with a region in the middle and objects on the diagonal
+    fn reshapable_map() -> Map {
+        let (width, height) = (3, 3);
+        let mut records: Vec<TileRecord> = (0..width * height)
+            .map(|i| TileRecord::new(if i % 2 == 0 { 'g' } else { 'd' }, i))
+            .collect();
+        records[4].region = Some(Region::Spawn(0));
+        let objects = vec![
+            MapObject { kind: ObjectKind::Wall, x: 0, y: 0, slot: ObjectSlot::C, team: None },
+            MapObject { kind: ObjectKind::Decoration(2), x: 1, y: 1, slot: ObjectSlot::UL, team: None },
+            MapObject { kind: ObjectKind::FlagStand, x: 2, y: 2, slot: ObjectSlot::C, team: Some(0) },
+        ];
+        for (i, object) in objects.iter().enumerate() {
+            records[object.y * width + object.x].objects.push(i);
+        }
+        Map {
+            dimensions: MapDimensions { width, height },
+            terrain_file: "256color".to_owned(),
+            records,
+            objects,
+            ..Map::default()
+        }
+    }
+
+    fn elevations(map: &Map) -> Vec<usize> {
+        map.records.iter().map(|record| record.elevation).collect()
+    }
+
+    // where the objects are, after checking each is referenced by the record of its tile and nothing else
+    fn object_tiles(map: &Map) -> Vec<(ObjectKind, usize, usize)> {
+        let width = map.dimensions.width;
+        for (i, record) in map.records.iter().enumerate() {
+            let expected: Vec<usize> = map.objects.iter()
+                .enumerate()
+                .filter(|(_, o)| o.y * width + o.x == i)
+                .map(|(index, _)| index)
+                .collect();
+            assert_eq!(record.objects, expected, "tile {}", i);
+        }
+        map.objects.iter().map(|o| (o.kind, o.x, o.y)).collect()
+    }
+
+    #[test]
+    fn growing_adds_fill_and_moves_everything_over() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        let mut map = reshapable_map();
+        map.resize(1, 2, 0, 1, &TileRecord::new('d', 9)).unwrap();
+        assert_eq!((map.dimensions.width, map.dimensions.height), (4, 6));
+        assert_eq!(elevations(&map), vec![
+            9, 9, 9, 9,
+            9, 9, 9, 9,
+            9, 0, 1, 2,
+            9, 3, 4, 5,
+            9, 6, 7, 8,
+            9, 9, 9, 9,
+        ]);
+        assert_eq!(map.records[0].terrain, 'd');
+        assert_eq!(map.records[3 * 4 + 2].region, Some(Region::Spawn(0)));
+        assert_eq!(object_tiles(&map), vec![
+            (ObjectKind::Wall, 1, 2),
+            (ObjectKind::Decoration(2), 2, 3),
+            (ObjectKind::FlagStand, 3, 4),
+        ]);
+        map.build_tiles(&terrain).unwrap();
+    }
+
+    #[test]
+    fn shrinking_drops_what_is_cut_off() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        let mut map = reshapable_map();
+        map.resize(-1, 0, 0, -1, &TileRecord::new('d', 9)).unwrap();
+        assert_eq!((map.dimensions.width, map.dimensions.height), (2, 2));
+        assert_eq!(elevations(&map), vec![1, 2, 4, 5]);
+        assert_eq!(map.records[2].region, Some(Region::Spawn(0)));
+        assert_eq!(object_tiles(&map), vec![(ObjectKind::Decoration(2), 0, 1)]);
+        map.build_tiles(&terrain).unwrap();
+
+        // a map can't be shrunk away
+        let mut map = reshapable_map();
+        match map.resize(-2, 0, -1, 0, &TileRecord::new('d', 9)) {
+            Err(MapLoadError::Resize { width: 0, height: 3 }) => {},
+            other => panic!("expected a resize error, got {:?}", other),
         }
+        assert_eq!((map.dimensions.width, map.dimensions.height), (3, 3));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn shifting_moves_the_map_either_way() {
+        let fill = TileRecord::new('d', 9);
+        let mut map = reshapable_map();
+        map.shift(-1, -1, &fill).unwrap();
+        assert_eq!((map.dimensions.width, map.dimensions.height), (3, 3));
+        assert_eq!(elevations(&map), vec![4, 5, 9, 7, 8, 9, 9, 9, 9]);
+        assert_eq!(map.records[0].region, Some(Region::Spawn(0)));
+        assert_eq!(object_tiles(&map), vec![
+            (ObjectKind::Decoration(2), 0, 0),
+            (ObjectKind::FlagStand, 1, 1),
+        ]);
+
+        let mut map = reshapable_map();
+        map.shift(1, 0, &fill).unwrap();
+        assert_eq!(elevations(&map), vec![9, 0, 1, 9, 3, 4, 9, 6, 7]);
+        assert_eq!(object_tiles(&map), vec![
+            (ObjectKind::Wall, 1, 0),
+            (ObjectKind::Decoration(2), 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn cropping_keeps_to_the_map() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        let mut map = reshapable_map();
+        map.crop(1, 1, 2, 1).unwrap();
+        assert_eq!(elevations(&map), vec![4, 5]);
+        assert_eq!(object_tiles(&map), vec![(ObjectKind::Decoration(2), 0, 0)]);
+
+        // a box running off the map is cut down to the part on it, without adding tiles
+        let mut map = reshapable_map();
+        map.crop(1, 1, 5, 5).unwrap();
+        assert_eq!((map.dimensions.width, map.dimensions.height), (2, 2));
+        assert_eq!(elevations(&map), vec![4, 5, 7, 8]);
+        assert_eq!(object_tiles(&map), vec![
+            (ObjectKind::Decoration(2), 0, 0),
+            (ObjectKind::FlagStand, 1, 1),
+        ]);
+        map.build_tiles(&terrain).unwrap();
+
+        // and one entirely off it leaves nothing
+        let mut map = reshapable_map();
+        assert!(map.crop(3, 0, 1, 1).is_err());
+    }
+}
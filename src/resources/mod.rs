@@ -1,25 +1,78 @@
+mod ctf;
 mod editor;
+mod error;
+mod history;
 mod map;
+mod stamp;
 mod terrain;
 mod ui;
 
-use ron::de::from_str;
-use std::fs;
+use ron::{
+    de::from_str,
+    ser::{to_string_pretty, PrettyConfig},
+};
+use log::warn;
+use serde::de::DeserializeOwned;
+use std::{fs, path::PathBuf};
 
-pub use self::map::{Map, MapDimensions, TileMap};
-pub use self::terrain::{TerrainSet, TerrainSprites};
-pub use self::ui::{CameraHandle, UISprites};
+pub use self::ctf::{MatchResult, MatchRules, MatchState};
+pub use self::editor::{Brush, BrushShape, EditorConfig, EditorMode, EditorPanel, Selection, TerrainPanel, TileRect, EDITOR_WIDTH, MAX_TEAMS};
+pub use self::error::{MapLoadError, TerrainPackError};
+pub use self::history::{EditCommand, EditorHistory, TileEdit};
+pub use self::map::{Map, MapDimensions, TileMap, TileRecord, MAP_VERSION};
+pub use self::stamp::{Clipboard, Stamp};
+pub use self::terrain::{TerrainSet, TerrainSprites, TransitionRule, NEIGHBOUR_OFFSETS};
+pub use self::ui::{CameraHandle, SheetDefinition, SpriteDefinition, UISprites};
 
-pub fn load_test_map() -> amethyst::Result<Map> {
+pub fn load_test_map() -> Result<Map, MapLoadError> {
     load_map("test_map".to_owned())
 }
 
-//pub fn load_random_map_text() -> Map {
-//
-//}
+// loads a settings file under the application root, falling back to the defaults if it can't be read
+pub fn load_config<T: DeserializeOwned + Default>(path: &str) -> T {
+    use amethyst::utils::application_root_dir;
+
+    let config = application_root_dir()
+        .map_err(|e| e.to_string())
+        .and_then(|app_root| fs::read_to_string(app_root.join(path)).map_err(|e| e.to_string()))
+        .and_then(|contents| from_str(&*contents).map_err(|e| e.to_string()));
+    match config {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("could not load {}, using defaults: {}", path, e);
+            T::default()
+        },
+    }
+}
+
+// loads the editor settings, falling back to the defaults if they can't be read
+pub fn load_editor_config() -> EditorConfig {
+    load_config("config/editor.ron")
+}
+
+// counts the sprites in a sprite sheet under resources, so each of them can be drawn
+// falls back to one sprite if the sheet can't be read
+pub fn count_sheet_sprites(file: &str) -> usize {
+    let sheet: SheetDefinition = load_config(&format!("resources/{}", file));
+    sheet.sprites.len().max(1)
+}
+
+// loads the match rules, falling back to the defaults if they can't be read
+pub fn load_match_rules() -> MatchRules {
+    load_config("config/match.ron")
+}
+
+// loads a map for a match, along with its terrain pack, and checks it's ready to be played
+pub fn load_match_map(filename: String) -> Result<(Map, TerrainSet), MapLoadError> {
+    let mut map = load_map(filename)?;
+    let terrain = load_terrain_pack(map.terrain_file.clone())?;
+    map.build_tiles(&terrain)?;
+    map.validate_teams(&terrain)?;
+    Ok((map, terrain))
+}
 
 // loads a map from a string filename
-pub fn load_map(filename: String) -> amethyst::Result<Map> {
+pub fn load_map(filename: String) -> Result<Map, MapLoadError> {
     use amethyst::utils::application_root_dir;
 
     // add extension
@@ -29,16 +82,116 @@ pub fn load_map(filename: String) -> amethyst::Result<Map> {
         filename
     };
     // load file
-    let app_root = application_root_dir()?;
+    let app_root = application_root_dir()
+        .map_err(|source| MapLoadError::Io { path: PathBuf::from("maps"), source })?;
 
     let map_path = app_root.join(format!("maps/{}", f));
-    let contents = fs::read_to_string(map_path).unwrap();
-    let map: Map = from_str(&*contents).unwrap();
+    let contents = fs::read_to_string(&map_path)
+        .map_err(|source| MapLoadError::Io { path: map_path.clone(), source })?;
+    let mut map: Map = from_str(&*contents)
+        .map_err(|e| {
+            let (line, col, msg) = error::syntax_position(&e);
+            MapLoadError::Syntax { path: map_path.clone(), line, col, msg }
+        })?;
+    map.filename = f;
+    map.migrate()?;
     Ok(map)
 }
 
+// saves a map to a string filename
+// the map is written to a temporary file first so a failed save can't clobber the old file
+pub fn save_map(map: &Map, filename: String) -> amethyst::Result<()> {
+    use amethyst::utils::application_root_dir;
+
+    // add extension
+    let f = if !filename.contains(".cfmap") {
+        format!("{}{}", filename, ".cfmap")
+    } else {
+        filename
+    };
+    // save file
+    let app_root = application_root_dir()?;
+
+    let map_path = app_root.join(format!("maps/{}", f));
+    let temp_path = app_root.join(format!("maps/{}.tmp", f));
+    let contents = to_string_pretty(map, PrettyConfig::default())?;
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, &map_path)?;
+    Ok(())
+}
+
+// loads a stamp from a string filename in the stamps folder
+pub fn load_stamp(filename: String) -> Result<Stamp, MapLoadError> {
+    use amethyst::utils::application_root_dir;
+
+    // add extension
+    let f = if !filename.contains(".cfstamp") {
+        format!("{}{}", filename, ".cfstamp")
+    } else {
+        filename
+    };
+    // load file
+    let app_root = application_root_dir()
+        .map_err(|source| MapLoadError::Io { path: PathBuf::from("stamps"), source })?;
+
+    let stamp_path = app_root.join(format!("stamps/{}", f));
+    let contents = fs::read_to_string(&stamp_path)
+        .map_err(|source| MapLoadError::Io { path: stamp_path.clone(), source })?;
+    let mut stamp: Stamp = from_str(&*contents)
+        .map_err(|e| {
+            let (line, col, msg) = error::syntax_position(&e);
+            MapLoadError::Syntax { path: stamp_path.clone(), line, col, msg }
+        })?;
+    if stamp.records.len() != stamp.width * stamp.height {
+        return Err(MapLoadError::Dimensions { width: stamp.width, height: stamp.height, found: stamp.records.len() });
+    }
+    stamp.filename = f;
+    Ok(stamp)
+}
+
+// saves a stamp to a string filename in the stamps folder, making the folder if it isn't there yet
+pub fn save_stamp(stamp: &Stamp, filename: String) -> amethyst::Result<()> {
+    use amethyst::utils::application_root_dir;
+
+    // add extension
+    let f = if !filename.contains(".cfstamp") {
+        format!("{}{}", filename, ".cfstamp")
+    } else {
+        filename
+    };
+    // save file
+    let app_root = application_root_dir()?;
+
+    fs::create_dir_all(app_root.join("stamps"))?;
+    let stamp_path = app_root.join(format!("stamps/{}", f));
+    let temp_path = app_root.join(format!("stamps/{}.tmp", f));
+    let contents = to_string_pretty(stamp, PrettyConfig::default())?;
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, &stamp_path)?;
+    Ok(())
+}
+
+// the filenames of all stamps in the stamps folder, sorted by name
+pub fn stamp_files() -> Vec<String> {
+    use amethyst::utils::application_root_dir;
+
+    let entries = application_root_dir()
+        .map_err(|e| e.to_string())
+        .and_then(|app_root| fs::read_dir(app_root.join("stamps")).map_err(|e| e.to_string()));
+    let mut files: Vec<String> = match entries {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.ends_with(".cfstamp"))
+            .collect(),
+        Err(_) => vec![],
+    };
+    files.sort();
+    files
+}
+
 // loads a terrain pack from a string filename
-pub fn load_terrain_pack(filename: String) -> amethyst::Result<TerrainSet> {
+pub fn load_terrain_pack(filename: String) -> Result<TerrainSet, TerrainPackError> {
     use amethyst::utils::application_root_dir;
 
     // add extension
@@ -48,10 +201,17 @@ pub fn load_terrain_pack(filename: String) -> amethyst::Result<TerrainSet> {
         filename
     };
     // load file
-    let app_root = application_root_dir()?;
+    let app_root = application_root_dir()
+        .map_err(|source| TerrainPackError::Io { path: PathBuf::from("resources/terrain"), source })?;
 
-    let map_path = app_root.join(format!("resources/terrain/{}", f));
-    let contents = fs::read_to_string(map_path).unwrap();
-    let terrain: TerrainSet = from_str(&*contents).unwrap();
+    let terrain_path = app_root.join(format!("resources/terrain/{}", f));
+    let contents = fs::read_to_string(&terrain_path)
+        .map_err(|source| TerrainPackError::Io { path: terrain_path.clone(), source })?;
+    let terrain: TerrainSet = from_str(&*contents)
+        .map_err(|e| {
+            let (line, col, msg) = error::syntax_position(&e);
+            TerrainPackError::Syntax { path: terrain_path.clone(), line, col, msg }
+        })?;
+    terrain.validate()?;
     Ok(terrain)
 }
\ No newline at end of file
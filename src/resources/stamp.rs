@@ -0,0 +1,358 @@
+use serde::{Serialize, Deserialize};
+
+use crate::components::{MapObject, ObjectSlot, Slope};
+use crate::resources::{Map, MapLoadError, TileRecord, TileRect};
+
+// a piece of a map that can be placed on any map with the same terrain pack
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Stamp {
+    pub terrain_file: String,
+    pub width: usize,
+    pub height: usize,
+    // one record per tile, row by row, without object references
+    pub records: Vec<TileRecord>,
+    // placed relative to the stamp's top corner
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<MapObject>,
+    // the file the stamp was loaded from or saved to
+    #[serde(skip)]
+    pub filename: String,
+}
+
+impl Stamp {
+    // copies the tiles and objects in rect, from a map with up to date records
+    pub fn copy(map: &Map, rect: TileRect) -> Stamp {
+        let records = rect.tiles()
+            .into_iter()
+            .map(|(x, y)| {
+                let mut record = map.records[y * map.dimensions.width + x].clone();
+                record.objects.clear();
+                record
+            })
+            .collect();
+        let objects = map.objects.iter()
+            .filter(|o| rect.contains(o.x, o.y))
+            .map(|o| MapObject { x: o.x - rect.x, y: o.y - rect.y, ..o.clone() })
+            .collect();
+        Stamp {
+            terrain_file: map.terrain_file.clone(),
+            width: rect.width,
+            height: rect.height,
+            records,
+            objects,
+            filename: "".to_owned(),
+        }
+    }
+
+    // a stamp of copies of one tile, for clearing an area
+    pub fn filled(terrain_file: &str, width: usize, height: usize, fill: &TileRecord) -> Stamp {
+        Stamp {
+            terrain_file: terrain_file.to_owned(),
+            width,
+            height,
+            records: vec![fill.clone(); width * height],
+            objects: vec![],
+            filename: "".to_owned(),
+        }
+    }
+
+    // turns the stamp a quarter turn clockwise on screen, so its north west side faces north east
+    pub fn rotate(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let mut records = vec![TileRecord::new(' ', 0); width * height];
+        for (i, record) in self.records.iter().enumerate() {
+            let (x, y) = (i % width, i / width);
+            let mut record = record.clone();
+            record.slope = record.slope.map(|slope| match slope {
+                Slope::NW => Slope::NE,
+                Slope::NE => Slope::SE,
+                Slope::SE => Slope::SW,
+                Slope::SW => Slope::NW,
+                Slope::None => Slope::None,
+            });
+            records[x * height + (height - 1 - y)] = record;
+        }
+        for object in self.objects.iter_mut() {
+            let (x, y) = (height - 1 - object.y, object.x);
+            object.x = x;
+            object.y = y;
+            object.slot = rotated_slot(object.slot);
+        }
+        self.records = records;
+        self.width = height;
+        self.height = width;
+    }
+
+    // flips the stamp, swapping its north west and south east sides
+    pub fn mirror(&mut self) {
+        let width = self.width;
+        for row in self.records.chunks_mut(width) {
+            row.reverse();
+        }
+        for record in self.records.iter_mut() {
+            record.slope = record.slope.map(|slope| match slope {
+                Slope::NW => Slope::SE,
+                Slope::SE => Slope::NW,
+                other => other,
+            });
+        }
+        for object in self.objects.iter_mut() {
+            object.x = width - 1 - object.x;
+            object.slot = mirrored_slot(object.slot);
+        }
+    }
+
+    // places the stamp with its top corner at (x,y), replacing the tiles and objects under it
+    // the parts that hang off the map are left out, and the tiles have to be built again after
+    pub fn paste(&self, map: &mut Map, x: usize, y: usize) -> Result<(), MapLoadError> {
+        if self.terrain_file != map.terrain_file {
+            return Err(MapLoadError::StampTerrain { stamp: self.terrain_file.clone(), map: map.terrain_file.clone() });
+        }
+        let (width, height) = (map.dimensions.width, map.dimensions.height);
+        let covered = TileRect { x, y, width: self.width, height: self.height };
+        map.objects.retain(|o| !covered.contains(o.x, o.y));
+        for (i, record) in self.records.iter().enumerate() {
+            let (tx, ty) = (x + i % self.width, y + i / self.width);
+            if tx < width && ty < height {
+                map.records[ty * width + tx] = record.clone();
+            }
+        }
+        map.objects.extend(self.objects.iter()
+            .map(|o| MapObject { x: x + o.x, y: y + o.y, ..o.clone() })
+            .filter(|o| o.x < width && o.y < height));
+
+        // the objects moved around, so the references to them are made again
+        for record in map.records.iter_mut() {
+            record.objects.clear();
+        }
+        for (i, object) in map.objects.iter().enumerate() {
+            map.records[object.y * width + object.x].objects.push(i);
+        }
+        Ok(())
+    }
+
+    // the tiles the stamp covers when placed at (x,y), left out where it hangs off the map
+    pub fn footprint(&self, x: usize, y: usize, map_width: usize, map_height: usize) -> Vec<(usize, usize)> {
+        TileRect { x, y, width: self.width, height: self.height }
+            .tiles()
+            .into_iter()
+            .filter(|(tx, ty)| *tx < map_width && *ty < map_height)
+            .collect()
+    }
+}
+
+// the copied or loaded stamp the paste tool places
+#[derive(Default)]
+pub struct Clipboard {
+    pub stamp: Option<Stamp>,
+    // where the paste tool was clicked, waiting to be pasted by the editor state
+    pub paste_at: Option<(usize, usize)>,
+}
+
+// the slot an object moves to when its tile is turned a quarter turn
+// the middle slots at the top and bottom of a tile have no slots a quarter turn away, so they swap
+fn rotated_slot(slot: ObjectSlot) -> ObjectSlot {
+    match slot {
+        ObjectSlot::UL => ObjectSlot::RU,
+        ObjectSlot::RU => ObjectSlot::DR,
+        ObjectSlot::DR => ObjectSlot::LD,
+        ObjectSlot::LD => ObjectSlot::UL,
+        ObjectSlot::UR => ObjectSlot::RD,
+        ObjectSlot::RD => ObjectSlot::DL,
+        ObjectSlot::DL => ObjectSlot::LU,
+        ObjectSlot::LU => ObjectSlot::UR,
+        ObjectSlot::CLU => ObjectSlot::CRU,
+        ObjectSlot::CRU => ObjectSlot::CRD,
+        ObjectSlot::CRD => ObjectSlot::CLD,
+        ObjectSlot::CLD => ObjectSlot::CLU,
+        ObjectSlot::CU => ObjectSlot::CD,
+        ObjectSlot::CD => ObjectSlot::CU,
+        ObjectSlot::C => ObjectSlot::C,
+    }
+}
+
+// the slot an object moves to when its tile is mirrored, with the same exception as turning
+fn mirrored_slot(slot: ObjectSlot) -> ObjectSlot {
+    match slot {
+        ObjectSlot::UL => ObjectSlot::RD,
+        ObjectSlot::RD => ObjectSlot::UL,
+        ObjectSlot::UR => ObjectSlot::RU,
+        ObjectSlot::RU => ObjectSlot::UR,
+        ObjectSlot::LU => ObjectSlot::DR,
+        ObjectSlot::DR => ObjectSlot::LU,
+        ObjectSlot::LD => ObjectSlot::DL,
+        ObjectSlot::DL => ObjectSlot::LD,
+        ObjectSlot::CLU => ObjectSlot::CRD,
+        ObjectSlot::CRD => ObjectSlot::CLU,
+        ObjectSlot::CU => ObjectSlot::CD,
+        ObjectSlot::CD => ObjectSlot::CU,
+        ObjectSlot::CRU => ObjectSlot::CRU,
+        ObjectSlot::CLD => ObjectSlot::CLD,
+        ObjectSlot::C => ObjectSlot::C,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{ObjectKind, Region, OBJECT_SLOTS};
+    use crate::resources::MapDimensions;
+
+    // a 4x3 map where each tile's elevation is its index, with a few slopes, a region and objects
+    fn map() -> Map {
+        let (width, height) = (4, 3);
+        let mut records: Vec<TileRecord> = (0..width * height)
+            .map(|i| TileRecord::new(if i % 2 == 0 { 'g' } else { 'd' }, i))
+            .collect();
+        records[1].slope = Some(Slope::NW);
+        records[5].slope = Some(Slope::SE);
+        records[6].region = Some(Region::Spawn(1));
+        let objects = vec![
+            MapObject { kind: ObjectKind::Wall, x: 1, y: 0, slot: ObjectSlot::CU, team: None },
+            MapObject { kind: ObjectKind::FlagStand, x: 3, y: 1, slot: ObjectSlot::UL, team: Some(0) },
+            MapObject { kind: ObjectKind::Decoration(2), x: 2, y: 2, slot: ObjectSlot::CRD, team: None },
+        ];
+        for (i, object) in objects.iter().enumerate() {
+            records[object.y * width + object.x].objects.push(i);
+        }
+        Map {
+            dimensions: MapDimensions { width, height },
+            terrain_file: "256color".to_owned(),
+            records,
+            objects,
+            ..Map::default()
+        }
+    }
+
+    // the 3x2 tiles from (1,0), holding the wall and the flag stand
+    fn stamp() -> Stamp {
+        Stamp::copy(&map(), TileRect { x: 1, y: 0, width: 3, height: 2 })
+    }
+
+    fn elevations(stamp: &Stamp) -> Vec<usize> {
+        stamp.records.iter().map(|record| record.elevation).collect()
+    }
+
+    // every object is referenced once, by the record of the tile it's on
+    fn check_object_refs(map: &Map) {
+        let width = map.dimensions.width;
+        for (i, record) in map.records.iter().enumerate() {
+            let expected: Vec<usize> = map.objects.iter()
+                .enumerate()
+                .filter(|(_, o)| o.y * width + o.x == i)
+                .map(|(index, _)| index)
+                .collect();
+            assert_eq!(record.objects, expected, "tile {}", i);
+        }
+    }
+
+    #[test]
+    fn copying_keeps_objects_relative_to_the_stamp() {
+        let stamp = stamp();
+        assert_eq!((stamp.width, stamp.height), (3, 2));
+        assert_eq!(elevations(&stamp), vec![1, 2, 3, 5, 6, 7]);
+        assert!(stamp.records.iter().all(|record| record.objects.is_empty()));
+        let placed: Vec<(usize, usize, ObjectSlot)> = stamp.objects.iter().map(|o| (o.x, o.y, o.slot)).collect();
+        assert_eq!(placed, vec![(0, 0, ObjectSlot::CU), (2, 1, ObjectSlot::UL)]);
+    }
+
+    #[test]
+    fn slots_turn_and_mirror_back() {
+        for slot in OBJECT_SLOTS.iter() {
+            let turned = (0..4).fold(*slot, |slot, _| rotated_slot(slot));
+            assert_eq!(turned, *slot);
+            assert_eq!(mirrored_slot(mirrored_slot(*slot)), *slot);
+        }
+        // turning and mirroring move every slot to a different one
+        let mut rotated: Vec<usize> = OBJECT_SLOTS.iter().map(|slot| rotated_slot(*slot).index()).collect();
+        let mut mirrored: Vec<usize> = OBJECT_SLOTS.iter().map(|slot| mirrored_slot(*slot).index()).collect();
+        rotated.sort_unstable();
+        mirrored.sort_unstable();
+        assert_eq!(rotated, (0..OBJECT_SLOTS.len()).collect::<Vec<usize>>());
+        assert_eq!(mirrored, (0..OBJECT_SLOTS.len()).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn rotating_turns_tiles_slopes_and_objects() {
+        let mut stamp = stamp();
+        stamp.rotate();
+        assert_eq!((stamp.width, stamp.height), (2, 3));
+        assert_eq!(elevations(&stamp), vec![5, 1, 6, 2, 7, 3]);
+        assert_eq!(stamp.records[0].slope, Some(Slope::SW));
+        assert_eq!(stamp.records[1].slope, Some(Slope::NE));
+        let placed: Vec<(usize, usize, ObjectSlot)> = stamp.objects.iter().map(|o| (o.x, o.y, o.slot)).collect();
+        assert_eq!(placed, vec![(1, 0, ObjectSlot::CD), (0, 2, ObjectSlot::RU)]);
+    }
+
+    #[test]
+    fn rotating_four_times_gives_the_original() {
+        let original = stamp();
+        let mut stamp = original.clone();
+        for _ in 0..4 {
+            stamp.rotate();
+        }
+        assert_eq!((stamp.width, stamp.height), (original.width, original.height));
+        assert_eq!(stamp.records, original.records);
+        assert_eq!(stamp.objects, original.objects);
+    }
+
+    #[test]
+    fn mirroring_flips_tiles_slopes_and_objects() {
+        let mut stamp = stamp();
+        stamp.mirror();
+        assert_eq!((stamp.width, stamp.height), (3, 2));
+        assert_eq!(elevations(&stamp), vec![3, 2, 1, 7, 6, 5]);
+        assert_eq!(stamp.records[2].slope, Some(Slope::SE));
+        assert_eq!(stamp.records[5].slope, Some(Slope::NW));
+        let placed: Vec<(usize, usize, ObjectSlot)> = stamp.objects.iter().map(|o| (o.x, o.y, o.slot)).collect();
+        assert_eq!(placed, vec![(2, 0, ObjectSlot::CD), (0, 1, ObjectSlot::RD)]);
+    }
+
+    #[test]
+    fn mirroring_twice_gives_the_original() {
+        let original = stamp();
+        let mut stamp = original.clone();
+        stamp.mirror();
+        stamp.mirror();
+        assert_eq!(stamp.records, original.records);
+        assert_eq!(stamp.objects, original.objects);
+    }
+
+    #[test]
+    fn pasting_at_the_edge_leaves_out_what_hangs_off() {
+        let mut map = map();
+        let stamp = stamp();
+        assert_eq!(stamp.footprint(2, 2, 4, 3), vec![(2, 2), (3, 2)]);
+        stamp.paste(&mut map, 2, 2).unwrap();
+
+        let elevations: Vec<usize> = map.records.iter().map(|record| record.elevation).collect();
+        assert_eq!(elevations, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 1, 2]);
+        assert_eq!(map.records[6].region, Some(Region::Spawn(1)));
+        assert_eq!(map.records[5].slope, Some(Slope::SE));
+        // the decoration under the stamp is replaced by the copied wall, and the flag stand falls off the map
+        let placed: Vec<(ObjectKind, usize, usize)> = map.objects.iter().map(|o| (o.kind, o.x, o.y)).collect();
+        assert_eq!(placed, vec![(ObjectKind::Wall, 1, 0), (ObjectKind::FlagStand, 3, 1), (ObjectKind::Wall, 2, 2)]);
+        check_object_refs(&map);
+    }
+
+    #[test]
+    fn pasting_over_objects_renumbers_them() {
+        let mut map = map();
+        let mut stamp = Stamp::copy(&map, TileRect { x: 2, y: 2, width: 1, height: 1 });
+        stamp.objects[0].slot = ObjectSlot::C;
+        // the wall is first in the map's objects, so the others move down when it's pasted over
+        stamp.paste(&mut map, 1, 0).unwrap();
+        let placed: Vec<(ObjectKind, usize, usize)> = map.objects.iter().map(|o| (o.kind, o.x, o.y)).collect();
+        assert_eq!(placed, vec![(ObjectKind::FlagStand, 3, 1), (ObjectKind::Decoration(2), 2, 2), (ObjectKind::Decoration(2), 1, 0)]);
+        check_object_refs(&map);
+    }
+
+    #[test]
+    fn stamps_only_paste_onto_maps_with_their_terrain() {
+        let mut map = map();
+        let mut stamp = stamp();
+        stamp.terrain_file = "other".to_owned();
+        assert!(stamp.paste(&mut map, 0, 0).is_err());
+        assert_eq!(map.records, self::map().records);
+    }
+}
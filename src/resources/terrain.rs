@@ -1,5 +1,7 @@
 use serde::{Serialize, Deserialize};
-use crate::components::{TerrainTile, Tile};
+use std::collections::HashMap;
+use crate::components::{Slope, TerrainTile, Tile};
+use crate::resources::TerrainPackError;
 use amethyst::{
     Error,
     renderer::SpriteRender,
@@ -15,25 +17,141 @@ pub struct TerrainSet {
     pub tile_size: usize,
     pub num_tiles: usize,
     pub tiles: Vec<TerrainTile>,
+    #[serde(default)]
+    pub transitions: Vec<TransitionRule>,
+}
+
+// where the neighbours of a tile are, along its sides NW, NE, SE, SW, then at its corners N, E, S, W
+pub const NEIGHBOUR_OFFSETS: [(isize, isize); 8] = [
+    (-1, 0), (0, -1), (1, 0), (0, 1),
+    (-1, -1), (1, -1), (1, 1), (-1, 1),
+];
+
+// the sides next to each corner, in the order N, E, S, W
+const CORNER_SIDES: [u8; 4] = [1 | 2, 2 | 4, 4 | 8, 8 | 1];
+
+// picks edge sprites for a terrain where it meets another terrain
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransitionRule {
+    // the terrain the edges are drawn on
+    pub terrain: char,
+    // the neighbouring terrain that causes the edges
+    pub neighbour: char,
+    // sprites by the mask of which neighbours are the other terrain:
+    // 1 = NW, 2 = NE, 4 = SE, 8 = SW along the sides, 16 = N, 32 = E, 64 = S, 128 = W at the corners
+    pub tiles: HashMap<u8, usize>,
+}
+
+impl TransitionRule {
+    // finds the sprite for the tile's neighbours, in the order of NEIGHBOUR_OFFSETS
+    pub fn sprite_for(&self, neighbours: &[Option<char>; 8]) -> Option<usize> {
+        let mask = neighbours.iter()
+            .enumerate()
+            .filter(|(_, n)| **n == Some(self.neighbour))
+            .fold(0u8, |mask, (i, _)| mask | (1 << i));
+        // a side already covers the corners at its ends, so they don't need sprites of their own
+        let mask = (0..4).fold(mask, |mask, i| {
+            if mask & CORNER_SIDES[i] != 0 { mask & !(16 << i) } else { mask }
+        });
+        if mask == 0 {
+            return None;
+        }
+        self.tiles.get(&mask).cloned()
+    }
 }
 
 impl TerrainSet {
+    // borrows a TerrainTile using the char code
+    pub fn terrain(&self, t: char) -> Option<&TerrainTile> {
+        self.tiles.iter().find(|tile| tile.char_code == t)
+    }
+
+    // checks if units can walk on a tile
+    pub fn is_passable(&self, tile: &Tile) -> bool {
+        self.terrain(tile.terrain).map_or(false, |t| t.passable)
+    }
+
+    // how much it costs to move onto a tile, or None if it can't be moved onto
+    pub fn move_cost(&self, tile: &Tile) -> Option<f32> {
+        match self.terrain(tile.terrain) {
+            Some(t) if t.passable => Some(t.move_cost),
+            _ => None,
+        }
+    }
+
+    // checks if a tile blocks line of sight
+    pub fn blocks_sight(&self, tile: &Tile) -> bool {
+        self.terrain(tile.terrain).map_or(false, |t| t.blocks_sight)
+    }
+
+    // how much of an attack a unit on a tile avoids
+    pub fn cover(&self, tile: &Tile) -> f32 {
+        self.terrain(tile.terrain).map_or(0., |t| t.cover)
+    }
+
+    // multiplies the speed of units moving across a tile
+    pub fn speed_modifier(&self, tile: &Tile) -> f32 {
+        self.terrain(tile.terrain).map_or(1., |t| t.speed_modifier)
+    }
+
+    // picks the sprite of a tile, given the terrain of its neighbours in the order of NEIGHBOUR_OFFSETS
+    // flat tiles use the first transition rule that fits, otherwise the terrain's own sprite is used
+    pub fn tile_sprite(&self, tile: &Tile, neighbours: &[Option<char>; 8]) -> usize {
+        if tile.slope == Slope::None {
+            let transition = self.transitions.iter()
+                .filter(|rule| rule.terrain == tile.terrain)
+                .filter_map(|rule| rule.sprite_for(neighbours))
+                .next();
+            if let Some(sprite) = transition {
+                return sprite;
+            }
+        }
+        match self.terrain(tile.terrain) {
+            Some(terrain) => terrain.sprite_for(tile.variant, tile.slope),
+            _ => tile.sprite_index,
+        }
+    }
+
+    // checks every sprite the terrain pack uses is in its sprite sheet
+    pub fn validate(&self) -> Result<(), TerrainPackError> {
+        let check = |owner: String, sprite: usize| {
+            if sprite < self.num_tiles {
+                Ok(())
+            } else {
+                Err(TerrainPackError::BadSprite { owner, sprite, count: self.num_tiles })
+            }
+        };
+        for terrain in self.tiles.iter() {
+            if terrain.tiles.is_empty() {
+                return Err(TerrainPackError::NoSprites(terrain.name.clone()));
+            }
+            let slope_tiles = &terrain.slope_tiles;
+            for sprite in terrain.tiles.iter().chain(slope_tiles.nw.iter()).chain(slope_tiles.ne.iter())
+                .chain(slope_tiles.se.iter()).chain(slope_tiles.sw.iter()) {
+                check(format!("terrain {}", terrain.name), *sprite)?;
+            }
+        }
+        for rule in self.transitions.iter() {
+            for sprite in rule.tiles.values() {
+                check(format!("the transition from '{}' to '{}'", rule.terrain, rule.neighbour), *sprite)?;
+            }
+        }
+        Ok(())
+    }
+
     // pulls a TerrainTile using the char code
     pub fn find_terrain(&self, t: char) -> amethyst::Result<TerrainTile> {
-        match self.tiles.clone()
-            .into_iter()
-            .find(|tile| tile.char_code == t) {
+        match self.terrain(t) {
             Some(tile) => Ok(tile.clone()),
             _ => Err(Error::from_string("tile incompatible with terrain set"))
         }
     }
 
     // creates a Tile from a TerrainTile
-    pub fn create_tile(&self, t: char, x: usize, y: usize, e: usize) -> amethyst::Result<Tile> {
-        match self.tiles.clone()
-            .into_iter()
+    pub fn create_tile(&self, t: char, x: usize, y: usize, e: usize, seed: u64) -> amethyst::Result<Tile> {
+        match self.tiles.iter()
             .find(|tile| tile.char_code == t) {
-            Some(tile) => Ok(tile.create_tile(x, y, e)),
+            Some(tile) => Ok(tile.create_tile(x, y, e, seed)),
             _ => Err(Error::from_string("tile incompatible with terrain set"))
         }
     }
@@ -45,4 +163,82 @@ fn default_description() -> String {
 
 pub struct TerrainSprites {
     pub set: Vec<SpriteRender>
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::load_terrain_pack;
+
+    fn grass_to_dirt() -> TransitionRule {
+        let tiles = [(1, 10), (1 | 2, 11), (16, 12), (16 | 64, 13)].iter().cloned().collect();
+        TransitionRule { terrain: 'g', neighbour: 'd', tiles }
+    }
+
+    // the neighbours with dirt at the given places in NEIGHBOUR_OFFSETS, and grass everywhere else
+    fn dirt_at(places: &[usize]) -> [Option<char>; 8] {
+        let mut neighbours = [Some('g'); 8];
+        for place in places.iter() {
+            neighbours[*place] = Some('d');
+        }
+        neighbours
+    }
+
+    #[test]
+    fn sides_and_corners_make_the_mask() {
+        let rule = grass_to_dirt();
+        assert_eq!(rule.sprite_for(&dirt_at(&[])), None);
+        assert_eq!(rule.sprite_for(&dirt_at(&[0])), Some(10));
+        assert_eq!(rule.sprite_for(&dirt_at(&[0, 1])), Some(11));
+        assert_eq!(rule.sprite_for(&dirt_at(&[4])), Some(12));
+        assert_eq!(rule.sprite_for(&dirt_at(&[4, 6])), Some(13));
+        // a mask without a sprite
+        assert_eq!(rule.sprite_for(&dirt_at(&[2])), None);
+    }
+
+    #[test]
+    fn corners_next_to_a_side_are_covered_by_it() {
+        let rule = grass_to_dirt();
+        // the N and W corners are at the ends of the NW side
+        assert_eq!(rule.sprite_for(&dirt_at(&[0, 4, 7])), Some(10));
+        // the N corner is at the end of the NW and NE sides
+        assert_eq!(rule.sprite_for(&dirt_at(&[0, 1, 4])), Some(11));
+    }
+
+    #[test]
+    fn map_edges_and_other_terrain_dont_count() {
+        let rule = grass_to_dirt();
+        let mut neighbours = [None; 8];
+        neighbours[0] = Some('d');
+        neighbours[1] = Some('w');
+        assert_eq!(rule.sprite_for(&neighbours), Some(10));
+    }
+
+    #[test]
+    fn shipped_pack_has_a_sprite_for_every_mask() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        let rule = terrain.transitions.iter()
+            .find(|rule| rule.terrain == 'g' && rule.neighbour == 'd')
+            .unwrap();
+        for places in 1..256usize {
+            let neighbours = dirt_at(&(0..8).filter(|i| places & (1 << i) != 0).collect::<Vec<_>>());
+            let sprite = rule.sprite_for(&neighbours);
+            assert!(sprite.map_or(false, |s| s < terrain.num_tiles), "no sprite for mask {}", places);
+        }
+    }
+
+    #[test]
+    fn sprites_outside_the_sheet_are_reported() {
+        let mut terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        assert!(terrain.validate().is_ok());
+        terrain.transitions.push(TransitionRule {
+            terrain: 'd',
+            neighbour: 'g',
+            tiles: [(1, terrain.num_tiles)].iter().cloned().collect(),
+        });
+        match terrain.validate() {
+            Err(TerrainPackError::BadSprite { sprite, .. }) => assert_eq!(sprite, terrain.num_tiles),
+            other => panic!("expected a bad sprite, got {:?}", other),
+        }
+    }
+}
@@ -2,6 +2,7 @@ use amethyst::{
     ecs::Entity,
     renderer::SpriteRender,
 };
+use serde::Deserialize;
 
 pub struct UISprites {
     pub set: Vec<SpriteRender>
@@ -9,4 +10,20 @@ pub struct UISprites {
 
 pub struct CameraHandle {
     pub camera: Entity
+}
+
+// the parts of a sprite sheet's .ron file needed to know how many sprites it has
+#[derive(Deserialize, Default)]
+pub struct SheetDefinition {
+    pub sprites: Vec<SpriteDefinition>,
+}
+
+// the positions aren't used, they're only read to check the sheet is well formed
+#[allow(dead_code)]
+#[derive(Deserialize)]
+pub struct SpriteDefinition {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
 }
\ No newline at end of file
@@ -1,31 +1,68 @@
 use amethyst::{
     assets::{AssetStorage, Loader},
     core::{math::base::Vector3, transform::Transform},
-    ecs::prelude::Entity,
-    input::{get_key, is_close_requested, is_key_down, VirtualKeyCode},
+    ecs::prelude::{Entities, Entity, Join, LazyUpdate, Read, ReadExpect, ReadStorage, WriteStorage},
+    input::{get_key, is_close_requested, is_key_down, InputEvent, VirtualKeyCode},
     prelude::*,
     renderer::{Camera, ImageFormat, SpriteRender, SpriteSheet, SpriteSheetFormat, Texture},
-//    ui::{Anchor, UiTransform},
+    ui::{Anchor, FontHandle, Interactable, TtfFormat, UiEvent, UiEventType, UiImage, UiText, UiTransform},
     window::ScreenDimensions,
 };
 
 use crate::{
-    components::Tile,
+    components::{Flag, MapObject, ObjectKind, ObjectSlot, Region, Tile, TileUIElement, Unit},
+    enitities::{create_flag, create_object, create_region_ui, create_unit, flag_sprite, object_sprite, unit_sprite, update_flags},
     resources::{
+        Brush,
         CameraHandle,
+        Clipboard,
+        count_sheet_sprites,
+        EditCommand,
+        EditorConfig,
+        EditorHistory,
+        EditorMode,
+        EditorPanel,
+        EDITOR_WIDTH,
+        MAX_TEAMS,
+        load_editor_config,
+        load_match_rules,
+        load_stamp,
         load_test_map,
         load_terrain_pack,
-        Map, MapDimensions,
+        Map, MapDimensions, MapLoadError,
+        MatchRules,
+        MatchState,
+        save_map,
+        save_stamp,
+        Selection,
+        Stamp,
+        stamp_files,
         TerrainSet,
         TerrainSprites,
         TileMap,
+        TileRecord,
         UISprites
     },
-    util::{map_to_world_iso_simple, map_to_world_iso, TILE_SIZE, TileLayer, z_value_iso},
+    systems::{refresh_regions, refresh_sprites, set_tile},
+    tools::{generate_map, validate_map, GeneratorSettings, Severity, MIN_GENERATED_SIZE},
+    util::{map_to_world_iso_simple, object_translation, tile_translation, PathOptions, TILE_SIZE},
+};
+use log::{error, info, warn};
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
 };
-use log::info;
 
-pub struct MapEditorState;
+pub struct MapEditorState {
+    // the map to edit and its terrain pack, until the state starts
+    loaded: Option<(Map, TerrainSet)>,
+}
+
+impl MapEditorState {
+    pub fn new(map: Map, terrain: TerrainSet) -> MapEditorState {
+        MapEditorState { loaded: Some((map, terrain)) }
+    }
+}
 
 impl SimpleState for MapEditorState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
@@ -33,14 +70,15 @@ impl SimpleState for MapEditorState {
 
         let dimensions = (*world.read_resource::<ScreenDimensions>()).clone();
 
-        // load map from disc (test map right now, will be loaded in loading state I think)
-        let mut map = load_test_map().unwrap();
+        let (map, terrain) = match self.loaded.take() {
+            Some(loaded) => loaded,
+            _ => return,
+        };
         // get map dimensions, save as resource
         world.insert(map.dimensions.clone());
         let camera = init_camera(world, &dimensions, &map.dimensions);
         // save camera as resource
         world.insert(CameraHandle{camera});
-        let terrain = load_terrain_pack(map.terrain_file.clone()).unwrap();
         // save terrain pack as resource
         world.insert(terrain.clone());
         let terrain_sprites = load_terrain_textures(world, &terrain);
@@ -49,18 +87,129 @@ impl SimpleState for MapEditorState {
         let ui_sprites = load_ui_textures(world);
         // save ui sprites as resource
         world.insert(UISprites { set: ui_sprites.clone() });
-        let tile_map = init_map(world, &mut map, &terrain, &terrain_sprites, &dimensions);
+        let tile_map = init_map(world, &map, &terrain, &terrain_sprites, &dimensions);
         // save set of tiles as resource
         world.insert(tile_map);
+        init_objects(world, &map, &ui_sprites, &terrain_sprites);
+        init_regions(world, &map, &ui_sprites);
+        let match_rules = load_match_rules();
+        init_units(world, &map, &terrain, &ui_sprites, &match_rules);
+        world.insert(match_rules);
+        world.insert(MatchState::default());
+        // save the map itself as resource, so it can be written back to disc
+        world.insert(map);
+        world.insert(EditorMode::None);
+        world.insert(Brush::default());
+        world.insert(Selection::default());
+        world.insert(Clipboard::default());
+        let editor_config = load_editor_config();
+        world.insert(EditorHistory::new(editor_config.history_budget));
+        world.insert(editor_config);
 
-//        init_editor_panel(world, &ui_sprites);
+        let editor_panel = init_editor_panel(world, &terrain, &terrain_sprites);
+        world.insert(editor_panel);
     }
 
     fn handle_event(
         &mut self,
-        mut _data: StateData<'_, GameData<'_, '_>>,
+        mut data: StateData<'_, GameData<'_, '_>>,
         event: StateEvent,
     ) -> SimpleTrans {
+        if let StateEvent::Input(InputEvent::ActionPressed(action)) = &event {
+            match action.as_str() {
+                "SaveMap" => save_editor_map(data.world),
+                "ValidateMap" => validate_editor_map(data.world),
+                "RandomMap" => new_random_map(data.world),
+                "Undo" => {
+                    let command = data.world.write_resource::<EditorHistory>().undo();
+                    if let Some(command) = command {
+                        apply_command(data.world, &command, true);
+                    }
+                },
+                "Redo" => {
+                    let command = data.world.write_resource::<EditorHistory>().redo();
+                    if let Some(command) = command {
+                        apply_command(data.world, &command, false);
+                    }
+                },
+                "CycleTerrain" => cycle_terrain(data.world),
+                "FillTool" => select_fill_tool(data.world, false),
+                "RectFillTool" => select_fill_tool(data.world, true),
+                "RaiseTool" => *data.world.write_resource::<EditorMode>() = EditorMode::Raise,
+                "LowerTool" => *data.world.write_resource::<EditorMode>() = EditorMode::Lower,
+                "SlopeTool" => *data.world.write_resource::<EditorMode>() = EditorMode::Slope,
+                "ObjectTool" | "ObjectSlot" | "ObjectTeam" => select_object_tool(data.world, action),
+                "RegionTool" => select_region_tool(data.world),
+                "SelectTool" => *data.world.write_resource::<EditorMode>() = EditorMode::Select,
+                "CropMap" => {
+                    let rect = data.world.read_resource::<Selection>().rect;
+                    match rect {
+                        Some(rect) => reshape_map(data.world, |map, _| map.crop(rect.x, rect.y, rect.width, rect.height)),
+                        _ => warn!("select the part of the map to crop to first"),
+                    }
+                },
+                "CopySelection" => copy_selection(data.world, false),
+                "CutSelection" => copy_selection(data.world, true),
+                "PasteTool" => {
+                    if data.world.read_resource::<Clipboard>().stamp.is_some() {
+                        *data.world.write_resource::<EditorMode>() = EditorMode::Paste;
+                    } else {
+                        warn!("copy some tiles or load a stamp first");
+                    }
+                },
+                "RotateStamp" | "MirrorStamp" => {
+                    if let Some(stamp) = data.world.write_resource::<Clipboard>().stamp.as_mut() {
+                        if action == "RotateStamp" { stamp.rotate() } else { stamp.mirror() }
+                    }
+                },
+                "SaveStamp" => save_clipboard(data.world),
+                "NextStamp" => next_stamp(data.world),
+                "GrowNW" => reshape_map(data.world, |map, fill| map.resize(1, 0, 0, 0, fill)),
+                "GrowNE" => reshape_map(data.world, |map, fill| map.resize(0, 1, 0, 0, fill)),
+                "GrowSE" => reshape_map(data.world, |map, fill| map.resize(0, 0, 1, 0, fill)),
+                "GrowSW" => reshape_map(data.world, |map, fill| map.resize(0, 0, 0, 1, fill)),
+                "ShrinkNW" => reshape_map(data.world, |map, fill| map.resize(-1, 0, 0, 0, fill)),
+                "ShrinkNE" => reshape_map(data.world, |map, fill| map.resize(0, -1, 0, 0, fill)),
+                "ShrinkSE" => reshape_map(data.world, |map, fill| map.resize(0, 0, -1, 0, fill)),
+                "ShrinkSW" => reshape_map(data.world, |map, fill| map.resize(0, 0, 0, -1, fill)),
+                "ShiftNW" => reshape_map(data.world, |map, fill| map.shift(-1, 0, fill)),
+                "ShiftNE" => reshape_map(data.world, |map, fill| map.shift(0, -1, fill)),
+                "ShiftSE" => reshape_map(data.world, |map, fill| map.shift(1, 0, fill)),
+                "ShiftSW" => reshape_map(data.world, |map, fill| map.shift(0, 1, fill)),
+                "TeamCount" => {
+                    let mut map = data.world.write_resource::<Map>();
+                    map.teams = match map.teams {
+                        2 => 3,
+                        3 => 4,
+                        _ => 2,
+                    };
+                    info!("map is for {} teams", map.teams);
+                },
+                "ToggleAutoSlopes" => {
+                    let mut map = data.world.write_resource::<Map>();
+                    map.auto_slopes = !map.auto_slopes;
+                    info!("automatic slopes: {}", map.auto_slopes);
+                },
+                "CycleBrush" => data.world.write_resource::<Brush>().cycle_shape(),
+                "BrushGrow" => {
+                    let max_size = data.world.read_resource::<EditorConfig>().max_brush_size;
+                    let mut brush = data.world.write_resource::<Brush>();
+                    brush.size = (brush.size + 1).min(max_size);
+                },
+                "BrushShrink" => {
+                    let mut brush = data.world.write_resource::<Brush>();
+                    brush.size = (brush.size - 1).max(1);
+                },
+                _ => {},
+            }
+            update_panel_border(data.world);
+        }
+
+        if let StateEvent::Ui(UiEvent { event_type: UiEventType::Click, target }) = &event {
+            click_editor_panel(data.world, *target);
+            update_panel_border(data.world);
+        }
+
         if let StateEvent::Window(event) = &event {
             // Check if the window should be closed
             if is_close_requested(&event) || is_key_down(&event, VirtualKeyCode::Escape) {
@@ -81,6 +230,395 @@ impl SimpleState for MapEditorState {
         // Keep going
         Trans::None
     }
+
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        paste_clipboard(data.world);
+        Trans::None
+    }
+}
+
+// reverts or reapplies a step from the editor history
+fn apply_command(world: &mut World, command: &EditCommand, undo: bool) {
+    match command {
+        EditCommand::Tiles(edits) => world.exec(|(entities, objects, tiles_ui, mut tiles, mut sprites, mut transforms, lazy_update, map_dimensions, terrain_set, terrain_sprites, tile_map, ui_sprites): (
+            Entities,
+            ReadStorage<MapObject>,
+            WriteStorage<TileUIElement>,
+            WriteStorage<Tile>,
+            WriteStorage<SpriteRender>,
+            WriteStorage<Transform>,
+            ReadExpect<LazyUpdate>,
+            Read<MapDimensions>,
+            ReadExpect<TerrainSet>,
+            ReadExpect<TerrainSprites>,
+            ReadExpect<TileMap>,
+            ReadExpect<UISprites>,
+        )| {
+            for edit in edits.iter() {
+                let tile = if undo { edit.before.clone() } else { edit.after.clone() };
+                let entity = tile_map.get(edit.x, edit.y, map_dimensions.width);
+                set_tile(entity, tile, &mut tiles, &mut sprites, &mut transforms, &terrain_sprites);
+            }
+            let points: Vec<(usize, usize)> = edits.iter().map(|edit| (edit.x, edit.y)).collect();
+            refresh_sprites(&points, &map_dimensions, &tile_map, &terrain_set, &mut tiles, &mut sprites, &terrain_sprites);
+            refresh_regions(&points, &entities, &tiles_ui, &tiles, &map_dimensions, &tile_map, &ui_sprites, &lazy_update);
+            // objects follow the elevation of their tiles
+            for (object, transform) in (&objects, &mut transforms).join() {
+                if points.contains(&(object.x, object.y)) {
+                    if let Some(tile) = tiles.get(tile_map.get(object.x, object.y, map_dimensions.width)) {
+                        let (world_x, world_y, world_z) = object_translation(object, tile.surface_elevation());
+                        transform.set_translation_xyz(world_x, world_y, world_z);
+                    }
+                }
+            }
+        }),
+        EditCommand::Objects { added, removed } => world.exec(|(entities, objects, flags, tiles, lazy_update, map_dimensions, terrain_sprites, tile_map, ui_sprites): (
+            Entities,
+            ReadStorage<MapObject>,
+            ReadStorage<Flag>,
+            ReadStorage<Tile>,
+            ReadExpect<LazyUpdate>,
+            Read<MapDimensions>,
+            ReadExpect<TerrainSprites>,
+            ReadExpect<TileMap>,
+            ReadExpect<UISprites>,
+        )| {
+            let (remove, add) = if undo { (added, removed) } else { (removed, added) };
+            for (entity, object) in (&entities, &objects).join() {
+                if remove.contains(object) {
+                    entities.delete(entity).unwrap();
+                }
+            }
+            for object in add.iter() {
+                if let Some(tile) = tiles.get(tile_map.get(object.x, object.y, map_dimensions.width)) {
+                    let sprite = object_sprite(object.kind, &ui_sprites.set, &terrain_sprites.set);
+                    create_object(&entities, object.clone(), sprite, tile.surface_elevation(), &lazy_update);
+                }
+            }
+            // teams gain or lose their flag with their flag stands
+            let placed: Vec<(&MapObject, f32)> = (&objects).join()
+                .filter(|object| !remove.contains(object))
+                .chain(add.iter())
+                .filter_map(|object| tiles.get(tile_map.get(object.x, object.y, map_dimensions.width))
+                    .map(|tile| (object, tile.surface_elevation())))
+                .collect();
+            update_flags(&entities, &flags, &placed, &ui_sprites.set, &lazy_update);
+        }),
+        EditCommand::Reshape { before, after } => {
+            let map = if undo { before } else { after };
+            rebuild_map(world, (**map).clone());
+        },
+    }
+}
+
+// switches the editor to the region tool, or to the next region it paints
+// goes through the spawn area and base of each team, then clearing regions
+fn select_region_tool(world: &mut World) {
+    let teams = match world.read_resource::<Map>().teams {
+        teams if teams >= 2 => teams,
+        _ => MAX_TEAMS,
+    };
+    let mut mode = world.write_resource::<EditorMode>();
+    let region = match &*mode {
+        EditorMode::Region(Some(Region::Spawn(team))) => Some(Region::Base(*team)),
+        EditorMode::Region(Some(Region::Base(team))) if team + 1 < teams => Some(Region::Spawn(team + 1)),
+        EditorMode::Region(Some(Region::Base(_))) => None,
+        _ => Some(Region::Spawn(0)),
+    };
+    match region {
+        Some(region) => info!("painting {:?}", region),
+        _ => info!("clearing regions"),
+    }
+    *mode = EditorMode::Region(region);
+}
+
+// switches the editor to the object tool, or changes the object, slot or team it places
+fn select_object_tool(world: &mut World, action: &str) {
+    let mut mode = world.write_resource::<EditorMode>();
+    let (kind, slot, team) = match &*mode {
+        EditorMode::Object { kind, slot, team } => match action {
+            "ObjectTool" => {
+                let next = match kind {
+                    ObjectKind::Spawn => ObjectKind::FlagStand,
+                    ObjectKind::FlagStand => ObjectKind::Wall,
+                    ObjectKind::Wall => ObjectKind::Decoration(0),
+                    ObjectKind::Decoration(_) => ObjectKind::Spawn,
+                };
+                (next, *slot, *team)
+            },
+            "ObjectSlot" => (*kind, slot.next(), *team),
+            _ => (*kind, *slot, (team + 1) % MAX_TEAMS),
+        },
+        _ => (ObjectKind::Spawn, ObjectSlot::C, 0),
+    };
+    info!("placing {:?} in slot {:?} for team {}", kind, slot, team);
+    *mode = EditorMode::Object { kind, slot, team };
+}
+
+// switches the editor to the next terrain in the terrain pack, or to no terrain after the last one
+fn cycle_terrain(world: &mut World) {
+    let terrain = world.read_resource::<TerrainSet>();
+    let mut mode = world.write_resource::<EditorMode>();
+    let next = match mode.terrain() {
+        Some(current) => terrain.tiles.iter()
+            .position(|t| t.char_code == current.char_code)
+            .map(|i| i + 1)
+            .unwrap_or(0),
+        _ => 0,
+    };
+    *mode = match terrain.tiles.get(next) {
+        Some(t) => {
+            info!("painting with {}", t.name);
+            mode.with_terrain(t.clone())
+        },
+        _ => EditorMode::None,
+    };
+}
+
+// switches the editor to a fill tool, keeping the current terrain
+// picking the bucket fill again toggles whether it only fills tiles of the same elevation
+fn select_fill_tool(world: &mut World, rect: bool) {
+    let first_terrain = world.read_resource::<TerrainSet>().tiles.first().cloned();
+    let mut mode = world.write_resource::<EditorMode>();
+    let terrain = match mode.terrain().cloned().or(first_terrain) {
+        Some(terrain) => terrain,
+        _ => return,
+    };
+    *mode = match (&*mode, rect) {
+        (_, true) => EditorMode::RectFill(terrain),
+        (EditorMode::Fill { match_elevation, .. }, false) => {
+            info!("fill matching elevation: {}", !match_elevation);
+            EditorMode::Fill { terrain, match_elevation: !match_elevation }
+        },
+        (_, false) => EditorMode::Fill { terrain, match_elevation: false },
+    };
+}
+
+// loads the map and its terrain pack, and builds the map's tiles
+// (test map right now, will be loaded in loading state I think)
+pub fn load_editor_map() -> Result<(Map, TerrainSet), MapLoadError> {
+    let mut map = load_test_map()?;
+    let terrain = load_terrain_pack(map.terrain_file.clone())?;
+    map.build_tiles(&terrain)?;
+    // unfinished maps can still be edited
+    if let Err(e) = map.validate_teams(&terrain) {
+        warn!("map isn't ready for a match: {}", e);
+    }
+    Ok((map, terrain))
+}
+
+// writes the map being edited back to its file
+fn save_editor_map(world: &mut World) {
+    let mut map = edited_map(world);
+    {
+        let terrain = world.read_resource::<TerrainSet>();
+        map.build_records(&terrain);
+        if let Err(e) = map.validate_teams(&terrain) {
+            warn!("map isn't ready for a match: {}", e);
+        }
+    }
+    let filename = map.filename.clone();
+    match save_map(&map, filename.clone()) {
+        Ok(_) => info!("saved map to {}", filename),
+        Err(e) => error!("could not save map to {}: {}", filename, e),
+    }
+}
+
+// the map being edited, with the tiles and objects as they are now
+fn edited_map(world: &World) -> Map {
+    let mut map = world.read_resource::<Map>().clone();
+    map.read_tiles(&world.read_resource::<TileMap>(), &world.read_storage::<Tile>());
+    map.read_objects(&world.read_storage::<MapObject>());
+    map
+}
+
+// checks the map being edited is playable and fair, and logs what was found
+fn validate_editor_map(world: &World) {
+    let map = edited_map(world);
+    let report = validate_map(&map, &world.read_resource::<TerrainSet>(), &PathOptions::default());
+    for routes in report.routes.iter() {
+        info!("team {} route costs: spawn to flag {:?}, flag to base {:?}", routes.team, routes.spawn_to_flag, routes.flag_to_base);
+    }
+    for issue in report.issues.iter() {
+        match issue.severity() {
+            Severity::Error => error!("{}", issue),
+            Severity::Warning => warn!("{}", issue),
+        }
+    }
+    info!("validated map: {} errors, {} warnings", report.errors(), report.warnings());
+}
+
+// replaces the map being edited with a newly generated one of the same size and terrain
+fn new_random_map(world: &mut World) {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or(0);
+    let (settings, terrain_file) = {
+        let map = world.read_resource::<Map>();
+        let settings = GeneratorSettings {
+            width: map.dimensions.width.max(MIN_GENERATED_SIZE),
+            height: map.dimensions.height.max(MIN_GENERATED_SIZE),
+            teams: if map.teams == 4 { 4 } else { 2 },
+            seed,
+            ..GeneratorSettings::default()
+        };
+        (settings, map.terrain_file.clone())
+    };
+    let generated = generate_map(&world.read_resource::<TerrainSet>(), &terrain_file, &settings);
+    match generated {
+        Ok(mut map) => {
+            // a new file, so saving doesn't overwrite the map that was open
+            map.filename = format!("random_{}.cfmap", seed);
+            info!("generated a {}x{} map for {} teams with seed {}", settings.width, settings.height, settings.teams, seed);
+            rebuild_map(world, map);
+            // the edit history belongs to the old map
+            let history_budget = world.read_resource::<EditorConfig>().history_budget;
+            world.insert(EditorHistory::new(history_budget));
+        },
+        Err(e) => error!("could not generate a map: {}", e),
+    }
+}
+
+// resizes, crops, shifts or pastes into the map being edited, as one step that can be undone
+// tiles added to the map get the terrain being painted with, at elevation 0
+fn reshape_map<F>(world: &mut World, reshape: F)
+    where F: FnOnce(&mut Map, &TileRecord) -> Result<(), MapLoadError>
+{
+    let mut before = edited_map(world);
+    let terrain = world.read_resource::<TerrainSet>().clone();
+    before.build_records(&terrain);
+    let fill_terrain = world.read_resource::<EditorMode>().terrain()
+        .or_else(|| terrain.tiles.first())
+        .map(|t| t.char_code);
+    let fill = match fill_terrain {
+        Some(code) => TileRecord::new(code, 0),
+        _ => return,
+    };
+    let mut after = before.clone();
+    if let Err(e) = reshape(&mut after, &fill).and_then(|_| after.build_tiles(&terrain)) {
+        error!("{}", e);
+        return;
+    }
+    info!("map is now {}x{}", after.dimensions.width, after.dimensions.height);
+    rebuild_map(world, after.clone());
+    world.write_resource::<EditorHistory>().push(EditCommand::Reshape { before: Box::new(before), after: Box::new(after) });
+}
+
+// copies the selected tiles to the clipboard, clearing them when cutting
+fn copy_selection(world: &mut World, cut: bool) {
+    let rect = match world.read_resource::<Selection>().rect {
+        Some(rect) => rect,
+        _ => {
+            warn!("select the tiles to copy first");
+            return;
+        },
+    };
+    let mut map = edited_map(world);
+    map.build_records(&world.read_resource::<TerrainSet>());
+    world.write_resource::<Clipboard>().stamp = Some(Stamp::copy(&map, rect));
+    info!("copied {}x{} tiles", rect.width, rect.height);
+    if cut {
+        reshape_map(world, |map, fill| {
+            let blank = Stamp::filled(&map.terrain_file, rect.width, rect.height, fill);
+            blank.paste(map, rect.x, rect.y)
+        });
+    }
+}
+
+// pastes the clipboard where the paste tool was clicked, if it was
+fn paste_clipboard(world: &mut World) {
+    let at = world.write_resource::<Clipboard>().paste_at.take();
+    let stamp = world.read_resource::<Clipboard>().stamp.clone();
+    if let (Some((x, y)), Some(stamp)) = (at, stamp) {
+        reshape_map(world, |map, _| stamp.paste(map, x, y));
+    }
+}
+
+// saves the clipboard as a new stamp file, which can be renamed to anything
+fn save_clipboard(world: &mut World) {
+    let mut clipboard = world.write_resource::<Clipboard>();
+    let stamp = match clipboard.stamp.as_mut() {
+        Some(stamp) => stamp,
+        _ => {
+            warn!("copy some tiles to save as a stamp first");
+            return;
+        },
+    };
+    let files = stamp_files();
+    let filename = (1..)
+        .map(|n| format!("stamp_{}.cfstamp", n))
+        .find(|f| !files.contains(f))
+        .unwrap();
+    match save_stamp(stamp, filename.clone()) {
+        Ok(_) => {
+            info!("saved stamp to stamps/{}", filename);
+            stamp.filename = filename;
+        },
+        Err(e) => error!("could not save stamp to stamps/{}: {}", filename, e),
+    }
+}
+
+// loads the next stamp made for the map's terrain pack into the clipboard, and switches to the paste tool
+fn next_stamp(world: &mut World) {
+    let terrain_file = world.read_resource::<Map>().terrain_file.clone();
+    let current = world.read_resource::<Clipboard>().stamp.as_ref()
+        .map(|stamp| stamp.filename.clone())
+        .unwrap_or_default();
+    let files = stamp_files();
+    let start = files.iter().position(|f| *f == current).map_or(0, |i| i + 1);
+    // try every file once, starting after the current stamp
+    for filename in files.iter().cycle().skip(start).take(files.len()) {
+        match load_stamp(filename.clone()) {
+            Ok(stamp) if stamp.terrain_file == terrain_file => {
+                info!("pasting stamp {} ({}x{})", filename, stamp.width, stamp.height);
+                world.write_resource::<Clipboard>().stamp = Some(stamp);
+                *world.write_resource::<EditorMode>() = EditorMode::Paste;
+                return;
+            },
+            Ok(_) => {},
+            Err(e) => warn!("{}", e),
+        }
+    }
+    warn!("there are no stamps for terrain pack {}", terrain_file);
+}
+
+// swaps the map being edited for another one, recreating its tiles, objects and overlays
+fn rebuild_map(world: &mut World, map: Map) {
+    let old: Vec<Entity> = world.exec(|(entities, tiles, objects, flags, tiles_ui, units): (
+        Entities,
+        ReadStorage<Tile>,
+        ReadStorage<MapObject>,
+        ReadStorage<Flag>,
+        ReadStorage<TileUIElement>,
+        ReadStorage<Unit>,
+    )| {
+        let mut old: Vec<Entity> = (&entities, &tiles).join().map(|(entity, _)| entity).collect();
+        old.extend((&entities, &objects).join().map(|(entity, _)| entity));
+        old.extend((&entities, &flags).join().map(|(entity, _)| entity));
+        old.extend((&entities, &tiles_ui).join().map(|(entity, _)| entity));
+        old.extend((&entities, &units).join().map(|(entity, _)| entity));
+        old
+    });
+    if let Err(e) = world.delete_entities(&old) {
+        error!("could not remove the old map: {}", e);
+    }
+
+    let dimensions = (*world.read_resource::<ScreenDimensions>()).clone();
+    let terrain = world.read_resource::<TerrainSet>().clone();
+    let terrain_sprites = world.read_resource::<TerrainSprites>().set.clone();
+    let ui_sprites = world.read_resource::<UISprites>().set.clone();
+    world.insert(map.dimensions.clone());
+    let tile_map = init_map(world, &map, &terrain, &terrain_sprites, &dimensions);
+    world.insert(tile_map);
+    init_objects(world, &map, &ui_sprites, &terrain_sprites);
+    init_regions(world, &map, &ui_sprites);
+    let match_rules = world.read_resource::<MatchRules>().clone();
+    init_units(world, &map, &terrain, &ui_sprites, &match_rules);
+    world.insert(MatchState::default());
+    world.insert(map);
+    // the selection may not fit on the new map
+    world.insert(Selection::default());
 }
 
 fn init_camera(world: &mut World, dimensions: &ScreenDimensions, map_dimensions: &MapDimensions) -> Entity {
@@ -153,7 +691,7 @@ pub fn load_ui_textures(world: &mut World) -> Vec<SpriteRender> {
         )
     };
 
-    (0..1)
+    (0..count_sheet_sprites("sprites/ui_sprites.ron"))
         .map(|i| SpriteRender {
             sprite_sheet: sheet_handle.clone(),
             sprite_number: i,
@@ -161,18 +699,16 @@ pub fn load_ui_textures(world: &mut World) -> Vec<SpriteRender> {
         .collect()
 }
 
-fn init_map(world: &mut World, map: &mut Map, terrain: &TerrainSet, tile_sprites: &[SpriteRender], dimensions: &ScreenDimensions) -> TileMap {
-    // initialize tiles (this will be done in loading state)
-    map.build_tiles(terrain);
+fn init_map(world: &mut World, map: &Map, terrain: &TerrainSet, tile_sprites: &[SpriteRender], dimensions: &ScreenDimensions) -> TileMap {
     let mut tile_map = TileMap { v: vec![] };
     for (y, row) in map.tiles.iter().enumerate() {
         for (x, t) in row.iter().enumerate() {
-            let (world_x, world_y) = map_to_world_iso(x as f32, y as f32, t.elevation as f32, t.height as f32);
+            let (world_x, world_y, world_z) = tile_translation(t);
 
             let mut transform = Transform::default();
             let scalar = TILE_SIZE / terrain.tile_size as f32;
             transform.set_scale(Vector3::new(scalar, scalar, 0.));
-            transform.set_translation_xyz(world_x, -world_y, z_value_iso(x as f32, y as f32, 0., TileLayer::Base));
+            transform.set_translation_xyz(world_x, world_y, world_z);
 
             tile_map.v.insert((y * map.dimensions.width) + x, world
                 .create_entity()
@@ -186,15 +722,151 @@ fn init_map(world: &mut World, map: &mut Map, terrain: &TerrainSet, tile_sprites
     tile_map
 }
 
-//fn init_editor_panel(world: &mut World, ui_sprites: &[SpriteRender]) {
-//    let transform = UiTransform::new(
-//        format!("terrain_panel_{}", 0),
-//        Anchor::TopLeft, Anchor::TopLeft,
-//        0.,0.,1.,32.,32.
-//    );
-//    world
-//        .create_entity()
-//        .with(ui_sprites[1].clone())
-//        .with(transform)
-//        .build();
-//}
+// places the map's objects, and a flag for each team with a flag stand
+fn init_objects(world: &mut World, map: &Map, ui_sprites: &[SpriteRender], terrain_sprites: &[SpriteRender]) {
+    world.exec(|(entities, lazy_update): (Entities, ReadExpect<LazyUpdate>)| {
+        let mut flagged = HashSet::new();
+        for object in map.objects.iter() {
+            let elevation = map.tiles[object.y][object.x].surface_elevation();
+            let sprite = object_sprite(object.kind, ui_sprites, terrain_sprites);
+            create_object(&entities, object.clone(), sprite, elevation, &lazy_update);
+            // a team has a single flag, which starts on its first flag stand
+            if let (ObjectKind::FlagStand, Some(team)) = (object.kind, object.team) {
+                if flagged.insert(team) {
+                    create_flag(&entities, flag_sprite(ui_sprites), team, object.x, object.y, elevation, &lazy_update);
+                }
+            }
+        }
+    });
+}
+
+// shows which tiles are in each team's spawn area and base
+fn init_regions(world: &mut World, map: &Map, ui_sprites: &[SpriteRender]) {
+    world.exec(|(entities, lazy_update): (Entities, ReadExpect<LazyUpdate>)| {
+        for tile in map.tiles.iter().flat_map(|row| row.iter()) {
+            if let Some(region) = tile.region {
+                create_region_ui(&entities, ui_sprites[0].clone(), tile.x, tile.y, tile.surface_elevation(), region, &lazy_update);
+            }
+        }
+    });
+}
+
+// tiles per second, and hit points
+const UNIT_SPEED: f32 = 2.;
+const UNIT_HEALTH: f32 = 100.;
+
+// places each team's starting units on the tiles of its spawn area that can be walked on
+fn init_units(world: &mut World, map: &Map, terrain: &TerrainSet, ui_sprites: &[SpriteRender], match_rules: &MatchRules) {
+    world.exec(|(entities, lazy_update): (Entities, ReadExpect<LazyUpdate>)| {
+        for team in 0..map.teams {
+            let spawns = map.tiles.iter()
+                .flat_map(|row| row.iter())
+                .filter(|tile| tile.region == Some(Region::Spawn(team)) && terrain.is_passable(tile))
+                .filter(|tile| !map.objects.iter().any(|o| o.kind.blocks_movement() && (o.x, o.y) == (tile.x, tile.y)))
+                .take(match_rules.units_per_team);
+            for tile in spawns {
+                let unit = Unit::new(team, UNIT_SPEED, UNIT_HEALTH, tile.x, tile.y);
+                create_unit(&entities, unit_sprite(ui_sprites), unit, tile.surface_elevation(), &lazy_update);
+            }
+        }
+    });
+}
+
+const PANEL_MARGIN: f32 = 8.;
+const PANEL_BUTTON_SIZE: f32 = 32.;
+
+// builds the side panel that lists the terrain of the terrain pack
+fn init_editor_panel(world: &mut World, terrain: &TerrainSet, terrain_sprites: &[SpriteRender]) -> EditorPanel {
+    let font: FontHandle = world.read_resource::<Loader>().load(
+        "font/FiraSans-Regular.otf",
+        TtfFormat,
+        (),
+        &world.read_resource(),
+    );
+    let mut panel = EditorPanel { width: EDITOR_WIDTH, ..Default::default() };
+
+    let background = world
+        .create_entity()
+        .with(UiTransform::new(
+            "editor_panel".to_string(),
+            Anchor::TopLeft, Anchor::TopLeft,
+            0., 0., 0., EDITOR_WIDTH, 4096.,
+        ))
+        .with(UiImage::SolidColor([0.1, 0.1, 0.12, 0.9]))
+        .build();
+    panel.terrain_panel.background.push(background);
+
+    // sits behind the selected terrain's sprite, hidden until something is selected
+    let border = world
+        .create_entity()
+        .with(UiTransform::new(
+            "terrain_panel_border".to_string(),
+            Anchor::TopLeft, Anchor::TopLeft,
+            PANEL_MARGIN - 2., -PANEL_MARGIN + 2., 1., PANEL_BUTTON_SIZE + 4., PANEL_BUTTON_SIZE + 4.,
+        ))
+        .with(UiImage::SolidColor([0.0, 0.0, 0.0, 0.0]))
+        .build();
+    panel.terrain_panel.border.push(border);
+
+    for (i, terrain_tile) in terrain.tiles.iter().enumerate() {
+        let y = -PANEL_MARGIN - i as f32 * (PANEL_BUTTON_SIZE + PANEL_MARGIN);
+        let sprite = world
+            .create_entity()
+            .with(UiTransform::new(
+                format!("terrain_panel_{}", i),
+                Anchor::TopLeft, Anchor::TopLeft,
+                PANEL_MARGIN, y, 2., PANEL_BUTTON_SIZE, PANEL_BUTTON_SIZE,
+            ))
+            .with(UiImage::Sprite(terrain_sprites[terrain_tile.tiles[0]].clone()))
+            .with(Interactable)
+            .build();
+        let label = world
+            .create_entity()
+            .with(UiTransform::new(
+                format!("terrain_panel_label_{}", i),
+                Anchor::TopLeft, Anchor::TopLeft,
+                PANEL_MARGIN * 2. + PANEL_BUTTON_SIZE, y, 2.,
+                EDITOR_WIDTH - PANEL_MARGIN * 3. - PANEL_BUTTON_SIZE, PANEL_BUTTON_SIZE,
+            ))
+            .with(UiText::new(font.clone(), terrain_tile.name.clone(), [1., 1., 1., 1.], 16.))
+            .with(Interactable)
+            .build();
+        panel.terrain_panel.buttons.push((sprite, terrain_tile.char_code));
+        panel.terrain_panel.buttons.push((label, terrain_tile.char_code));
+    }
+    panel
+}
+
+// selects the terrain of a clicked panel button
+fn click_editor_panel(world: &mut World, target: Entity) {
+    let code = match world.read_resource::<EditorPanel>().terrain_panel.buttons.iter().find(|(e, _)| *e == target) {
+        Some((_, code)) => *code,
+        _ => return,
+    };
+    let terrain_tile = world.read_resource::<TerrainSet>().tiles.iter().find(|t| t.char_code == code).cloned();
+    if let Some(terrain_tile) = terrain_tile {
+        info!("painting with {}", terrain_tile.name);
+        let mut mode = world.write_resource::<EditorMode>();
+        *mode = mode.with_terrain(terrain_tile);
+    }
+}
+
+// moves the border of the terrain panel to the selected terrain
+fn update_panel_border(world: &mut World) {
+    let selected = world.read_resource::<EditorMode>().terrain().map(|t| t.char_code);
+    let index = selected.and_then(|code| world.read_resource::<TerrainSet>().tiles.iter().position(|t| t.char_code == code));
+    let panel = world.read_resource::<EditorPanel>();
+    let mut transforms = world.write_storage::<UiTransform>();
+    let mut images = world.write_storage::<UiImage>();
+    for border in panel.terrain_panel.border.iter() {
+        if let (Some(transform), Some(image)) = (transforms.get_mut(*border), images.get_mut(*border)) {
+            match index {
+                Some(i) => {
+                    transform.local_y = -PANEL_MARGIN + 2. - i as f32 * (PANEL_BUTTON_SIZE + PANEL_MARGIN);
+                    *image = UiImage::SolidColor([1.0, 0.85, 0.2, 1.0]);
+                },
+                _ => *image = UiImage::SolidColor([0.0, 0.0, 0.0, 0.0]),
+            }
+        }
+    }
+}
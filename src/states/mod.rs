@@ -1,3 +1,3 @@
 mod map_edit_state;
 
-pub use self::map_edit_state::MapEditorState;
\ No newline at end of file
+pub use self::map_edit_state::{load_editor_map, MapEditorState};
\ No newline at end of file
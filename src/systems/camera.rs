@@ -6,7 +6,7 @@ use amethyst::{
     window::ScreenDimensions,
 };
 use crate::{
-    resources::MapDimensions,
+    resources::{EditorPanel, MapDimensions},
     util::{world_to_map_iso_simple, closest_point_in_map_iso}
 };
 
@@ -19,10 +19,11 @@ impl<'s> System<'s> for CameraSystem {
         WriteStorage<'s, Transform>,
         Read<'s, InputHandler<StringBindings>>,
         Read<'s, MapDimensions>,
+        Read<'s, EditorPanel>,
         Read<'s, Time>,
     );
 
-    fn run(&mut self, (screen_dimensions, cameras, mut transforms, input_handler, map_dimensions, time): Self::SystemData) {
+    fn run(&mut self, (screen_dimensions, cameras, mut transforms, input_handler, map_dimensions, editor_panel, time): Self::SystemData) {
         // will be an option
         let mouse_scroll_sensitivity = 50.;
         let delta_time = time.delta_real_seconds();
@@ -35,21 +36,23 @@ impl<'s> System<'s> for CameraSystem {
                 Some((x, y)) => (x, y),
                 _ => (screen_dimensions.width() * 0.5, screen_dimensions.height() * 0.5),
             };
+            // the mouse doesn't scroll the camera while it's over the editor panel
+            let edge_scroll = !editor_panel.contains(mouse_x);
             // move camera if mouse is at edge of screen or keys are pressed
             if input_handler.action_is_down("CameraMoveUp").unwrap()
-                || mouse_y - mouse_scroll_sensitivity < 0. {
+                || (edge_scroll && mouse_y - mouse_scroll_sensitivity < 0.) {
                 transform.move_up(move_factor);
             }
             if input_handler.action_is_down("CameraMoveDown").unwrap()
-                || mouse_y + mouse_scroll_sensitivity > screen_dimensions.height() {
+                || (edge_scroll && mouse_y + mouse_scroll_sensitivity > screen_dimensions.height()) {
                 transform.move_down(move_factor);
             }
             if input_handler.action_is_down("CameraMoveLeft").unwrap()
-                || mouse_x - mouse_scroll_sensitivity < 0.  {
+                || (edge_scroll && mouse_x - mouse_scroll_sensitivity < 0.)  {
                 transform.move_left(move_factor);
             }
             if input_handler.action_is_down("CameraMoveRight").unwrap()
-                || mouse_x + mouse_scroll_sensitivity > screen_dimensions.width()  {
+                || (edge_scroll && mouse_x + mouse_scroll_sensitivity > screen_dimensions.width())  {
                 transform.move_right(move_factor);
             }
             // snap camera to edge of map
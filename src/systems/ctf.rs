@@ -0,0 +1,276 @@
+use amethyst::{
+    core::{math::Vector3, Time, Transform},
+    ecs::*,
+};
+use log::info;
+use std::collections::HashSet;
+use crate::{
+    components::{Base, Flag, MapObject, ObjectKind, ObjectSlot, Tile, Unit},
+    resources::{MapDimensions, MatchResult, MatchRules, MatchState, TileMap},
+    util::{object_translation, HALF_TILE},
+};
+
+// runs the capture the flag rules: picking up, dropping, returning and capturing flags
+// only needs units, flags and bases, so it can be run on a bare world, where dropped flags lie at elevation 0
+pub struct CaptureFlagSystem;
+
+impl<'s> System<'s> for CaptureFlagSystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Unit>,
+        ReadStorage<'s, Base>,
+        WriteStorage<'s, Flag>,
+        WriteStorage<'s, Transform>,
+        ReadStorage<'s, Tile>,
+        Read<'s, Time>,
+        Read<'s, MatchRules>,
+        Read<'s, MapDimensions>,
+        Write<'s, MatchState>,
+        Option<Read<'s, TileMap>>,
+    );
+
+    fn run(&mut self, (entities, units, bases, mut flags, mut transforms, tiles, time, rules, map_dimensions, mut state, tile_map): Self::SystemData) {
+        if state.result.is_some() {
+            return;
+        }
+        state.elapsed += time.delta_seconds();
+
+        // carried flags follow their carrier, and are dropped where the carrier dies
+        for flag in (&mut flags).join() {
+            if let Some(carrier) = flag.carrier {
+                match units.get(carrier) {
+                    Some(unit) if entities.is_alive(carrier) && unit.is_alive() => {
+                        flag.x = unit.x;
+                        flag.y = unit.y;
+                    },
+                    _ => {
+                        flag.carrier = None;
+                        info!("team {} flag dropped at ({}, {})", flag.team, flag.x, flag.y);
+                    },
+                }
+            }
+        }
+
+        // units pick up enemy flags and return their own dropped flag by walking onto them
+        let mut carriers: HashSet<Entity> = (&flags).join().filter_map(|flag| flag.carrier).collect();
+        for (entity, unit) in (&entities, &units).join() {
+            if !unit.is_alive() {
+                continue;
+            }
+            for flag in (&mut flags).join() {
+                if flag.carrier.is_some() || (flag.x, flag.y) != (unit.x, unit.y) {
+                    continue;
+                }
+                if flag.team != unit.team {
+                    // a unit can only carry one flag
+                    if carriers.insert(entity) {
+                        flag.carrier = Some(entity);
+                        info!("team {} took the team {} flag", unit.team, flag.team);
+                    }
+                } else if !flag.is_home() {
+                    flag.return_home();
+                    info!("team {} returned its flag", unit.team);
+                }
+            }
+        }
+
+        // a team captures by bringing a flag to its own base while its own flag is home
+        let home: HashSet<usize> = (&flags).join().filter(|flag| flag.is_home()).map(|flag| flag.team).collect();
+        for flag in (&mut flags).join() {
+            let unit = match flag.carrier.and_then(|carrier| units.get(carrier)) {
+                Some(unit) => unit,
+                _ => continue,
+            };
+            let at_base = (&bases).join().any(|base| base.team == unit.team && (base.x, base.y) == (unit.x, unit.y));
+            if at_base && home.contains(&unit.team) {
+                state.add_capture(unit.team);
+                info!("team {} captured the team {} flag ({} captures)", unit.team, flag.team, state.score(unit.team));
+                flag.return_home();
+            }
+        }
+
+        // move the flag entities to where the flags are
+        let mut moves: Vec<(Entity, Vector3<f32>)> = vec![];
+        for (entity, flag) in (&entities, &flags).join() {
+            let translation = match flag.carrier {
+                // held up above the carrier
+                Some(carrier) => transforms.get(carrier)
+                    .map(|t| t.translation() + Vector3::new(0., HALF_TILE * 0.5, 0.001)),
+                _ if flag.is_home() => (&entities, &bases).join()
+                    .find(|(_, base)| base.team == flag.team && (base.x, base.y) == flag.home)
+                    .and_then(|(base_entity, _)| transforms.get(base_entity))
+                    .map(|t| *t.translation()),
+                // dropped flags lie on the ground where they fell
+                _ => {
+                    let elevation = tile_map.as_ref()
+                        .and_then(|tile_map| tile_map.v.get(flag.y * map_dimensions.width + flag.x))
+                        .and_then(|tile| tiles.get(*tile))
+                        .map_or(0., |tile| tile.surface_elevation());
+                    Some(ground_translation(flag.x, flag.y, elevation))
+                },
+            };
+            if let Some(translation) = translation {
+                moves.push((entity, translation));
+            }
+        }
+        for (entity, translation) in moves.into_iter() {
+            if let Some(transform) = transforms.get_mut(entity) {
+                transform.set_translation(translation);
+            }
+        }
+
+        // check if the match is over
+        let score_reached = rules.score_limit
+            .and_then(|limit| state.scores.iter().find(|(_, score)| **score >= limit).map(|(team, _)| *team));
+        let result = match (score_reached, rules.time_limit) {
+            (Some(team), _) => Some(MatchResult::Win(team)),
+            (_, Some(limit)) if state.elapsed >= limit => Some(state.leader()),
+            _ => None,
+        };
+        if let Some(result) = result {
+            match result {
+                MatchResult::Win(team) => info!("team {} wins", team),
+                MatchResult::Draw => info!("the match is a draw"),
+            }
+            state.result = Some(result);
+        }
+    }
+}
+
+// where a flag lying on a tile is drawn, in the middle of the tile like an object
+fn ground_translation(x: usize, y: usize, elevation: f32) -> Vector3<f32> {
+    let object = MapObject { kind: ObjectKind::FlagStand, x, y, slot: ObjectSlot::C, team: None };
+    let (world_x, world_y, world_z) = object_translation(&object, elevation);
+    Vector3::new(world_x, world_y, world_z)
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::ecs::{Builder, RunNow, World, WorldExt};
+
+    use super::*;
+
+    struct Match {
+        world: World,
+        system: CaptureFlagSystem,
+    }
+
+    impl Match {
+        // two teams with their bases at either end of a row of tiles
+        fn new(score_limit: usize) -> Match {
+            let mut world = World::new();
+            let mut system = CaptureFlagSystem;
+            System::setup(&mut system, &mut world);
+            world.insert(MatchRules { score_limit: Some(score_limit), time_limit: None, units_per_team: 1 });
+            for (team, x) in [(0, 0), (1, 5)].iter() {
+                world.create_entity()
+                    .with(Base { team: *team, x: *x, y: 0 })
+                    .with(Transform::default())
+                    .build();
+                world.create_entity()
+                    .with(Flag::new(*team, (*x, 0)))
+                    .with(Transform::default())
+                    .build();
+            }
+            Match { world, system }
+        }
+
+        fn unit(&mut self, team: usize, x: usize) -> Entity {
+            self.world.create_entity()
+                .with(Unit::new(team, 1., 10., x, 0))
+                .with(Transform::default())
+                .build()
+        }
+
+        fn walk(&mut self, unit: Entity, x: usize) {
+            self.world.write_storage::<Unit>().get_mut(unit).unwrap().x = x;
+            self.step();
+        }
+
+        fn step(&mut self) {
+            self.system.run_now(&self.world);
+            self.world.maintain();
+        }
+
+        // the team's flag, with its entity
+        fn flag(&self, team: usize) -> (Entity, usize, Option<Entity>, bool) {
+            let entities = self.world.entities();
+            let flags = self.world.read_storage::<Flag>();
+            (&entities, &flags).join()
+                .find(|(_, flag)| flag.team == team)
+                .map(|(entity, flag)| (entity, flag.x, flag.carrier, flag.is_home()))
+                .unwrap()
+        }
+
+        fn translation(&self, entity: Entity) -> Vector3<f32> {
+            *self.world.read_storage::<Transform>().get(entity).unwrap().translation()
+        }
+
+        fn state(&self) -> MatchState {
+            self.world.read_resource::<MatchState>().clone()
+        }
+    }
+
+    #[test]
+    fn flags_are_picked_up_dropped_returned_and_captured() {
+        let mut game = Match::new(2);
+        let raider = game.unit(0, 1);
+        let defender = game.unit(1, 4);
+        game.step();
+        assert!(game.flag(0).3 && game.flag(1).3);
+
+        // walking onto the enemy flag picks it up, and it's carried along
+        game.walk(raider, 5);
+        assert_eq!(game.flag(1).2, Some(raider));
+        game.walk(raider, 3);
+        let (flag, x, carrier, _) = game.flag(1);
+        assert_eq!((x, carrier), (3, Some(raider)));
+        let carried = game.translation(flag);
+        assert!(carried.y > game.translation(raider).y);
+
+        // the flag is dropped on the ground when its carrier dies
+        game.world.write_storage::<Unit>().get_mut(raider).unwrap().health = 0.;
+        game.step();
+        assert_eq!(game.flag(1), (flag, 3, None, false));
+        assert_eq!(game.translation(flag), ground_translation(3, 0, 0.));
+        assert!(game.translation(flag).y < carried.y);
+
+        // the enemy can't pick its own flag up, but returns it by touching it
+        game.walk(defender, 3);
+        assert_eq!(game.flag(1), (flag, 5, None, true));
+        assert_eq!(game.state().score(0), 0);
+
+        // bringing the flag home scores while the team's own flag is home
+        let runner = game.unit(0, 5);
+        game.step();
+        assert_eq!(game.flag(1).2, Some(runner));
+        game.walk(runner, 0);
+        assert_eq!(game.flag(1), (flag, 5, None, true));
+        assert_eq!(game.state().score(0), 1);
+        assert_eq!(game.state().result, None);
+
+        // not while the team's own flag is taken
+        game.walk(runner, 5);
+        game.walk(defender, 0);
+        assert_eq!(game.flag(0).2, Some(defender));
+        game.walk(defender, 2);
+        game.walk(runner, 0);
+        assert_eq!(game.state().score(0), 1);
+        assert_eq!(game.flag(1).2, Some(runner));
+
+        // once it's returned, the waiting carrier scores and reaches the score limit
+        game.world.write_storage::<Unit>().get_mut(defender).unwrap().health = 0.;
+        game.step();
+        assert_eq!(game.flag(0).1, 2);
+        assert_eq!(game.state().score(0), 1);
+        let guard = game.unit(0, 1);
+        game.walk(guard, 2);
+        assert!(game.flag(0).3);
+        assert_eq!(game.state().score(0), 2);
+        assert_eq!(game.state().result, Some(MatchResult::Win(0)));
+
+        // nothing changes once the match is over
+        game.walk(runner, 5);
+        assert!(game.flag(1).3);
+    }
+}
\ No newline at end of file
@@ -1,31 +1,48 @@
 use amethyst::{
-    core::{math::{Point3, Vector2, Vector3}, Transform, Time},
+    core::Transform,
     ecs::*,
     input::{InputHandler, StringBindings},
     renderer::{camera::Camera, SpriteRender},
     window::ScreenDimensions,
 };
-use std::collections::HashMap;
+use std::collections::HashSet;
 use crate::{
-    components::{Tile, TileUIElement, TileUIElementType},
-    enitities::{create_tile_ui},
-    resources::{CameraHandle, MapDimensions, TerrainSet, TerrainSprites, TileMap, UISprites},
-    util::mouse_to_map_iso,
+    components::{Flag, MapObject, Slope, TerrainTile, Tile, TileUIElement, TileUIElementType},
+    enitities::{create_object, create_region_ui, create_tile_ui, object_sprite, update_flags},
+    resources::{Brush, CameraHandle, Clipboard, EditCommand, EditorConfig, EditorHistory, EditorMode, EditorPanel, Map, MapDimensions, Selection, TerrainSet, TerrainSprites, TileEdit, TileMap, TileRect, UISprites, NEIGHBOUR_OFFSETS},
+    util::{flood_fill, infer_slope, mouse_to_map_iso, object_translation, rect_area, tile_exists, tile_translation, tile_ui_translation},
 };
 
-pub struct EditorTileSystem;
+#[derive(Default)]
+pub struct EditorTileSystem {
+    // tiles already changed by the current stroke, so holding the mouse down doesn't repeat an edit
+    stroke: HashSet<(usize, usize)>,
+    // the corner a rectangle fill or selection was started from
+    drag_start: Option<(usize, usize)>,
+}
 
 impl<'s> System<'s> for EditorTileSystem {
     type SystemData = (
         Entities<'s>,
         WriteStorage<'s, TileUIElement>,
+        ReadStorage<'s, MapObject>,
+        ReadStorage<'s, Flag>,
         WriteStorage<'s, Tile>,
-        ReadStorage<'s, Transform>,
+        WriteStorage<'s, SpriteRender>,
+        WriteStorage<'s, Transform>,
         ReadStorage<'s, Camera>,
         Read<'s, InputHandler<StringBindings>>,
         Read<'s, MapDimensions>,
+        Read<'s, Brush>,
+        Read<'s, EditorMode>,
+        Read<'s, EditorConfig>,
+        Write<'s, EditorHistory>,
+        Write<'s, Selection>,
+        Write<'s, Clipboard>,
+        Read<'s, EditorPanel>,
         ReadExpect<'s, CameraHandle>,
         ReadExpect<'s, LazyUpdate>,
+        ReadExpect<'s, Map>,
         ReadExpect<'s, ScreenDimensions>,
         ReadExpect<'s, TerrainSet>,
         ReadExpect<'s, TerrainSprites>,
@@ -36,51 +53,452 @@ impl<'s> System<'s> for EditorTileSystem {
     fn run(&mut self, (
         entities,
         mut tiles_ui,
+        objects,
+        flags,
         mut tiles,
-        transforms,
+        mut sprites,
+        mut transforms,
         cameras,
         input_handler,
         map_dimensions,
+        brush,
+        editor_mode,
+        editor_config,
+        mut history,
+        mut selection,
+        mut clipboard,
+        editor_panel,
         camera_handle,
         lazy_update,
+        map,
         screen_dimensions,
         terrain_set,
         terrain_sprites,
         tile_map,
         ui_sprites,
     ): Self::SystemData) {
+        // alt clicking selects units instead of painting
+        let painting = input_handler.action_is_down("EditorPaint").unwrap_or(false)
+            && !input_handler.action_is_down("SelectUnit").unwrap_or(false);
+        if !painting {
+            self.stroke.clear();
+            history.end_group();
+        }
         if let Some((xf, yf)) = input_handler.mouse_position() {
-            // todo: ignore if inside editor panel or if in menu
-            // translate the mouse coordinates to map coordinates
-            let camera_transform = transforms.get(camera_handle.camera).unwrap();
+            // todo: ignore if in menu
+            // translate the mouse coordinates to map coordinates, unless the mouse is over the editor panel
+            let camera_transform = transforms.get(camera_handle.camera).unwrap().clone();
             let camera = cameras.get(camera_handle.camera).unwrap();
-            if let Some((map_x, map_y)) = mouse_to_map_iso(xf, yf, &map_dimensions,&screen_dimensions, camera, camera_transform, &tile_map.clone(), &tiles) {
-                // todo: this is where more tiles can be highlighted
-                let tile_ui_need: Vec<(usize, usize)> = vec![(map_x, map_y)];
+            let map_coords = if editor_panel.contains(xf) {
+                None
+            } else {
+                mouse_to_map_iso(xf, yf, &map_dimensions,&screen_dimensions, camera, &camera_transform, &tile_map.clone(), &tiles)
+            };
+            if let Some((map_x, map_y)) = map_coords {
+                // the tiles edited this frame
+                let mut changed: Vec<(usize, usize)> = vec![];
+                // a dragged out box is filled when the mouse is released
+                if let (EditorMode::RectFill(terrain), Some(start), false) = (&*editor_mode, self.drag_start, painting) {
+                    let area = rect_area(start, (map_x, map_y));
+                    let edits = paint_tiles(&area, terrain, map.seed, map_dimensions.width, &tile_map, &mut tiles, &mut sprites, &mut transforms, &terrain_sprites);
+                    changed.extend(edits.iter().map(|edit| (edit.x, edit.y)));
+                    history.push_tiles(edits);
+                }
+                if let (EditorMode::Select, Some(start), false) = (&*editor_mode, self.drag_start, painting) {
+                    selection.rect = Some(TileRect::from_corners(start, (map_x, map_y)));
+                }
+                if !painting {
+                    self.drag_start = None;
+                }
+                // edit the tiles under the brush with the current tool
+                let targets = match (&*editor_mode, self.drag_start) {
+                    (EditorMode::RectFill(_), Some(start))
+                    | (EditorMode::Select, Some(start)) => rect_area(start, (map_x, map_y)),
+                    (EditorMode::Select, None) => match selection.rect {
+                        Some(rect) => rect.tiles(),
+                        _ => vec![(map_x, map_y)],
+                    },
+                    (EditorMode::Paste, _) => match &clipboard.stamp {
+                        Some(stamp) => stamp.footprint(map_x, map_y, map_dimensions.width, map_dimensions.height),
+                        _ => vec![(map_x, map_y)],
+                    },
+                    _ => brush.footprint(map_x, map_y, &map_dimensions),
+                };
+                if painting {
+                    match &*editor_mode {
+                        EditorMode::Fill { terrain, match_elevation } => {
+                            // only fill once per click
+                            if self.stroke.is_empty() {
+                                self.stroke.insert((map_x, map_y));
+                                let start = tiles.get(tile_map.get(map_x, map_y, map_dimensions.width))
+                                    .map(|tile| (tile.terrain, tile.elevation));
+                                if let Some((start_terrain, start_elevation)) = start {
+                                    if start_terrain != terrain.char_code || *match_elevation {
+                                        let area = flood_fill(map_x, map_y, map_dimensions.width, map_dimensions.height, |x, y| {
+                                            match tiles.get(tile_map.get(x, y, map_dimensions.width)) {
+                                                Some(tile) => tile.terrain == start_terrain
+                                                    && (!*match_elevation || tile.elevation == start_elevation),
+                                                _ => false,
+                                            }
+                                        });
+                                        let edits = paint_tiles(&area, terrain, map.seed, map_dimensions.width, &tile_map, &mut tiles, &mut sprites, &mut transforms, &terrain_sprites);
+                                        changed.extend(edits.iter().map(|edit| (edit.x, edit.y)));
+                                        history.push_tiles(edits);
+                                    }
+                                }
+                            }
+                        },
+                        EditorMode::Object { kind, slot, team } => {
+                            // only place or remove once per click
+                            if self.stroke.is_empty() {
+                                self.stroke.insert((map_x, map_y));
+                                let placed = MapObject {
+                                    kind: *kind,
+                                    x: map_x,
+                                    y: map_y,
+                                    slot: *slot,
+                                    team: if kind.has_team() { Some(*team) } else { None },
+                                };
+                                // an object replaces whatever is in its slot
+                                let mut removed = vec![];
+                                for (entity, object) in (&*entities, &objects).join() {
+                                    if (object.x, object.y, object.slot) == (map_x, map_y, placed.slot) {
+                                        entities.delete(entity).unwrap();
+                                        removed.push(object.clone());
+                                    }
+                                }
+                                // clicking an object with the same object removes it
+                                let added = if removed.contains(&placed) {
+                                    vec![]
+                                } else if let Some(tile) = tiles.get(tile_map.get(map_x, map_y, map_dimensions.width)) {
+                                    let sprite = object_sprite(placed.kind, &ui_sprites.set, &terrain_sprites.set);
+                                    create_object(&entities, placed.clone(), sprite, tile.surface_elevation(), &lazy_update);
+                                    vec![placed]
+                                } else {
+                                    vec![]
+                                };
+                                // teams gain or lose their flag with their flag stands
+                                let placed: Vec<(&MapObject, f32)> = (&objects).join()
+                                    .filter(|object| !removed.contains(object))
+                                    .chain(added.iter())
+                                    .filter_map(|object| tiles.get(tile_map.get(object.x, object.y, map_dimensions.width))
+                                        .map(|tile| (object, tile.surface_elevation())))
+                                    .collect();
+                                update_flags(&entities, &flags, &placed, &ui_sprites.set, &lazy_update);
+                                history.push(EditCommand::Objects { added, removed });
+                            }
+                        },
+                        EditorMode::Paste => {
+                            // only paste once per click, the editor state does the pasting
+                            if self.stroke.is_empty() {
+                                self.stroke.insert((map_x, map_y));
+                                clipboard.paste_at = Some((map_x, map_y));
+                            }
+                        },
+                        EditorMode::RectFill(_) | EditorMode::Select => {
+                            if self.drag_start.is_none() {
+                                self.drag_start = Some((map_x, map_y));
+                            }
+                        },
+                        _ => {
+                            // a whole stroke is undone in one step
+                            history.begin_group();
+                            for (x, y) in targets.iter() {
+                                let entity = tile_map.get(*x, *y, map_dimensions.width);
+                                let before = match tiles.get(entity) {
+                                    Some(tile) => tile.clone(),
+                                    _ => continue,
+                                };
+                                let edited = match &*editor_mode {
+                                    EditorMode::Terrain(terrain) if before.terrain != terrain.char_code => {
+                                        Some(repaint(terrain, &before, map.seed))
+                                    },
+                                    EditorMode::Region(region) if before.region != *region => {
+                                        let mut marked = before.clone();
+                                        marked.region = *region;
+                                        Some(marked)
+                                    },
+                                    EditorMode::Raise if !self.stroke.contains(&(*x, *y))
+                                        && before.elevation < editor_config.max_elevation => {
+                                        let mut raised = before.clone();
+                                        raised.elevation += 1;
+                                        Some(raised)
+                                    },
+                                    EditorMode::Lower if !self.stroke.contains(&(*x, *y))
+                                        && before.elevation > editor_config.min_elevation => {
+                                        let mut lowered = before.clone();
+                                        lowered.elevation -= 1;
+                                        Some(lowered)
+                                    },
+                                    EditorMode::Slope if !self.stroke.contains(&(*x, *y)) => {
+                                        let neighbours = neighbour_elevations(*x, *y, &map_dimensions, &tile_map, &tiles);
+                                        resloped(&before, neighbours, &terrain_set)
+                                    },
+                                    _ => None,
+                                };
+                                if let Some(edited) = edited {
+                                    history.record(TileEdit { x: *x, y: *y, before, after: edited.clone() });
+                                    changed.push((*x, *y));
+                                    set_tile(entity, edited, &mut tiles, &mut sprites, &mut transforms, &terrain_sprites);
+                                    self.stroke.insert((*x, *y));
+                                }
+                            }
+                            // changing elevation can make or break ramps around the edited tiles
+                            let elevation_tool = match &*editor_mode {
+                                EditorMode::Raise | EditorMode::Lower => true,
+                                _ => false,
+                            };
+                            if map.auto_slopes && elevation_tool {
+                                let mut affected: Vec<(usize, usize)> = vec![];
+                                for (x, y) in targets.iter() {
+                                    for (dx, dy) in [(0, 0), (-1, 0), (0, -1), (1, 0), (0, 1)].iter() {
+                                        let (nx, ny) = (*x as isize + dx, *y as isize + dy);
+                                        let point = (nx as usize, ny as usize);
+                                        if tile_exists(nx, ny, map_dimensions.width as isize, map_dimensions.height as isize)
+                                            && !affected.contains(&point) {
+                                            affected.push(point);
+                                        }
+                                    }
+                                }
+                                for (x, y) in affected.into_iter() {
+                                    let entity = tile_map.get(x, y, map_dimensions.width);
+                                    let before = match tiles.get(entity) {
+                                        Some(tile) => tile.clone(),
+                                        _ => continue,
+                                    };
+                                    let neighbours = neighbour_elevations(x, y, &map_dimensions, &tile_map, &tiles);
+                                    if let Some(sloped) = resloped(&before, neighbours, &terrain_set) {
+                                        history.record(TileEdit { x, y, before, after: sloped.clone() });
+                                        changed.push((x, y));
+                                        set_tile(entity, sloped, &mut tiles, &mut sprites, &mut transforms, &terrain_sprites);
+                                    }
+                                }
+                            }
+                        },
+                    }
+                }
+                // edges between terrain depend on the neighbouring tiles, so they're picked after editing
+                refresh_sprites(&changed, &map_dimensions, &tile_map, &terrain_set, &mut tiles, &mut sprites, &terrain_sprites);
+                refresh_regions(&changed, &entities, &tiles_ui, &tiles, &map_dimensions, &tile_map, &ui_sprites, &lazy_update);
+                // keep objects on top of their tiles when the elevation changes
+                if !changed.is_empty() {
+                    for (object, transform) in (&objects, &mut transforms).join() {
+                        if !changed.contains(&(object.x, object.y)) {
+                            continue;
+                        }
+                        if let Some(tile) = tiles.get(tile_map.get(object.x, object.y, map_dimensions.width)) {
+                            let (world_x, world_y, world_z) = object_translation(object, tile.surface_elevation());
+                            transform.set_translation_xyz(world_x, world_y, world_z);
+                        }
+                    }
+                }
+                // highlight every tile under the brush, or in the box being dragged out
+                let tile_ui_need: HashSet<(usize, usize)> = targets.into_iter().collect();
                 // go through all ui_tiles, if they aren't needed, remove them, if they are, add them to "found"
-                let mut tile_ui_found: Vec<(usize, usize)> = vec![];
+                let mut tile_ui_found: HashSet<(usize, usize)> = HashSet::new();
                 for (entity, tile_ui) in (&*entities, &mut tiles_ui).join() {
-                    if tile_ui.tile_x != map_x || tile_ui.tile_y != map_y {
+                    if tile_ui.el_type != TileUIElementType::EditorMouseOver {
+                        continue;
+                    }
+                    if !tile_ui_need.contains(&(tile_ui.tile_x, tile_ui.tile_y)) {
                         entities.delete(entity);
                     } else {
-                        tile_ui_found.push((tile_ui.tile_x, tile_ui.tile_y));
+                        tile_ui_found.insert((tile_ui.tile_x, tile_ui.tile_y));
                     }
                 }
                 for (x, y) in tile_ui_need.iter() {
                     // unless the ui_tile already exists in found ...
-                    if !(tile_ui_found.iter().any(|(ix, iy)| ix == x && iy == y)) {
+                    if !tile_ui_found.contains(&(*x, *y)) {
                         let parent = tile_map.get(*x, *y, map_dimensions.width);
                         if let Some(tile) = tiles.get(parent) {
-                            create_tile_ui(&entities, ui_sprites.set[0].clone(), tile.height, *x, *y, tile.elevation, TileUIElementType::EditorMouseOver, &lazy_update);
+                            create_tile_ui(&entities, ui_sprites.set[0].clone(), tile.height, *x, *y, tile.surface_elevation(), TileUIElementType::EditorMouseOver, &lazy_update);
                         }
                     }
                 }
             } else {
                 // if mouse is outside the map
-                for (entity, _) in (&*entities, &mut tiles_ui).join() {
-                    entities.delete(entity);
+                if !painting {
+                    self.drag_start = None;
+                }
+                for (entity, tile_ui) in (&*entities, &mut tiles_ui).join() {
+                    if tile_ui.el_type == TileUIElementType::EditorMouseOver {
+                        entities.delete(entity);
+                    }
                 }
             }
         };
+        // keep ui elements on top of their tiles when the elevation changes
+        for (tile_ui, transform) in (&tiles_ui, &mut transforms).join() {
+            if let Some(tile) = tiles.get(tile_map.get(tile_ui.tile_x, tile_ui.tile_y, map_dimensions.width)) {
+                let (world_x, world_y, world_z) = tile_ui_translation(tile.x, tile.y, tile.surface_elevation());
+                transform.set_translation_xyz(world_x, world_y, world_z);
+            }
+        }
+    }
+}
+
+// finds the elevations of the tiles next to (x,y), in the order NW, NE, SE, SW
+pub fn neighbour_elevations(x: usize, y: usize, map_dimensions: &MapDimensions, tile_map: &TileMap, tiles: &WriteStorage<Tile>) -> [Option<usize>; 4] {
+    let (w, h) = (map_dimensions.width as isize, map_dimensions.height as isize);
+    let mut elevations = [None; 4];
+    for (i, (dx, dy)) in [(-1, 0), (0, -1), (1, 0), (0, 1)].iter().enumerate() {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if tile_exists(nx, ny, w, h) {
+            elevations[i] = tiles.get(tile_map.get(nx as usize, ny as usize, map_dimensions.width)).map(|t| t.elevation);
+        }
+    }
+    elevations
+}
+
+// finds the terrain of the tiles around (x,y), in the order of NEIGHBOUR_OFFSETS
+pub fn neighbour_terrains(x: usize, y: usize, map_dimensions: &MapDimensions, tile_map: &TileMap, tiles: &WriteStorage<Tile>) -> [Option<char>; 8] {
+    let (w, h) = (map_dimensions.width as isize, map_dimensions.height as isize);
+    let mut terrains = [None; 8];
+    for (i, (dx, dy)) in NEIGHBOUR_OFFSETS.iter().enumerate() {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if tile_exists(nx, ny, w, h) {
+            terrains[i] = tiles.get(tile_map.get(nx as usize, ny as usize, map_dimensions.width)).map(|t| t.terrain);
+        }
+    }
+    terrains
+}
+
+// picks the sprites of edited tiles and their neighbours again
+pub fn refresh_sprites(
+    points: &[(usize, usize)],
+    map_dimensions: &MapDimensions,
+    tile_map: &TileMap,
+    terrain_set: &TerrainSet,
+    tiles: &mut WriteStorage<Tile>,
+    sprites: &mut WriteStorage<SpriteRender>,
+    terrain_sprites: &TerrainSprites,
+) {
+    let (w, h) = (map_dimensions.width as isize, map_dimensions.height as isize);
+    let mut refreshed: HashSet<(usize, usize)> = HashSet::new();
+    for (x, y) in points.iter() {
+        for (dx, dy) in [(0, 0)].iter().chain(NEIGHBOUR_OFFSETS.iter()) {
+            let (nx, ny) = (*x as isize + dx, *y as isize + dy);
+            if !tile_exists(nx, ny, w, h) || !refreshed.insert((nx as usize, ny as usize)) {
+                continue;
+            }
+            let (nx, ny) = (nx as usize, ny as usize);
+            let neighbours = neighbour_terrains(nx, ny, map_dimensions, tile_map, tiles);
+            let entity = tile_map.get(nx, ny, map_dimensions.width);
+            if let Some(tile) = tiles.get_mut(entity) {
+                let sprite_index = terrain_set.tile_sprite(tile, &neighbours);
+                if sprite_index != tile.sprite_index {
+                    tile.sprite_index = sprite_index;
+                    if let (Some(sprite), Some(new_sprite)) = (sprites.get_mut(entity), terrain_sprites.set.get(sprite_index)) {
+                        *sprite = new_sprite.clone();
+                    }
+                }
+            }
+        }
+    }
+}
+
+// replaces the region overlays of edited tiles
+#[allow(clippy::too_many_arguments)]
+pub fn refresh_regions(
+    points: &[(usize, usize)],
+    entities: &Entities,
+    tiles_ui: &WriteStorage<TileUIElement>,
+    tiles: &WriteStorage<Tile>,
+    map_dimensions: &MapDimensions,
+    tile_map: &TileMap,
+    ui_sprites: &UISprites,
+    lazy_update: &ReadExpect<LazyUpdate>,
+) {
+    if points.is_empty() {
+        return;
+    }
+    let points: HashSet<(usize, usize)> = points.iter().cloned().collect();
+    for (entity, tile_ui) in (&**entities, tiles_ui).join() {
+        if let TileUIElementType::Region(_) = tile_ui.el_type {
+            if points.contains(&(tile_ui.tile_x, tile_ui.tile_y)) {
+                entities.delete(entity).unwrap();
+            }
+        }
+    }
+    for (x, y) in points.into_iter() {
+        if let Some(tile) = tiles.get(tile_map.get(x, y, map_dimensions.width)) {
+            if let Some(region) = tile.region {
+                create_region_ui(entities, ui_sprites.set[0].clone(), x, y, tile.surface_elevation(), region, lazy_update);
+            }
+        }
+    }
+}
+
+// works out the slope of a tile from its neighbours, returning the changed tile if the slope changed
+// terrain that can't ramp is left alone
+pub fn resloped(tile: &Tile, neighbours: [Option<usize>; 4], terrain_set: &TerrainSet) -> Option<Tile> {
+    let terrain = terrain_set.terrain(tile.terrain)?;
+    let slope = infer_slope(tile.elevation, neighbours);
+    if !terrain.ramps || slope == tile.slope {
+        return None;
+    }
+    let mut sloped = tile.clone();
+    sloped.slope = slope;
+    sloped.sprite_index = terrain.sprite_for(sloped.variant, slope);
+    Some(sloped)
+}
+
+// paints tiles with a terrain, keeping their elevation and region, and returns the edits made
+#[allow(clippy::too_many_arguments)]
+fn paint_tiles(
+    points: &[(usize, usize)],
+    terrain: &TerrainTile,
+    seed: u64,
+    width: usize,
+    tile_map: &TileMap,
+    tiles: &mut WriteStorage<Tile>,
+    sprites: &mut WriteStorage<SpriteRender>,
+    transforms: &mut WriteStorage<Transform>,
+    terrain_sprites: &TerrainSprites,
+) -> Vec<TileEdit> {
+    let mut edits = Vec::with_capacity(points.len());
+    for (x, y) in points.iter() {
+        let entity = tile_map.get(*x, *y, width);
+        let before = match tiles.get(entity) {
+            Some(tile) => tile.clone(),
+            _ => continue,
+        };
+        let painted = repaint(terrain, &before, seed);
+        edits.push(TileEdit { x: *x, y: *y, before, after: painted.clone() });
+        set_tile(entity, painted, tiles, sprites, transforms, terrain_sprites);
+    }
+    edits
+}
+
+// a tile of another terrain in place of before, keeping its elevation, region and slope
+// the slope is only kept if the new terrain can be a ramp
+fn repaint(terrain: &TerrainTile, before: &Tile, seed: u64) -> Tile {
+    let mut painted = terrain.create_tile(before.x, before.y, before.elevation, seed);
+    if before.slope != Slope::None && terrain.ramps {
+        painted.slope = before.slope;
+        painted.sprite_index = terrain.sprite_for(painted.variant, before.slope);
+    }
+    painted.region = before.region;
+    painted
+}
+
+// replaces the tile of a tile entity, and moves and re-skins the entity to match
+pub fn set_tile(
+    entity: Entity,
+    tile: Tile,
+    tiles: &mut WriteStorage<Tile>,
+    sprites: &mut WriteStorage<SpriteRender>,
+    transforms: &mut WriteStorage<Transform>,
+    terrain_sprites: &TerrainSprites,
+) {
+    if let Some(transform) = transforms.get_mut(entity) {
+        let (world_x, world_y, world_z) = tile_translation(&tile);
+        transform.set_translation_xyz(world_x, world_y, world_z);
+    }
+    if let (Some(sprite), Some(new_sprite)) = (sprites.get_mut(entity), terrain_sprites.set.get(tile.sprite_index)) {
+        *sprite = new_sprite.clone();
+    }
+    if let Some(old_tile) = tiles.get_mut(entity) {
+        *old_tile = tile;
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,9 @@
 mod camera;
+mod ctf;
 mod editor;
+mod unit;
 
 pub use self::camera::{CameraSystem, WindowResizeSystem};
-pub use self::editor::EditorTileSystem;
\ No newline at end of file
+pub use self::ctf::CaptureFlagSystem;
+pub use self::editor::{refresh_regions, refresh_sprites, set_tile, EditorTileSystem};
+pub use self::unit::{unit_position, UnitMovementSystem, UnitSelectSystem};
\ No newline at end of file
@@ -0,0 +1,192 @@
+use amethyst::{
+    core::{Time, Transform},
+    ecs::*,
+    input::{InputHandler, StringBindings},
+    renderer::{camera::Camera, palette::Srgba, resources::Tint},
+    window::ScreenDimensions,
+};
+use std::f32::consts::SQRT_2;
+use crate::{
+    components::{MapObject, Selected, Tile, Unit},
+    resources::{CameraHandle, EditorPanel, MapDimensions, TerrainSet, TileMap},
+    util::{find_path, mouse_to_map_iso, team_colour, unit_translation, NavGrid, Neighbourhood, PathOptions},
+};
+
+// moves units along their paths
+pub struct UnitMovementSystem;
+
+impl<'s> System<'s> for UnitMovementSystem {
+    type SystemData = (
+        WriteStorage<'s, Unit>,
+        WriteStorage<'s, Transform>,
+        ReadStorage<'s, Tile>,
+        Read<'s, MapDimensions>,
+        Read<'s, Time>,
+        ReadExpect<'s, TerrainSet>,
+        ReadExpect<'s, TileMap>,
+    );
+
+    fn run(&mut self, (mut units, mut transforms, tiles, map_dimensions, time, terrain_set, tile_map): Self::SystemData) {
+        let delta_time = time.delta_seconds();
+        let width = map_dimensions.width;
+        for (unit, transform) in (&mut units, &mut transforms).join() {
+            if !unit.is_alive() {
+                continue;
+            }
+            let next = match unit.path.front() {
+                Some(next) => *next,
+                _ => continue,
+            };
+            let (from, to) = match (tiles.get(tile_map.get(unit.x, unit.y, width)), tiles.get(tile_map.get(next.0, next.1, width))) {
+                (Some(from), Some(to)) => (from, to),
+                _ => {
+                    unit.path.clear();
+                    continue;
+                },
+            };
+            // the map can change under a unit after its path was found
+            if !terrain_set.is_passable(to) {
+                unit.path.clear();
+                unit.progress = 0.;
+            } else {
+                // units cross the first half of a step at the speed of the tile they're leaving
+                let modifier = if unit.progress < 0.5 {
+                    terrain_set.speed_modifier(from)
+                } else {
+                    terrain_set.speed_modifier(to)
+                };
+                let length = if unit.x != next.0 && unit.y != next.1 { SQRT_2 } else { 1. };
+                unit.progress += unit.speed * modifier * delta_time / length;
+                if unit.progress >= 1. {
+                    unit.x = next.0;
+                    unit.y = next.1;
+                    unit.path.pop_front();
+                    unit.progress = if unit.is_moving() { unit.progress - 1. } else { 0. };
+                }
+            }
+            if let Some((map_x, map_y, elevation)) = unit_position(unit, &tiles, &tile_map, width) {
+                let (world_x, world_y, world_z) = unit_translation(map_x, map_y, elevation);
+                transform.set_translation_xyz(world_x, world_y, world_z);
+            }
+        }
+    }
+}
+
+// finds where a unit is on the map, between its tile and the next one on its path
+pub fn unit_position(unit: &Unit, tiles: &ReadStorage<Tile>, tile_map: &TileMap, width: usize) -> Option<(f32, f32, f32)> {
+    let from = tiles.get(tile_map.get(unit.x, unit.y, width))?;
+    let (x, y, elevation) = (unit.x as f32, unit.y as f32, from.surface_elevation());
+    match unit.path.front().and_then(|(nx, ny)| tiles.get(tile_map.get(*nx, *ny, width))) {
+        Some(to) => {
+            let t = unit.progress;
+            Some((
+                x + (to.x as f32 - x) * t,
+                y + (to.y as f32 - y) * t,
+                elevation + (to.surface_elevation() - elevation) * t,
+            ))
+        },
+        _ => Some((x, y, elevation)),
+    }
+}
+
+// selects units with the mouse, and orders the selected units to move
+#[derive(Default)]
+pub struct UnitSelectSystem {
+    // whether the buttons were down last frame, so holding them only acts once
+    selecting: bool,
+    ordering: bool,
+}
+
+impl<'s> System<'s> for UnitSelectSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, Unit>,
+        WriteStorage<'s, Selected>,
+        WriteStorage<'s, Tint>,
+        ReadStorage<'s, MapObject>,
+        ReadStorage<'s, Tile>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Transform>,
+        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, MapDimensions>,
+        Read<'s, EditorPanel>,
+        ReadExpect<'s, CameraHandle>,
+        ReadExpect<'s, ScreenDimensions>,
+        ReadExpect<'s, TerrainSet>,
+        ReadExpect<'s, TileMap>,
+    );
+
+    fn run(&mut self, (
+        entities,
+        mut units,
+        mut selected,
+        mut tints,
+        objects,
+        tiles,
+        cameras,
+        transforms,
+        input_handler,
+        map_dimensions,
+        editor_panel,
+        camera_handle,
+        screen_dimensions,
+        terrain_set,
+        tile_map,
+    ): Self::SystemData) {
+        let selecting = input_handler.action_is_down("SelectUnit").unwrap_or(false);
+        let ordering = input_handler.action_is_down("MoveUnit").unwrap_or(false);
+        let select_pressed = selecting && !self.selecting;
+        let order_pressed = ordering && !self.ordering;
+        self.selecting = selecting;
+        self.ordering = ordering;
+        if !select_pressed && !order_pressed {
+            return;
+        }
+        let map_coords = match input_handler.mouse_position() {
+            Some((xf, yf)) if !editor_panel.contains(xf) => {
+                let camera_transform = transforms.get(camera_handle.camera).unwrap();
+                let camera = cameras.get(camera_handle.camera).unwrap();
+                mouse_to_map_iso(xf, yf, &map_dimensions, &screen_dimensions, camera, camera_transform, &tile_map, &tiles)
+            },
+            _ => None,
+        };
+        let (map_x, map_y) = match map_coords {
+            Some(coords) => coords,
+            _ => return,
+        };
+        if select_pressed {
+            // clicking a tile selects the units on it, instead of the ones selected before
+            for (entity, unit, _) in (&entities, &units, &selected).join() {
+                let [r, g, b, a] = team_colour(unit.team);
+                tints.insert(entity, Tint(Srgba::new(r, g, b, a))).unwrap();
+            }
+            selected.clear();
+            for (entity, unit) in (&entities, &units).join() {
+                if unit.is_alive() && (unit.x, unit.y) == (map_x, map_y) {
+                    selected.insert(entity, Selected).unwrap();
+                    tints.insert(entity, Tint(Srgba::new(1., 1., 0.6, 1.))).unwrap();
+                }
+            }
+        }
+        if order_pressed && (&selected).join().next().is_some() {
+            let mut grid = NavGrid::from_tile_map(&map_dimensions, &tile_map, &tiles, &terrain_set);
+            for object in objects.join().filter(|object| object.kind.blocks_movement()) {
+                grid.block(object.x, object.y);
+            }
+            let options = PathOptions {
+                neighbourhood: Neighbourhood::Eight,
+                ..PathOptions::default()
+            };
+            for (unit, _) in (&mut units, &selected).join() {
+                // a unit partway through a step finishes it before turning
+                let moving = unit.is_moving();
+                let start = unit.path.front().cloned().unwrap_or((unit.x, unit.y));
+                if let Some(path) = find_path(&grid, start, (map_x, map_y), &options) {
+                    unit.path = path.into_iter()
+                        .skip(if moving { 0 } else { 1 })
+                        .collect();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use serde::{Serialize, Deserialize};
+use std::{error::Error, fmt};
+
+use crate::components::{MapObject, ObjectKind, ObjectSlot, Region, TerrainTile};
+use crate::resources::{Map, MapDimensions, MapLoadError, TerrainSet, TileRecord, MAP_VERSION};
+use crate::util::{connected_areas, tile_hash, NavGrid, PathOptions};
+
+// the smallest map that fits every team's base
+pub const MIN_GENERATED_SIZE: usize = 16;
+
+// what kind of map to generate, saved in generated maps so they can be made again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorSettings {
+    pub width: usize,
+    pub height: usize,
+    // 2 teams get maps that look the same turned halfway around, 4 teams get maps mirrored both ways
+    pub teams: usize,
+    pub seed: u64,
+    pub max_elevation: usize,
+    // roughly how many tiles across hills and patches of terrain are
+    pub feature_size: f32,
+    // the rules the map has to be connected by
+    pub path_options: PathOptions,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> GeneratorSettings {
+        GeneratorSettings {
+            width: 32,
+            height: 32,
+            teams: 2,
+            seed: 0,
+            max_elevation: 4,
+            feature_size: 8.,
+            path_options: PathOptions::default(),
+        }
+    }
+}
+
+// errors that can occur while generating a map
+#[derive(Debug)]
+pub enum GenerateError {
+    // the map is too small for the teams' bases
+    Size { width: usize, height: usize },
+    // only 2 and 4 team maps can be made symmetric
+    Teams(usize),
+    // the terrain pack has nothing units can walk on
+    NoPassableTerrain,
+    // the generated tiles couldn't be built
+    Map(MapLoadError),
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenerateError::Size { width, height } =>
+                write!(f, "can't generate a {}x{} map, maps need to be at least {}x{}", width, height, MIN_GENERATED_SIZE, MIN_GENERATED_SIZE),
+            GenerateError::Teams(teams) =>
+                write!(f, "can't generate a map for {} teams, only 2 or 4", teams),
+            GenerateError::NoPassableTerrain =>
+                write!(f, "the terrain pack has no terrain units can walk on"),
+            GenerateError::Map(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for GenerateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GenerateError::Map(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MapLoadError> for GenerateError {
+    fn from(e: MapLoadError) -> GenerateError {
+        GenerateError::Map(e)
+    }
+}
+
+// how many layouts are tried, the last one with flat corridors that always join the bases up
+const ATTEMPTS: usize = 8;
+
+// generates a symmetric capture the flag map, with its tiles built
+// layouts from seeds made from the settings' seed are tried until one connects every base,
+// so the same settings always give the same map
+pub fn generate_map(terrain: &TerrainSet, terrain_file: &str, settings: &GeneratorSettings) -> Result<Map, GenerateError> {
+    let (w, h, teams) = (settings.width, settings.height, settings.teams);
+    if w < MIN_GENERATED_SIZE || h < MIN_GENERATED_SIZE {
+        return Err(GenerateError::Size { width: w, height: h });
+    }
+    if teams != 2 && teams != 4 {
+        return Err(GenerateError::Teams(teams));
+    }
+    let mut attempt = 0;
+    let mut map = loop {
+        let seed = if attempt == 0 { settings.seed } else { tile_hash(settings.seed, attempt, 0) };
+        let flat = attempt + 1 == ATTEMPTS;
+        let map = layout_map(terrain, terrain_file, settings, seed, flat)?;
+        if flat || is_connected(&map, terrain, &settings.path_options)? {
+            break map;
+        }
+        attempt += 1;
+    };
+    map.generator = Some(settings.clone());
+    Ok(map)
+}
+
+// lays out one map from noise, with flat corridors to the bases if flat is set
+fn layout_map(terrain: &TerrainSet, terrain_file: &str, settings: &GeneratorSettings, seed: u64, flat: bool) -> Result<Map, GenerateError> {
+    let (w, h, teams) = (settings.width, settings.height, settings.teams);
+    let passable: Vec<&TerrainTile> = terrain.tiles.iter().filter(|t| t.passable).collect();
+    let impassable: Vec<&TerrainTile> = terrain.tiles.iter().filter(|t| !t.passable).collect();
+    let ground = match passable.first() {
+        Some(ground) => ground.char_code,
+        _ => return Err(GenerateError::NoPassableTerrain),
+    };
+
+    // noise is sampled at the same spot for every copy of a tile, which makes the map symmetric
+    let mut records = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let (cx, cy) = canonical(x, y, w, h, teams);
+            let height_noise = stretch(fractal_noise(seed, cx as f32, cy as f32, settings.feature_size));
+            let elevation = ((height_noise * (settings.max_elevation + 1) as f32) as usize).min(settings.max_elevation);
+            let terrain_noise = fractal_noise(seed ^ 0x5EED, cx as f32, cy as f32, settings.feature_size * 0.75);
+            let code = if !impassable.is_empty() && terrain_noise > 0.7 {
+                impassable[(terrain_noise * 1000.) as usize % impassable.len()].char_code
+            } else {
+                passable[((stretch(terrain_noise) * passable.len() as f32) as usize).min(passable.len() - 1)].char_code
+            };
+            records.push(TileRecord::new(code, elevation));
+        }
+    }
+
+    // team 0's base is laid out in its corner, and copied to the other teams
+    let base = 2 + w.min(h) / 10;
+    let stand = (base, base);
+    let plaza = ((w - 1) / 2, (h - 1) / 2);
+    let plaza_elevation = records[plaza.1 * w + plaza.0].elevation;
+    // with flat corridors, everything the teams need is level with the middle of the map
+    let base_elevation = if flat { plaza_elevation } else { records[stand.1 * w + stand.0].elevation };
+    let mut objects = vec![];
+    for y in base - 2..=base + 4 {
+        for x in base - 2..=base + 2 {
+            let region = if y <= base + 1 && x + 1 >= base && x <= base + 1 && y + 1 >= base {
+                Some(Region::Base(0))
+            } else if y >= base + 2 && x + 1 >= base && x <= base + 1 {
+                Some(Region::Spawn(0))
+            } else {
+                None
+            };
+            set_symmetric(&mut records, x, y, w, h, teams, |record, team| {
+                record.terrain = ground;
+                record.elevation = base_elevation;
+                record.region = region.map(|region| match region {
+                    Region::Base(_) => Region::Base(team),
+                    Region::Spawn(_) => Region::Spawn(team),
+                });
+            });
+        }
+    }
+    for (team, (x, y)) in orbit(stand.0, stand.1, w, h, teams).into_iter().enumerate() {
+        records[y * w + x].objects.push(objects.len());
+        objects.push(MapObject { kind: ObjectKind::FlagStand, x, y, slot: ObjectSlot::C, team: Some(team) });
+    }
+
+    // a flat plaza in the middle, with a corridor to it from every base, keeps the bases connected
+    for y in (h - 1) / 2..=h / 2 {
+        for x in (w - 1) / 2..=w / 2 {
+            let record = &mut records[y * w + x];
+            record.terrain = ground;
+            record.elevation = plaza_elevation;
+        }
+    }
+    let corridor = corridor_path(plaza, stand);
+    for (i, (x, y)) in corridor.iter().enumerate() {
+        // the corridor slopes evenly from the plaza to the base
+        let along = i as f32 / (corridor.len() - 1).max(1) as f32;
+        let target = plaza_elevation as f32 + (base_elevation as f32 - plaza_elevation as f32) * along;
+        set_symmetric(&mut records, *x, *y, w, h, teams, |record, _| {
+            if terrain.terrain(record.terrain).map_or(true, |t| !t.passable) {
+                record.terrain = ground;
+            }
+            record.elevation = target.round() as usize;
+        });
+    }
+
+    let mut map = Map {
+        version: MAP_VERSION,
+        dimensions: MapDimensions { width: w, height: h },
+        terrain_file: terrain_file.to_owned(),
+        seed: settings.seed,
+        auto_slopes: true,
+        teams,
+        records,
+        objects,
+        ..Map::default()
+    };
+    map.build_tiles(terrain)?;
+    Ok(map)
+}
+
+// checks that everything a team needs can be reached from every other team's base
+fn is_connected(map: &Map, terrain: &TerrainSet, path_options: &PathOptions) -> Result<bool, GenerateError> {
+    let w = map.dimensions.width;
+    let grid = NavGrid::from_tiles(&map.tiles, terrain)?;
+    let areas = connected_areas(&grid, path_options);
+    let stand = match map.objects.first() {
+        Some(stand) => (stand.x, stand.y),
+        _ => return Ok(false),
+    };
+    let main_area = areas[stand.1 * w + stand.0];
+    Ok(main_area.is_some()
+        && map.objects.iter().all(|o| areas[o.y * w + o.x] == main_area)
+        && map.tiles.iter()
+            .flat_map(|row| row.iter())
+            .filter(|tile| tile.region.is_some())
+            .all(|tile| areas[tile.y * w + tile.x] == main_area))
+}
+
+// finds where each team's copy of a tile is, in team order
+fn orbit(x: usize, y: usize, width: usize, height: usize, teams: usize) -> Vec<(usize, usize)> {
+    let (mx, my) = (width - 1 - x, height - 1 - y);
+    if teams == 4 {
+        vec![(x, y), (mx, y), (mx, my), (x, my)]
+    } else {
+        vec![(x, y), (mx, my)]
+    }
+}
+
+// the copy of a tile that the others are copies of
+fn canonical(x: usize, y: usize, width: usize, height: usize, teams: usize) -> (usize, usize) {
+    orbit(x, y, width, height, teams).into_iter()
+        .min_by_key(|(x, y)| (*y, *x))
+        .unwrap_or((x, y))
+}
+
+// changes every team's copy of a tile the same way
+fn set_symmetric<F>(records: &mut [TileRecord], x: usize, y: usize, width: usize, height: usize, teams: usize, mut f: F)
+    where F: FnMut(&mut TileRecord, usize) {
+    for (team, (tx, ty)) in orbit(x, y, width, height, teams).into_iter().enumerate() {
+        f(&mut records[ty * width + tx], team);
+    }
+}
+
+// the tiles from a to b, across then down, so every step is to a tile sharing an edge
+fn corridor_path(a: (usize, usize), b: (usize, usize)) -> Vec<(usize, usize)> {
+    let mut path = vec![a];
+    let (mut x, mut y) = a;
+    while x != b.0 {
+        x = if x < b.0 { x + 1 } else { x - 1 };
+        path.push((x, y));
+    }
+    while y != b.1 {
+        y = if y < b.1 { y + 1 } else { y - 1 };
+        path.push((x, y));
+    }
+    path
+}
+
+// spreads noise that bunches up around the middle back out towards 0 and 1
+fn stretch(n: f32) -> f32 {
+    ((n - 0.5) * 1.8 + 0.5).max(0.).min(0.999)
+}
+
+// smooth noise between 0 and 1, from a few layers of value noise
+fn fractal_noise(seed: u64, x: f32, y: f32, feature_size: f32) -> f32 {
+    let (mut total, mut weight, mut amplitude) = (0., 0., 1.);
+    let mut frequency = 1. / feature_size.max(1.);
+    for octave in 0..4 {
+        total += value_noise(seed.wrapping_add(octave), x * frequency, y * frequency) * amplitude;
+        weight += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.;
+    }
+    total / weight
+}
+
+// blends random values at the corners of a grid
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3. - 2. * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let corner = |cx: f32, cy: f32| (tile_hash(seed, cx as usize, cy as usize) >> 40) as f32 / (1u64 << 24) as f32;
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let top = lerp(corner(x0, y0), corner(x0 + 1., y0), tx);
+    let bottom = lerp(corner(x0, y0 + 1.), corner(x0 + 1., y0 + 1.), tx);
+    lerp(top, bottom, ty)
+}
+
+#[cfg(test)]
+mod tests {
+    use ron::{de::from_str, ser::to_string};
+
+    use super::*;
+    use crate::resources::load_terrain_pack;
+    use crate::util::Neighbourhood;
+
+    fn terrain() -> TerrainSet {
+        load_terrain_pack("256color".to_owned()).unwrap()
+    }
+
+    fn settings(teams: usize, seed: u64, neighbourhood: Neighbourhood, max_climb: usize) -> GeneratorSettings {
+        GeneratorSettings {
+            teams,
+            seed,
+            path_options: PathOptions { neighbourhood, max_climb },
+            ..GeneratorSettings::default()
+        }
+    }
+
+    #[test]
+    fn generated_maps_are_connected() {
+        let terrain = terrain();
+        for teams in [2, 4].iter() {
+            for (neighbourhood, max_climb) in [(Neighbourhood::Four, 1), (Neighbourhood::Four, 0), (Neighbourhood::Eight, 1)].iter() {
+                for seed in 0..8 {
+                    let settings = settings(*teams, seed, *neighbourhood, *max_climb);
+                    let map = generate_map(&terrain, "256color", &settings).unwrap();
+                    assert!(is_connected(&map, &terrain, &settings.path_options).unwrap(), "{:?}", settings);
+                    assert_eq!(map.objects.len(), *teams);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_corridors_connect_without_climbing() {
+        let terrain = terrain();
+        for teams in [2, 4].iter() {
+            for seed in 0..16 {
+                let settings = settings(*teams, seed, Neighbourhood::Four, 0);
+                let map = layout_map(&terrain, "256color", &settings, seed, true).unwrap();
+                assert!(is_connected(&map, &terrain, &settings.path_options).unwrap(), "{:?}", settings);
+            }
+        }
+    }
+
+    #[test]
+    fn saved_settings_make_the_same_map() {
+        let terrain = terrain();
+        let mut map = generate_map(&terrain, "256color", &settings(4, 42, Neighbourhood::Eight, 0)).unwrap();
+        map.build_records(&terrain);
+        let saved: Map = from_str(&to_string(&map).unwrap()).unwrap();
+        let settings = saved.generator.unwrap();
+        assert_eq!((settings.teams, settings.seed, settings.path_options.max_climb), (4, 42, 0));
+        assert_eq!(settings.path_options.neighbourhood, Neighbourhood::Eight);
+
+        let mut again = generate_map(&terrain, &saved.terrain_file, &settings).unwrap();
+        again.build_records(&terrain);
+        assert_eq!(again.records, map.records);
+        assert_eq!(to_string(&again.objects).unwrap(), to_string(&map.objects).unwrap());
+
+        let mut other = generate_map(&terrain, "256color", &GeneratorSettings { seed: 43, ..settings }).unwrap();
+        other.build_records(&terrain);
+        assert_ne!(other.records, map.records);
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use image::{GrayImage, ImageError, Luma};
+use png::HasParameters;
+
+use crate::resources::{Map, MapDimensions, MapLoadError, TerrainSet, TileRecord, MAP_VERSION};
+
+// errors that can occur while turning images into a map or back
+#[derive(Debug)]
+pub enum ImageMapError {
+    // an image file couldn't be opened or created
+    Io { path: PathBuf, source: io::Error },
+    // the heightmap couldn't be read
+    Image { path: PathBuf, source: ImageError },
+    // the terrain mask isn't a valid png
+    Decode { path: PathBuf, source: png::DecodingError },
+    // the terrain mask couldn't be written
+    Encode { path: PathBuf, source: png::EncodingError },
+    // the terrain mask doesn't have a palette to read terrain from
+    NotIndexed { path: PathBuf },
+    // the heightmap and terrain mask are different sizes
+    SizeMismatch { heightmap: (usize, usize), mask: (usize, usize) },
+    // a pixel of the terrain mask uses a palette entry the terrain pack has no terrain for
+    UnknownIndex { index: usize, x: usize, y: usize },
+    // the terrain pack has no terrain to fill the map with
+    NoTerrain,
+    // the terrain pack has more terrain than a palette can hold
+    TooManyTerrains(usize),
+    // the map can't be turned into images
+    Map(MapLoadError),
+}
+
+impl fmt::Display for ImageMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageMapError::Io { path, source } =>
+                write!(f, "could not open {}: {}", path.display(), source),
+            ImageMapError::Image { path, source } =>
+                write!(f, "could not use heightmap {}: {}", path.display(), source),
+            ImageMapError::Decode { path, source } =>
+                write!(f, "could not read terrain mask {}: {}", path.display(), source),
+            ImageMapError::Encode { path, source } =>
+                write!(f, "could not write terrain mask {}: {}", path.display(), source),
+            ImageMapError::NotIndexed { path } =>
+                write!(f, "terrain mask {} isn't an indexed colour png", path.display()),
+            ImageMapError::SizeMismatch { heightmap, mask } =>
+                write!(f, "the heightmap is {}x{} but the terrain mask is {}x{}", heightmap.0, heightmap.1, mask.0, mask.1),
+            ImageMapError::UnknownIndex { index, x, y } =>
+                write!(f, "palette entry {} at ({}, {}) has no terrain in the terrain pack", index, x, y),
+            ImageMapError::NoTerrain =>
+                write!(f, "the terrain pack has no terrain"),
+            ImageMapError::TooManyTerrains(count) =>
+                write!(f, "the terrain pack has {} terrains, but a palette only holds 256", count),
+            ImageMapError::Map(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ImageMapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageMapError::Io { source, .. } => Some(source),
+            ImageMapError::Image { source, .. } => Some(source),
+            ImageMapError::Decode { source, .. } => Some(source),
+            ImageMapError::Encode { source, .. } => Some(source),
+            ImageMapError::Map(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MapLoadError> for ImageMapError {
+    fn from(e: MapLoadError) -> ImageMapError {
+        ImageMapError::Map(e)
+    }
+}
+
+// colours for the terrain mask palette, so the terrains are easy to tell apart when painting
+// entry i of the palette is the i-th terrain of the terrain pack, whatever colour it is
+const PALETTE: [[u8; 3]; 8] = [
+    [76, 153, 0],
+    [153, 102, 51],
+    [0, 102, 204],
+    [128, 128, 128],
+    [230, 204, 128],
+    [0, 76, 38],
+    [255, 255, 255],
+    [204, 0, 0],
+];
+
+// makes a map from a grayscale heightmap, and a terrain mask if there is one
+// black is elevation 0 and white is max_elevation, and tiles without a mask are the pack's first terrain
+pub fn import_images(heightmap: &Path, mask: Option<&Path>, terrain: &TerrainSet, terrain_file: &str, max_elevation: usize) -> Result<Map, ImageMapError> {
+    let heights = image::open(heightmap)
+        .map_err(|source| ImageMapError::Image { path: heightmap.to_owned(), source })?
+        .to_luma();
+    let (width, height) = (heights.width() as usize, heights.height() as usize);
+    let codes = match mask {
+        Some(mask) => {
+            let (size, indices) = read_mask(mask)?;
+            if size != (width, height) {
+                return Err(ImageMapError::SizeMismatch { heightmap: (width, height), mask: size });
+            }
+            indices.into_iter()
+                .enumerate()
+                .map(|(i, index)| terrain.tiles.get(index)
+                    .map(|t| t.char_code)
+                    .ok_or(ImageMapError::UnknownIndex { index, x: i % width, y: i / width }))
+                .collect::<Result<Vec<char>, ImageMapError>>()?
+        },
+        _ => {
+            let code = terrain.tiles.first()
+                .map(|t| t.char_code)
+                .ok_or(ImageMapError::NoTerrain)?;
+            vec![code; width * height]
+        },
+    };
+
+    let records = heights.pixels()
+        .zip(codes.into_iter())
+        .map(|(pixel, code)| {
+            let elevation = (pixel[0] as f32 / 255. * max_elevation as f32).round() as usize;
+            TileRecord::new(code, elevation)
+        })
+        .collect();
+    let mut map = Map {
+        version: MAP_VERSION,
+        dimensions: MapDimensions { width, height },
+        terrain_file: terrain_file.to_owned(),
+        records,
+        ..Map::default()
+    };
+    map.build_tiles(terrain)?;
+    Ok(map)
+}
+
+// writes a map's elevation to a grayscale heightmap, and its terrain to an indexed colour mask
+// elevations above max_elevation come out white
+pub fn export_images(map: &Map, terrain: &TerrainSet, heightmap: &Path, mask: Option<&Path>, max_elevation: usize) -> Result<(), ImageMapError> {
+    let (width, height) = (map.dimensions.width, map.dimensions.height);
+    if width * height != map.records.len() {
+        return Err(MapLoadError::Dimensions { width, height, found: map.records.len() }.into());
+    }
+    let heights = GrayImage::from_fn(width as u32, height as u32, |x, y| {
+        let elevation = map.records[y as usize * width + x as usize].elevation.min(max_elevation);
+        Luma([(elevation as f32 / max_elevation.max(1) as f32 * 255.).round() as u8])
+    });
+    heights.save(heightmap)
+        .map_err(|source| ImageMapError::Io { path: heightmap.to_owned(), source })?;
+
+    if let Some(mask) = mask {
+        if terrain.tiles.len() > 256 {
+            return Err(ImageMapError::TooManyTerrains(terrain.tiles.len()));
+        }
+        let indices = map.records.iter()
+            .enumerate()
+            .map(|(i, record)| terrain.tiles.iter()
+                .position(|t| t.char_code == record.terrain)
+                .map(|index| index as u8)
+                .ok_or(MapLoadError::UnknownTerrain { code: record.terrain, x: i % width, y: i / width }))
+            .collect::<Result<Vec<u8>, MapLoadError>>()?;
+        write_mask(mask, width, height, terrain.tiles.len(), &indices)?;
+    }
+    Ok(())
+}
+
+// reads the palette index of every pixel of an indexed colour png, row by row
+fn read_mask(path: &Path) -> Result<((usize, usize), Vec<usize>), ImageMapError> {
+    let file = File::open(path).map_err(|source| ImageMapError::Io { path: path.to_owned(), source })?;
+    let mut decoder = png::Decoder::new(file);
+    // keep the palette indices instead of expanding them to colours
+    decoder.set(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info()
+        .map_err(|source| ImageMapError::Decode { path: path.to_owned(), source })?;
+    if info.color_type != png::ColorType::Indexed {
+        return Err(ImageMapError::NotIndexed { path: path.to_owned() });
+    }
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)
+        .map_err(|source| ImageMapError::Decode { path: path.to_owned(), source })?;
+
+    // indices smaller than a byte are packed from the high bits down
+    let (width, height) = (info.width as usize, info.height as usize);
+    let bits = info.bit_depth as usize;
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &buffer[y * info.line_size..];
+        for x in 0..width {
+            let byte = row[x * bits / 8] as usize;
+            let shift = 8 - bits - (x * bits) % 8;
+            indices.push((byte >> shift) & ((1 << bits) - 1));
+        }
+    }
+    Ok(((width, height), indices))
+}
+
+// writes palette indices to an 8 bit indexed colour png
+fn write_mask(path: &Path, width: usize, height: usize, colours: usize, indices: &[u8]) -> Result<(), ImageMapError> {
+    let file = File::create(path).map_err(|source| ImageMapError::Io { path: path.to_owned(), source })?;
+    let encode_error = |source| ImageMapError::Encode { path: path.to_owned(), source };
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set(png::ColorType::Indexed).set(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(encode_error)?;
+    let palette: Vec<u8> = (0..colours.max(1))
+        .flat_map(|i| PALETTE[i % PALETTE.len()].iter().map(move |c| c.saturating_sub((i / PALETTE.len() * 32) as u8)))
+        .collect();
+    writer.write_chunk(png::chunk::PLTE, &palette).map_err(encode_error)?;
+    writer.write_image_data(indices).map_err(encode_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::resources::load_terrain_pack;
+
+    // a path in the temp directory that no other test uses
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("cap_flag_{}_{}", process::id(), name))
+    }
+
+    // writes palette indices packed bits to a pixel, from the high bits down, with each row starting on a new byte
+    fn write_packed_mask(path: &Path, width: usize, height: usize, bits: usize, indices: &[u8]) {
+        let depth = match bits {
+            1 => png::BitDepth::One,
+            2 => png::BitDepth::Two,
+            _ => png::BitDepth::Four,
+        };
+        let line_size = (width * bits + 7) / 8;
+        let mut data = vec![0; line_size * height];
+        for (i, index) in indices.iter().enumerate() {
+            let (x, y) = (i % width, i / width);
+            data[y * line_size + x * bits / 8] |= index << (8 - bits - (x * bits) % 8);
+        }
+        let mut encoder = png::Encoder::new(File::create(path).unwrap(), width as u32, height as u32);
+        encoder.set(png::ColorType::Indexed).set(depth);
+        let mut writer = encoder.write_header().unwrap();
+        let palette: Vec<u8> = (0..1 << bits).flat_map(|i| PALETTE[i % PALETTE.len()].iter().cloned()).collect();
+        writer.write_chunk(png::chunk::PLTE, &palette).unwrap();
+        writer.write_image_data(&data).unwrap();
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        let (heightmap, mask) = (temp_path("round_trip_heights.png"), temp_path("round_trip_mask.png"));
+        let (exported_heightmap, exported_mask) = (temp_path("round_trip_heights_out.png"), temp_path("round_trip_mask_out.png"));
+        // a 5x2 map, the shades of the top row are levels 0 to 4 of 4, the bottom row is white
+        let shades = [0, 64, 128, 191, 255];
+        let heights = GrayImage::from_fn(5, 2, |x, y| Luma([if y == 0 { shades[x as usize] } else { 255 }]));
+        heights.save(&heightmap).unwrap();
+        // grass and dirt, the first two terrains of the pack, in alternating tiles
+        let indices: Vec<u8> = (0..10).map(|i| i % 2).collect();
+        write_mask(&mask, 5, 2, terrain.tiles.len(), &indices).unwrap();
+
+        let map = import_images(&heightmap, Some(&mask), &terrain, "256color", 4).unwrap();
+        assert_eq!((map.dimensions.width, map.dimensions.height), (5, 2));
+        assert_eq!(map.terrain_file, "256color");
+        let elevations: Vec<usize> = map.records.iter().map(|r| r.elevation).collect();
+        assert_eq!(elevations, vec![0, 1, 2, 3, 4, 4, 4, 4, 4, 4]);
+        let codes: String = map.records.iter().map(|r| r.terrain).collect();
+        assert_eq!(codes, "gdgdgdgdgd");
+        assert_eq!(map.tiles[1][4].elevation, 4);
+
+        // the images come back out as they went in
+        export_images(&map, &terrain, &exported_heightmap, Some(&exported_mask), 4).unwrap();
+        assert_eq!(image::open(&exported_heightmap).unwrap().to_luma().into_raw(), heights.into_raw());
+        assert_eq!(read_mask(&exported_mask).unwrap(), ((5, 2), indices.iter().map(|i| *i as usize).collect()));
+        let reimported = import_images(&exported_heightmap, Some(&exported_mask), &terrain, "256color", 4).unwrap();
+        assert_eq!(reimported.records, map.records);
+
+        // elevation is scaled to the max elevation asked for, and anything higher is white
+        let mut high = map.clone();
+        high.records[0].elevation = 9;
+        export_images(&high, &terrain, &exported_heightmap, None, 8).unwrap();
+        let pixels = image::open(&exported_heightmap).unwrap().to_luma().into_raw();
+        assert_eq!(&pixels[..5], &[255, 32, 64, 96, 128]);
+
+        // without a mask, the map is the pack's first terrain
+        let unmasked = import_images(&heightmap, None, &terrain, "256color", 2).unwrap();
+        assert!(unmasked.records.iter().all(|r| r.terrain == 'g'));
+        assert_eq!(unmasked.records[2].elevation, 1);
+
+        for path in [heightmap, mask, exported_heightmap, exported_mask].iter() {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn masks_unpack_small_palette_indices() {
+        // 5 pixels wide, so rows don't end on a byte
+        let (width, height) = (5, 3);
+        for bits in [1, 2, 4].iter().cloned() {
+            let path = temp_path(&format!("mask_{}_bits.png", bits));
+            let indices: Vec<u8> = (0..width * height).map(|i| (i * 7 % (1 << bits)) as u8).collect();
+            write_packed_mask(&path, width, height, bits, &indices);
+            let (size, read) = read_mask(&path).unwrap();
+            assert_eq!(size, (width, height), "{} bits", bits);
+            assert_eq!(read, indices.iter().map(|i| *i as usize).collect::<Vec<_>>(), "{} bits", bits);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn masks_have_to_be_indexed() {
+        let path = temp_path("grey_mask.png");
+        GrayImage::new(2, 2).save(&path).unwrap();
+        match read_mask(&path) {
+            Err(ImageMapError::NotIndexed { .. }) => {},
+            other => panic!("expected an unindexed mask error, got {:?}", other.map(|(size, _)| size)),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
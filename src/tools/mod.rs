@@ -0,0 +1,267 @@
+mod generate;
+mod heightmap;
+mod tiled;
+mod validate;
+
+use amethyst::Error;
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::resources::{load_editor_config, load_map, load_terrain_pack, save_map, Map, MapLoadError, TerrainSet};
+use crate::util::{Neighbourhood, PathOptions};
+
+pub use self::generate::{generate_map, GenerateError, GeneratorSettings, MIN_GENERATED_SIZE};
+pub use self::heightmap::{export_images, import_images, ImageMapError};
+pub use self::tiled::{load_sheet_layout, map_to_tiled, read_tiled, tiled_to_map, write_tiled, TiledError, TiledMap};
+pub use self::validate::{validate_map, Issue, RouteKind, Severity, TeamRoutes, ValidationReport, ASYMMETRY_TOLERANCE};
+
+// runs one of the command line tools instead of the game
+// cap_flag validate [--eight] [--climb=N] <map>...
+// cap_flag generate [--from=map] [--seed=N] [--size=WxH] [--teams=N] [--terrain=pack] [--eight] [--climb=N] <map>
+// cap_flag import [--mask=mask.png] [--terrain=pack] [--max-elevation=N] <heightmap.png> <map>
+// cap_flag export [--mask=mask.png] [--max-elevation=N] <map> <heightmap.png>
+// cap_flag tiled-import [--terrain=pack] <map.tmx|map.json> <map>
+// cap_flag tiled-export [--image=texture path] <map> <map.tmx|map.json>
+pub fn run_tool(tool: &str, args: &[String]) -> amethyst::Result<()> {
+    match tool {
+        "validate" => validate_command(args),
+        "generate" => generate_command(args),
+        "import" => import_command(args),
+        "export" => export_command(args),
+        "tiled-import" => tiled_import_command(args),
+        "tiled-export" => tiled_export_command(args),
+        _ => Err(Error::from_string(format!("unknown tool '{}', the tools are: validate, generate, import, export, tiled-import, tiled-export", tool))),
+    }
+}
+
+// reads the pathfinding flags out of the arguments, returning the options and the other arguments
+fn path_options(args: &[String]) -> amethyst::Result<(PathOptions, Vec<String>)> {
+    let mut options = PathOptions::default();
+    let mut rest = vec![];
+    for arg in args.iter() {
+        if arg == "--eight" {
+            options.neighbourhood = Neighbourhood::Eight;
+        } else if arg.starts_with("--climb=") {
+            options.max_climb = arg["--climb=".len()..].parse()
+                .map_err(|_| Error::from_string(format!("bad climb limit in '{}'", arg)))?;
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    Ok((options, rest))
+}
+
+// loads a map and its terrain pack, and builds the map's tiles
+fn load_built_map(filename: &str) -> Result<(Map, TerrainSet), MapLoadError> {
+    let mut map = load_map(filename.to_owned())?;
+    let terrain = load_terrain_pack(map.terrain_file.clone())?;
+    map.build_tiles(&terrain)?;
+    Ok((map, terrain))
+}
+
+fn validate_command(args: &[String]) -> amethyst::Result<()> {
+    let (options, maps) = path_options(args)?;
+    if maps.is_empty() {
+        return Err(Error::from_string("validate needs at least one map"));
+    }
+    let mut failed = 0;
+    for filename in maps.iter() {
+        println!("{}:", filename);
+        match load_built_map(filename) {
+            Ok((map, terrain)) => {
+                let report = validate_map(&map, &terrain, &options);
+                println!("{}", report);
+                if !report.is_playable() {
+                    failed += 1;
+                }
+            },
+            Err(e) => {
+                println!("error: {}", e);
+                failed += 1;
+            },
+        }
+    }
+    if failed > 0 {
+        return Err(Error::from_string(format!("{} of {} maps can't be played", failed, maps.len())));
+    }
+    Ok(())
+}
+
+fn generate_command(args: &[String]) -> amethyst::Result<()> {
+    let (path_options, rest) = path_options(args)?;
+    let path_flags = rest.len() < args.len();
+    let mut settings = GeneratorSettings {
+        seed: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos() as u64).unwrap_or(0),
+        path_options,
+        ..GeneratorSettings::default()
+    };
+    let mut terrain_file = "256color".to_owned();
+    // a generated map's saved settings are used for anything not given
+    if let Some(arg) = rest.iter().find(|arg| arg.starts_with("--from=")) {
+        let from_file = &arg["--from=".len()..];
+        let from = load_map(from_file.to_owned()).map_err(|e| Error::from_string(e.to_string()))?;
+        let saved = from.generator.ok_or_else(|| Error::from_string(format!("{} wasn't generated", from_file)))?;
+        terrain_file = from.terrain_file;
+        settings = GeneratorSettings {
+            path_options: if path_flags { path_options } else { saved.path_options },
+            ..saved
+        };
+    }
+    let mut filename = None;
+    for arg in rest.iter() {
+        let bad = || Error::from_string(format!("bad value in '{}'", arg));
+        if arg.starts_with("--from=") {
+            continue;
+        } else if arg.starts_with("--seed=") {
+            settings.seed = arg["--seed=".len()..].parse().map_err(|_| bad())?;
+        } else if arg.starts_with("--teams=") {
+            settings.teams = arg["--teams=".len()..].parse().map_err(|_| bad())?;
+        } else if arg.starts_with("--size=") {
+            let size: Vec<&str> = arg["--size=".len()..].split('x').collect();
+            if size.len() != 2 {
+                return Err(bad());
+            }
+            settings.width = size[0].parse().map_err(|_| bad())?;
+            settings.height = size[1].parse().map_err(|_| bad())?;
+        } else if arg.starts_with("--terrain=") {
+            terrain_file = arg["--terrain=".len()..].to_owned();
+        } else if filename.is_none() {
+            filename = Some(arg.clone());
+        } else {
+            return Err(Error::from_string(format!("unexpected argument '{}'", arg)));
+        }
+    }
+    let filename = filename.ok_or_else(|| Error::from_string("generate needs the name of the map to write"))?;
+    let terrain = load_terrain_pack(terrain_file.clone()).map_err(|e| Error::from_string(e.to_string()))?;
+    let mut map = generate_map(&terrain, &terrain_file, &settings).map_err(|e| Error::from_string(e.to_string()))?;
+    println!("generated a {}x{} map for {} teams with seed {}", settings.width, settings.height, settings.teams, settings.seed);
+    println!("{}", validate_map(&map, &terrain, &settings.path_options));
+    map.build_records(&terrain);
+    save_map(&map, filename.clone())?;
+    println!("saved map to {}", filename);
+    Ok(())
+}
+
+// the options shared by the image import and export tools, and the other arguments
+struct ImageOptions {
+    mask: Option<String>,
+    terrain_file: String,
+    max_elevation: usize,
+    rest: Vec<String>,
+}
+
+fn image_options(args: &[String]) -> amethyst::Result<ImageOptions> {
+    let mut options = ImageOptions {
+        mask: None,
+        terrain_file: "256color".to_owned(),
+        // the same levels the editor raises tiles to
+        max_elevation: load_editor_config().max_elevation,
+        rest: vec![],
+    };
+    for arg in args.iter() {
+        if arg.starts_with("--mask=") {
+            options.mask = Some(arg["--mask=".len()..].to_owned());
+        } else if arg.starts_with("--terrain=") {
+            options.terrain_file = arg["--terrain=".len()..].to_owned();
+        } else if arg.starts_with("--max-elevation=") {
+            options.max_elevation = arg["--max-elevation=".len()..].parse()
+                .map_err(|_| Error::from_string(format!("bad elevation in '{}'", arg)))?;
+        } else {
+            options.rest.push(arg.clone());
+        }
+    }
+    Ok(options)
+}
+
+fn import_command(args: &[String]) -> amethyst::Result<()> {
+    let options = image_options(args)?;
+    let (heightmap, filename) = match options.rest.as_slice() {
+        [heightmap, filename] => (heightmap, filename),
+        _ => return Err(Error::from_string("import needs a heightmap and the name of the map to write")),
+    };
+    let terrain = load_terrain_pack(options.terrain_file.clone()).map_err(|e| Error::from_string(e.to_string()))?;
+    let mut map = import_images(
+        Path::new(heightmap),
+        options.mask.as_ref().map(Path::new),
+        &terrain,
+        &options.terrain_file,
+        options.max_elevation,
+    ).map_err(|e| Error::from_string(e.to_string()))?;
+    map.build_records(&terrain);
+    save_map(&map, filename.clone())?;
+    println!("imported a {}x{} map to {}", map.dimensions.width, map.dimensions.height, filename);
+    Ok(())
+}
+
+fn export_command(args: &[String]) -> amethyst::Result<()> {
+    let options = image_options(args)?;
+    let (filename, heightmap) = match options.rest.as_slice() {
+        [filename, heightmap] => (filename, heightmap),
+        _ => return Err(Error::from_string("export needs a map and the heightmap to write")),
+    };
+    let (map, terrain) = load_built_map(filename).map_err(|e| Error::from_string(e.to_string()))?;
+    export_images(&map, &terrain, Path::new(heightmap), options.mask.as_ref().map(Path::new), options.max_elevation)
+        .map_err(|e| Error::from_string(e.to_string()))?;
+    println!("exported {} to {}", filename, heightmap);
+    Ok(())
+}
+
+fn tiled_import_command(args: &[String]) -> amethyst::Result<()> {
+    let mut terrain_file = None;
+    let mut rest = vec![];
+    for arg in args.iter() {
+        if arg.starts_with("--terrain=") {
+            terrain_file = Some(arg["--terrain=".len()..].to_owned());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    let (source, filename) = match rest.as_slice() {
+        [source, filename] => (source, filename),
+        _ => return Err(Error::from_string("tiled-import needs a Tiled map and the name of the map to write")),
+    };
+    let tiled = read_tiled(Path::new(source)).map_err(|e| Error::from_string(e.to_string()))?;
+    // the terrain pack the map was exported with, unless another one is asked for
+    let terrain_file = terrain_file
+        .or_else(|| tiled::property(&tiled.properties, "terrain").and_then(|v| v.as_str()).map(|s| s.to_owned()))
+        .unwrap_or_else(|| "256color".to_owned());
+    let terrain = load_terrain_pack(terrain_file.clone()).map_err(|e| Error::from_string(e.to_string()))?;
+    let sheet = load_sheet_layout(&terrain).map_err(|e| Error::from_string(e.to_string()))?;
+    let mut map = tiled_to_map(&tiled, &terrain, &terrain_file, &sheet).map_err(|e| Error::from_string(e.to_string()))?;
+    map.build_records(&terrain);
+    save_map(&map, filename.clone())?;
+    println!("imported {} to {}", source, filename);
+    Ok(())
+}
+
+fn tiled_export_command(args: &[String]) -> amethyst::Result<()> {
+    let mut image = None;
+    let mut rest = vec![];
+    for arg in args.iter() {
+        if arg.starts_with("--image=") {
+            image = Some(arg["--image=".len()..].to_owned());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    let (filename, target) = match rest.as_slice() {
+        [filename, target] => (filename, target),
+        _ => return Err(Error::from_string("tiled-export needs a map and the Tiled map to write")),
+    };
+    let (map, terrain) = load_built_map(filename).map_err(|e| Error::from_string(e.to_string()))?;
+    let sheet = load_sheet_layout(&terrain).map_err(|e| Error::from_string(e.to_string()))?;
+    // Tiled finds the texture relative to the map, so the full path works wherever the map is written
+    let image = match image {
+        Some(image) => image,
+        _ => amethyst::utils::application_root_dir()?
+            .join(format!("resources/terrain/{}.{}", terrain.texture_file, terrain.texture_format))
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let tiled = map_to_tiled(&map, &terrain, &sheet, &image).map_err(|e| Error::from_string(e.to_string()))?;
+    write_tiled(&tiled, Path::new(target)).map_err(|e| Error::from_string(e.to_string()))?;
+    println!("exported {} to {}", filename, target);
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::components::{MapObject, ObjectKind, ObjectSlot, Region, Slope, OBJECT_SLOTS};
+use crate::resources::{Map, MapDimensions, MapLoadError, TerrainSet, TileRecord, MAP_VERSION, MAX_TEAMS};
+use crate::util::{ELEVATION, HALF_TILE, TILE_SIZE};
+
+use super::{property, TiledData, TiledError, TiledLayer, TiledMap, TiledObject, TiledProperty, TiledTileset};
+
+// ids at or above this have flip and rotation flags set
+const FLIP_FLAGS: u32 = 0xF000_0000;
+
+// the sprites of a terrain pack's sprite sheet, from its .ron file
+#[derive(Deserialize, Debug, Clone)]
+pub struct SheetLayout {
+    pub texture_width: u32,
+    pub texture_height: u32,
+    pub sprites: Vec<SheetSprite>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SheetSprite {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// what a sprite of the terrain pack paints
+#[derive(Debug, Clone, Copy)]
+struct SpriteTerrain {
+    terrain: char,
+    variant: Option<usize>,
+    slope: Option<Slope>,
+}
+
+// converts a Tiled isometric map into a map for the terrain pack
+// tile layers are terrain at the elevation in their "elevation" property, with later layers over earlier ones
+// object layers hold spawns, flag stands, walls, decorations and team regions
+pub fn tiled_to_map(tiled: &TiledMap, terrain: &TerrainSet, terrain_file: &str, sheet: &SheetLayout) -> Result<Map, TiledError> {
+    if tiled.orientation != "isometric" {
+        return Err(TiledError::Unsupported(format!("{} maps, only isometric ones", tiled.orientation)));
+    }
+    if tiled.infinite {
+        return Err(TiledError::Unsupported("infinite maps".to_owned()));
+    }
+    let tileset = match tiled.tilesets.as_slice() {
+        [tileset] => tileset,
+        tilesets => return Err(TiledError::Tilesets(tilesets.len())),
+    };
+    if tileset.source.is_some() {
+        return Err(TiledError::Unsupported(format!("external tileset {}, embed it in the map", tileset.name)));
+    }
+    check_tileset(tileset, terrain, sheet)?;
+    let sprites = sprite_terrain(terrain);
+    let (w, h) = (tiled.width, tiled.height);
+
+    let mut records: Vec<Option<TileRecord>> = vec![None; w * h];
+    let mut objects = vec![];
+    let mut regions: Vec<Option<Region>> = vec![None; w * h];
+    for layer in tiled.layers.iter() {
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let ids = layer_ids(layer)?;
+                if layer.width != w || layer.height != h || ids.len() != w * h {
+                    return Err(TiledError::LayerSize { layer: layer.name.clone(), width: layer.width, height: layer.height });
+                }
+                let elevation = int_property(&layer.properties, "elevation")
+                    .map_err(|msg| TiledError::BadProperty { owner: format!("layer '{}'", layer.name), msg })?
+                    .unwrap_or(0);
+                for (i, id) in ids.iter().enumerate() {
+                    if *id == 0 {
+                        continue;
+                    }
+                    let (x, y) = (i % w, i / w);
+                    if id & FLIP_FLAGS != 0 {
+                        return Err(TiledError::Unsupported(format!("flipped or rotated tiles, like the one in layer '{}' at ({}, {})", layer.name, x, y)));
+                    }
+                    let unknown = || TiledError::UnknownTile { id: *id, layer: layer.name.clone(), x, y };
+                    let sprite = tile_sprite(*id, tileset, sheet).ok_or_else(unknown)?;
+                    let found = sprites.get(&sprite).ok_or_else(unknown)?;
+                    records[i] = Some(TileRecord {
+                        slope: found.slope,
+                        variant: found.variant,
+                        ..TileRecord::new(found.terrain, elevation)
+                    });
+                }
+            },
+            "objectgroup" => {
+                for object in layer.objects.iter().flat_map(|objects| objects.iter()) {
+                    read_object(object, tiled, &mut objects, &mut regions)?;
+                }
+            },
+            other => return Err(TiledError::Unsupported(format!("{} layers, like '{}'", other, layer.name))),
+        }
+    }
+
+    let mut tile_records = Vec::with_capacity(w * h);
+    for (i, record) in records.into_iter().enumerate() {
+        let mut record = record.ok_or(TiledError::MissingTile { x: i % w, y: i / w })?;
+        record.region = regions[i];
+        tile_records.push(record);
+    }
+    for (index, object) in objects.iter().enumerate() {
+        tile_records[object.y * w + object.x].objects.push(index);
+    }
+    let properties = &tiled.properties;
+    let bad_property = |msg: String| TiledError::BadProperty { owner: "the map".to_owned(), msg };
+    let mut map = Map {
+        version: MAP_VERSION,
+        dimensions: MapDimensions { width: w, height: h },
+        terrain_file: terrain_file.to_owned(),
+        seed: int_property(properties, "seed").map_err(bad_property)?.unwrap_or(0) as u64,
+        auto_slopes: property(properties, "auto_slopes").and_then(|v| v.as_bool()).unwrap_or(false),
+        teams: int_property(properties, "teams").map_err(bad_property)?.unwrap_or(0),
+        records: tile_records,
+        objects,
+        ..Map::default()
+    };
+    map.build_tiles(terrain)?;
+    Ok(map)
+}
+
+// converts a map with its tiles built into a Tiled isometric map
+// image is where Tiled should find the terrain pack's texture, relative to the Tiled map
+pub fn map_to_tiled(map: &Map, terrain: &TerrainSet, sheet: &SheetLayout, image: &str) -> Result<TiledMap, TiledError> {
+    let (w, h) = (map.dimensions.width, map.dimensions.height);
+    let tile_size = terrain.tile_size as u32;
+    let columns = (sheet.texture_width / tile_size.max(1)).max(1);
+    let tileset = TiledTileset {
+        firstgid: 1,
+        source: None,
+        name: map.terrain_file.clone(),
+        image: image.to_owned(),
+        imagewidth: sheet.texture_width,
+        imageheight: sheet.texture_height,
+        tilewidth: tile_size,
+        tileheight: tile_size,
+        tilecount: columns * (sheet.texture_height / tile_size.max(1)),
+        columns,
+        margin: 0,
+        spacing: 0,
+    };
+
+    // a tile layer for each elevation, raised the way the game draws it
+    let mut layers = vec![];
+    let max_elevation = map.tiles.iter().flat_map(|row| row.iter()).map(|t| t.elevation).max().unwrap_or(0);
+    let scale = terrain.tile_size as f32 / TILE_SIZE;
+    for elevation in 0..=max_elevation {
+        let mut ids = vec![0; w * h];
+        for tile in map.tiles.iter().flat_map(|row| row.iter()).filter(|t| t.elevation == elevation) {
+            // the flat or sloped sprite, since edge sprites are worked out again when the map is loaded
+            let terrain_tile = terrain.terrain(tile.terrain)
+                .ok_or(MapLoadError::UnknownTerrain { code: tile.terrain, x: tile.x, y: tile.y })?;
+            let sprite = terrain_tile.sprite_for(tile.variant, tile.slope);
+            ids[tile.y * w + tile.x] = sprite_tile(sprite, &tileset, sheet)
+                .ok_or_else(|| TiledError::TilesetMismatch { tileset: tileset.name.clone(), msg: format!("sprite {} isn't on the tile grid", sprite) })?;
+        }
+        if ids.iter().all(|id| *id == 0) {
+            continue;
+        }
+        layers.push(TiledLayer {
+            kind: "tilelayer".to_owned(),
+            id: layers.len() as u32 + 1,
+            name: format!("elevation {}", elevation),
+            width: w,
+            height: h,
+            offsety: -(elevation as f32 * ELEVATION * scale),
+            visible: true,
+            opacity: 1.,
+            data: Some(TiledData::Ids(ids)),
+            properties: vec![TiledProperty::int("elevation", elevation)],
+            ..TiledLayer::default()
+        });
+    }
+
+    // objects are points in the middle of their tiles, regions are squares covering them
+    let unit = tiled_tile_height(terrain) as f32;
+    let mut next_id = 1;
+    let mut objects = vec![];
+    for object in map.objects.iter() {
+        let mut properties = vec![TiledProperty::string("slot", &format!("{:?}", object.slot))];
+        if let Some(team) = object.team {
+            properties.push(TiledProperty::int("team", team));
+        }
+        let kind = match object.kind {
+            ObjectKind::Spawn => "spawn",
+            ObjectKind::FlagStand => "flag_stand",
+            ObjectKind::Wall => "wall",
+            ObjectKind::Decoration(sprite) => {
+                properties.push(TiledProperty::int("sprite", sprite));
+                "decoration"
+            },
+        };
+        objects.push(TiledObject {
+            id: next_id,
+            kind: kind.to_owned(),
+            x: (object.x as f32 + 0.5) * unit,
+            y: (object.y as f32 + 0.5) * unit,
+            visible: true,
+            point: true,
+            properties,
+            ..TiledObject::default()
+        });
+        next_id += 1;
+    }
+    let mut region_objects = vec![];
+    for tile in map.tiles.iter().flat_map(|row| row.iter()) {
+        if let Some(region) = tile.region {
+            let kind = match region {
+                Region::Spawn(_) => "spawn",
+                Region::Base(_) => "base",
+            };
+            region_objects.push(TiledObject {
+                id: next_id,
+                kind: "region".to_owned(),
+                x: tile.x as f32 * unit,
+                y: tile.y as f32 * unit,
+                width: unit,
+                height: unit,
+                visible: true,
+                properties: vec![TiledProperty::string("region", kind), TiledProperty::int("team", region.team())],
+                ..TiledObject::default()
+            });
+            next_id += 1;
+        }
+    }
+    for (name, objects) in vec![("objects", objects), ("regions", region_objects)] {
+        layers.push(TiledLayer {
+            kind: "objectgroup".to_owned(),
+            id: layers.len() as u32 + 1,
+            name: name.to_owned(),
+            visible: true,
+            opacity: 1.,
+            objects: Some(objects),
+            ..TiledLayer::default()
+        });
+    }
+
+    Ok(TiledMap {
+        kind: "map".to_owned(),
+        orientation: "isometric".to_owned(),
+        renderorder: "right-down".to_owned(),
+        width: w,
+        height: h,
+        tilewidth: tile_size,
+        tileheight: tiled_tile_height(terrain),
+        infinite: false,
+        nextlayerid: layers.len() as u32 + 1,
+        nextobjectid: next_id,
+        layers,
+        tilesets: vec![tileset],
+        properties: vec![
+            TiledProperty::string("terrain", &map.terrain_file),
+            TiledProperty::int("teams", map.teams),
+            TiledProperty::int("seed", map.seed as usize),
+            TiledProperty::bool("auto_slopes", map.auto_slopes),
+        ],
+    })
+}
+
+// the height of a tile's diamond, which Tiled measures object positions in
+fn tiled_tile_height(terrain: &TerrainSet) -> u32 {
+    (terrain.tile_size as f32 * HALF_TILE / TILE_SIZE) as u32
+}
+
+// checks a tileset is cut from the terrain pack's sprite sheet
+fn check_tileset(tileset: &TiledTileset, terrain: &TerrainSet, sheet: &SheetLayout) -> Result<(), TiledError> {
+    let mismatch = |msg: String| TiledError::TilesetMismatch { tileset: tileset.name.clone(), msg };
+    let texture = format!("{}.{}", terrain.texture_file, terrain.texture_format);
+    if !tileset.image.ends_with(&texture) {
+        return Err(mismatch(format!("its image is {}, not {}", tileset.image, texture)));
+    }
+    if (tileset.imagewidth, tileset.imageheight) != (sheet.texture_width, sheet.texture_height) {
+        return Err(mismatch(format!("its image is {}x{}, but the sprite sheet is {}x{}",
+            tileset.imagewidth, tileset.imageheight, sheet.texture_width, sheet.texture_height)));
+    }
+    if tileset.tilewidth as usize != terrain.tile_size || tileset.tileheight as usize != terrain.tile_size {
+        return Err(mismatch(format!("its tiles are {}x{}, but the terrain pack's are {}x{}",
+            tileset.tilewidth, tileset.tileheight, terrain.tile_size, terrain.tile_size)));
+    }
+    Ok(())
+}
+
+// finds the sprite of the sprite sheet a tile of the tileset is cut from
+fn tile_sprite(id: u32, tileset: &TiledTileset, sheet: &SheetLayout) -> Option<usize> {
+    let local = id.checked_sub(tileset.firstgid)?;
+    let columns = tileset.columns.max(1);
+    let x = tileset.margin + (local % columns) * (tileset.tilewidth + tileset.spacing);
+    let y = tileset.margin + (local / columns) * (tileset.tileheight + tileset.spacing);
+    sheet.sprites.iter().position(|s| (s.x, s.y, s.width, s.height) == (x, y, tileset.tilewidth, tileset.tileheight))
+}
+
+// finds the tile of the tileset a sprite of the sprite sheet is
+fn sprite_tile(sprite: usize, tileset: &TiledTileset, sheet: &SheetLayout) -> Option<u32> {
+    let s = sheet.sprites.get(sprite)?;
+    let (step_x, step_y) = (tileset.tilewidth + tileset.spacing, tileset.tileheight + tileset.spacing);
+    if step_x == 0 || step_y == 0 || s.x < tileset.margin || s.y < tileset.margin
+        || (s.x - tileset.margin) % step_x != 0 || (s.y - tileset.margin) % step_y != 0 {
+        return None;
+    }
+    Some(tileset.firstgid + (s.y - tileset.margin) / step_y * tileset.columns + (s.x - tileset.margin) / step_x)
+}
+
+// what each sprite of the terrain pack paints, flat sprites first, then sloped and edge sprites
+fn sprite_terrain(terrain: &TerrainSet) -> HashMap<usize, SpriteTerrain> {
+    let mut sprites = HashMap::new();
+    for t in terrain.tiles.iter() {
+        for (variant, sprite) in t.tiles.iter().enumerate() {
+            sprites.entry(*sprite).or_insert(SpriteTerrain { terrain: t.char_code, variant: Some(variant), slope: None });
+        }
+    }
+    for t in terrain.tiles.iter() {
+        for slope in [Slope::NW, Slope::NE, Slope::SE, Slope::SW].iter() {
+            for (variant, sprite) in t.slope_tiles.get(*slope).iter().enumerate() {
+                sprites.entry(*sprite).or_insert(SpriteTerrain { terrain: t.char_code, variant: Some(variant), slope: Some(*slope) });
+            }
+        }
+    }
+    for rule in terrain.transitions.iter() {
+        for sprite in rule.tiles.values() {
+            sprites.entry(*sprite).or_insert(SpriteTerrain { terrain: rule.terrain, variant: None, slope: None });
+        }
+    }
+    sprites
+}
+
+// the tile ids of a tile layer
+fn layer_ids(layer: &TiledLayer) -> Result<&[u32], TiledError> {
+    if layer.chunks.is_some() {
+        return Err(TiledError::Unsupported("infinite maps".to_owned()));
+    }
+    match &layer.data {
+        Some(TiledData::Ids(ids)) => Ok(ids),
+        Some(TiledData::Encoded(_)) => Err(TiledError::Unsupported(format!(
+            "{} encoded tile data in layer '{}', save the map with the CSV tile layer format",
+            layer.encoding.as_ref().map_or("base64", |e| e.as_str()),
+            layer.name,
+        ))),
+        None => Err(TiledError::LayerSize { layer: layer.name.clone(), width: 0, height: 0 }),
+    }
+}
+
+// turns an object into a map object or team region
+fn read_object(object: &TiledObject, tiled: &TiledMap, objects: &mut Vec<MapObject>, regions: &mut [Option<Region>]) -> Result<(), TiledError> {
+    let bad = |msg: String| TiledError::BadObject { id: object.id, msg };
+    if object.gid.is_some() || object.polygon.is_some() || object.polyline.is_some() || object.text.is_some() || object.template.is_some() {
+        return Err(bad("tile objects, polygons, polylines, text and templates can't be converted".to_owned()));
+    }
+    if object.rotation != 0. {
+        return Err(bad("rotated objects can't be converted".to_owned()));
+    }
+    let unit = tiled.tileheight.max(1) as f32;
+    let (w, h) = (tiled.width, tiled.height);
+    let team = int_property(&object.properties, "team").map_err(bad)?;
+    // teams go up to the map's teams property, or as high as they can if it isn't set yet
+    let teams = int_property(&tiled.properties, "teams").unwrap_or(None).filter(|teams| *teams > 0).unwrap_or(MAX_TEAMS);
+    if let Some(team) = team.filter(|team| *team >= teams) {
+        return Err(bad(format!("team {} is out of range, the map has {} teams", team, teams)));
+    }
+    let inside = |x: f32, y: f32| x >= 0. && y >= 0. && (x / unit) < w as f32 && (y / unit) < h as f32;
+    if !inside(object.x, object.y) {
+        return Err(bad(format!("it's off the map at ({}, {})", object.x, object.y)));
+    }
+
+    if object.object_type() == "region" {
+        let team = team.ok_or_else(|| bad("regions need a team property".to_owned()))?;
+        let region = match property(&object.properties, "region").and_then(|v| v.as_str()) {
+            Some("spawn") => Region::Spawn(team),
+            Some("base") => Region::Base(team),
+            _ => return Err(bad("regions need a region property of spawn or base".to_owned())),
+        };
+        // every tile the rectangle covers, or the tile a point is on
+        let (x0, y0) = ((object.x / unit) as usize, (object.y / unit) as usize);
+        let x1 = (((object.x + object.width) / unit).ceil() as usize).max(x0 + 1).min(w);
+        let y1 = (((object.y + object.height) / unit).ceil() as usize).max(y0 + 1).min(h);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                regions[y * w + x] = Some(region);
+            }
+        }
+        return Ok(());
+    }
+
+    let kind = match object.object_type() {
+        "spawn" => ObjectKind::Spawn,
+        "flag_stand" => ObjectKind::FlagStand,
+        "wall" => ObjectKind::Wall,
+        "decoration" => {
+            let sprite = int_property(&object.properties, "sprite").map_err(bad)?
+                .ok_or_else(|| bad("decorations need a sprite property".to_owned()))?;
+            ObjectKind::Decoration(sprite)
+        },
+        other => return Err(bad(format!("'{}' isn't spawn, flag_stand, wall, decoration or region", other))),
+    };
+    let slot = match property(&object.properties, "slot").and_then(|v| v.as_str()) {
+        Some(name) => *OBJECT_SLOTS.iter()
+            .find(|slot| format!("{:?}", slot) == name)
+            .ok_or_else(|| bad(format!("'{}' isn't an object slot", name)))?,
+        _ => ObjectSlot::C,
+    };
+    // a shape is placed on the tile under its middle
+    let (x, y) = (object.x + object.width / 2., object.y + object.height / 2.);
+    objects.push(MapObject {
+        kind,
+        x: ((x / unit) as usize).min(w - 1),
+        y: ((y / unit) as usize).min(h - 1),
+        slot,
+        team: if kind.has_team() { team } else { None },
+    });
+    Ok(())
+}
+
+// reads a whole number property, which Tiled may have saved as a number or a string
+fn int_property(properties: &[TiledProperty], name: &str) -> Result<Option<usize>, String> {
+    match property(properties, name) {
+        None => Ok(None),
+        Some(Value::Number(n)) => n.as_u64().map(|n| Some(n as usize)).ok_or_else(|| format!("a bad {} property {}", name, n)),
+        Some(Value::String(s)) => s.parse().map(Some).map_err(|_| format!("a bad {} property '{}'", name, s)),
+        Some(other) => Err(format!("a bad {} property {}", name, other)),
+    }
+}
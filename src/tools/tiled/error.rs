@@ -0,0 +1,107 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use crate::resources::{MapLoadError, TerrainPackError};
+
+// errors that can occur while converting between Tiled maps and cap_flag maps
+#[derive(Debug)]
+pub enum TiledError {
+    // a file couldn't be read or written
+    Io { path: PathBuf, source: io::Error },
+    // a .json map isn't valid
+    Json { path: PathBuf, source: serde_json::Error },
+    // a .tmx map isn't valid xml
+    Xml { path: PathBuf, source: xml::reader::Error },
+    // a .tmx map couldn't be written
+    XmlWrite { path: PathBuf, source: xml::writer::Error },
+    // the file is valid, but not a map Tiled would write
+    Malformed { path: PathBuf, msg: String },
+    // only .tmx and .json maps can be converted
+    Format(PathBuf),
+    // the map uses something cap_flag maps can't hold
+    Unsupported(String),
+    // the map has to use exactly one tileset, made from the terrain pack's sprite sheet
+    Tilesets(usize),
+    // the tileset is a different size from the terrain pack's sprite sheet
+    TilesetMismatch { tileset: String, msg: String },
+    // a tile has no terrain in the terrain pack
+    UnknownTile { id: u32, layer: String, x: usize, y: usize },
+    // a tile layer is a different size from the map
+    LayerSize { layer: String, width: usize, height: usize },
+    // no tile layer has a tile at this position
+    MissingTile { x: usize, y: usize },
+    // a layer or the map has a property that can't be read
+    BadProperty { owner: String, msg: String },
+    // an object isn't a spawn, flag stand, wall, decoration or region, or has bad properties
+    BadObject { id: u32, msg: String },
+    // the terrain pack's sprite sheet couldn't be read
+    Sheet { path: PathBuf, msg: String },
+    // the converted map isn't valid
+    Map(MapLoadError),
+    // the map's terrain pack couldn't be loaded
+    Terrain(TerrainPackError),
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TiledError::Io { path, source } =>
+                write!(f, "could not use {}: {}", path.display(), source),
+            TiledError::Json { path, source } =>
+                write!(f, "could not read Tiled map {}: {}", path.display(), source),
+            TiledError::Xml { path, source } =>
+                write!(f, "could not read Tiled map {}: {}", path.display(), source),
+            TiledError::XmlWrite { path, source } =>
+                write!(f, "could not write Tiled map {}: {}", path.display(), source),
+            TiledError::Malformed { path, msg } =>
+                write!(f, "Tiled map {} is malformed: {}", path.display(), msg),
+            TiledError::Format(path) =>
+                write!(f, "{} isn't a .tmx or .json Tiled map", path.display()),
+            TiledError::Unsupported(feature) =>
+                write!(f, "cap_flag maps don't support {}", feature),
+            TiledError::Tilesets(count) =>
+                write!(f, "the map has {} tilesets, but needs exactly one made from the terrain pack", count),
+            TiledError::TilesetMismatch { tileset, msg } =>
+                write!(f, "tileset {} doesn't match the terrain pack: {}", tileset, msg),
+            TiledError::UnknownTile { id, layer, x, y } =>
+                write!(f, "tile {} in layer '{}' at ({}, {}) isn't a terrain sprite of the terrain pack", id, layer, x, y),
+            TiledError::LayerSize { layer, width, height } =>
+                write!(f, "layer '{}' is {}x{}, which isn't the size of the map", layer, width, height),
+            TiledError::MissingTile { x, y } =>
+                write!(f, "no tile layer has a tile at ({}, {})", x, y),
+            TiledError::BadProperty { owner, msg } =>
+                write!(f, "{} has {}", owner, msg),
+            TiledError::BadObject { id, msg } =>
+                write!(f, "object {}: {}", id, msg),
+            TiledError::Sheet { path, msg } =>
+                write!(f, "could not read sprite sheet {}: {}", path.display(), msg),
+            TiledError::Map(e) => e.fmt(f),
+            TiledError::Terrain(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for TiledError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TiledError::Io { source, .. } => Some(source),
+            TiledError::Json { source, .. } => Some(source),
+            TiledError::Xml { source, .. } => Some(source),
+            TiledError::XmlWrite { source, .. } => Some(source),
+            TiledError::Map(e) => Some(e),
+            TiledError::Terrain(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MapLoadError> for TiledError {
+    fn from(e: MapLoadError) -> TiledError {
+        TiledError::Map(e)
+    }
+}
+
+impl From<TerrainPackError> for TiledError {
+    fn from(e: TerrainPackError) -> TiledError {
+        TiledError::Terrain(e)
+    }
+}
@@ -0,0 +1,227 @@
+mod convert;
+mod error;
+mod model;
+mod tmx;
+
+use ron::de::from_str;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use crate::resources::TerrainSet;
+
+pub use self::convert::{map_to_tiled, tiled_to_map, SheetLayout, SheetSprite};
+pub use self::error::TiledError;
+pub use self::model::{property, TiledData, TiledLayer, TiledMap, TiledObject, TiledProperty, TiledTileset};
+pub use self::tmx::{read_tmx, write_tmx};
+
+// reads a Tiled map, as .tmx or .json depending on its extension
+pub fn read_tiled(path: &Path) -> Result<TiledMap, TiledError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("tmx") => read_tmx(path),
+        Some("json") => {
+            let file = File::open(path).map_err(|source| TiledError::Io { path: path.to_owned(), source })?;
+            serde_json::from_reader(BufReader::new(file))
+                .map_err(|source| TiledError::Json { path: path.to_owned(), source })
+        },
+        _ => Err(TiledError::Format(path.to_owned())),
+    }
+}
+
+// writes a Tiled map, as .tmx or .json depending on its extension
+pub fn write_tiled(map: &TiledMap, path: &Path) -> Result<(), TiledError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("tmx") => write_tmx(map, path),
+        Some("json") => {
+            let file = File::create(path).map_err(|source| TiledError::Io { path: path.to_owned(), source })?;
+            serde_json::to_writer_pretty(BufWriter::new(file), map)
+                .map_err(|source| TiledError::Json { path: path.to_owned(), source })
+        },
+        _ => Err(TiledError::Format(path.to_owned())),
+    }
+}
+
+// loads where the sprites of a terrain pack are in its texture
+pub fn load_sheet_layout(terrain: &TerrainSet) -> Result<SheetLayout, TiledError> {
+    use amethyst::utils::application_root_dir;
+
+    let path = application_root_dir()
+        .map(|app_root| app_root.join(format!("resources/terrain/{}.ron", terrain.texture_file)))
+        .unwrap_or_else(|_| PathBuf::from(format!("resources/terrain/{}.ron", terrain.texture_file)));
+    let contents = fs::read_to_string(&path)
+        .map_err(|source| TiledError::Io { path: path.clone(), source })?;
+    from_str(&contents).map_err(|e| TiledError::Sheet { path, msg: e.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::components::{MapObject, ObjectKind, ObjectSlot, Region, Slope, SlopeTiles};
+    use crate::resources::{load_terrain_pack, Map, MapDimensions, TileRecord};
+
+    // the terrain pack, with ramp sprites for grass so slopes survive being turned into tile ids
+    fn terrain() -> TerrainSet {
+        let mut terrain = load_terrain_pack("256color".to_owned()).unwrap();
+        terrain.tiles[0].slope_tiles = SlopeTiles {
+            nw: vec![8, 9, 10, 11],
+            ne: vec![12, 13, 14, 15],
+            se: vec![16, 17, 18, 19],
+            sw: vec![20, 21, 22, 23],
+        };
+        terrain
+    }
+
+    // a 4x3 two team map with elevations, slopes, objects in different slots and regions
+    fn map(terrain: &TerrainSet) -> Map {
+        let (width, height) = (4, 3);
+        let mut records: Vec<TileRecord> = (0..width * height)
+            .map(|i| TileRecord::new(if i % 2 == 0 { 'g' } else { 'd' }, i % 3))
+            .collect();
+        records[4].slope = Some(Slope::NW);
+        records[6].slope = Some(Slope::SE);
+        records[0].region = Some(Region::Spawn(0));
+        records[11].region = Some(Region::Base(1));
+        let objects = vec![
+            MapObject { kind: ObjectKind::FlagStand, x: 1, y: 0, slot: ObjectSlot::C, team: Some(0) },
+            MapObject { kind: ObjectKind::FlagStand, x: 2, y: 2, slot: ObjectSlot::UL, team: Some(1) },
+            MapObject { kind: ObjectKind::Wall, x: 3, y: 1, slot: ObjectSlot::CU, team: None },
+            MapObject { kind: ObjectKind::Decoration(2), x: 0, y: 2, slot: ObjectSlot::CRD, team: None },
+        ];
+        for (i, object) in objects.iter().enumerate() {
+            records[object.y * width + object.x].objects.push(i);
+        }
+        let mut map = Map {
+            dimensions: MapDimensions { width, height },
+            terrain_file: "256color".to_owned(),
+            seed: 7,
+            teams: 2,
+            records,
+            objects,
+            ..Map::default()
+        };
+        map.build_tiles(terrain).unwrap();
+        map
+    }
+
+    // the map as Tiled would have it
+    fn tiled_map() -> TiledMap {
+        let terrain = terrain();
+        let sheet = load_sheet_layout(&terrain).unwrap();
+        map_to_tiled(&map(&terrain), &terrain, &sheet, "256color.png").unwrap()
+    }
+
+    // what converting a Tiled map fails with
+    fn error(tiled: &TiledMap) -> TiledError {
+        let terrain = terrain();
+        let sheet = load_sheet_layout(&terrain).unwrap();
+        match tiled_to_map(tiled, &terrain, "256color", &sheet) {
+            Err(e) => e,
+            Ok(_) => panic!("expected the map not to convert"),
+        }
+    }
+
+    fn objects(tiled: &mut TiledMap, layer: &str) -> &mut Vec<TiledObject> {
+        tiled.layers.iter_mut()
+            .find(|l| l.name == layer)
+            .and_then(|l| l.objects.as_mut())
+            .unwrap()
+    }
+
+    #[test]
+    fn maps_round_trip_through_tmx_and_json() {
+        let terrain = terrain();
+        let sheet = load_sheet_layout(&terrain).unwrap();
+        let map = map(&terrain);
+        let summary = |m: &Map| m.tiles.iter()
+            .flat_map(|row| row.iter())
+            .map(|t| (t.terrain, t.elevation, t.variant, t.slope, t.region))
+            .collect::<Vec<_>>();
+        for extension in ["tmx", "json"].iter() {
+            let path = env::temp_dir().join(format!("cap_flag_{}_round_trip.{}", process::id(), extension));
+            write_tiled(&map_to_tiled(&map, &terrain, &sheet, "256color.png").unwrap(), &path).unwrap();
+            let read = read_tiled(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            let converted = tiled_to_map(&read, &terrain, "256color", &sheet).unwrap();
+            assert_eq!((converted.dimensions.width, converted.dimensions.height), (4, 3), "{}", extension);
+            assert_eq!((converted.teams, converted.seed, converted.auto_slopes), (2, 7, false), "{}", extension);
+            assert_eq!(converted.objects, map.objects, "{}", extension);
+            assert_eq!(summary(&converted), summary(&map), "{}", extension);
+            assert_eq!(converted.tiles[1][0].slope, Slope::NW, "{}", extension);
+            assert_eq!(converted.tiles[2][3].region, Some(Region::Base(1)), "{}", extension);
+            assert_eq!(converted.records[2 * 4 + 2].objects, vec![1], "{}", extension);
+        }
+    }
+
+    #[test]
+    fn flipped_tiles_are_unsupported() {
+        let mut tiled = tiled_map();
+        if let Some(TiledData::Ids(ids)) = &mut tiled.layers[0].data {
+            let i = ids.iter().position(|id| *id != 0).unwrap();
+            ids[i] |= 0x8000_0000;
+        }
+        match error(&tiled) {
+            TiledError::Unsupported(msg) => assert!(msg.contains("flipped"), "{}", msg),
+            other => panic!("expected flipped tiles to be unsupported, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encoded_tile_data_is_unsupported() {
+        let mut tiled = tiled_map();
+        tiled.layers[0].data = Some(TiledData::Encoded("AQAAAA==".to_owned()));
+        tiled.layers[0].encoding = Some("base64".to_owned());
+        match error(&tiled) {
+            TiledError::Unsupported(msg) => assert!(msg.contains("base64 encoded"), "{}", msg),
+            other => panic!("expected encoded data to be unsupported, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn external_tilesets_are_unsupported() {
+        let mut tiled = tiled_map();
+        tiled.tilesets[0].source = Some("256color.tsx".to_owned());
+        match error(&tiled) {
+            TiledError::Unsupported(msg) => assert!(msg.contains("external tileset"), "{}", msg),
+            other => panic!("expected an external tileset to be unsupported, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rotated_objects_are_bad() {
+        let mut tiled = tiled_map();
+        objects(&mut tiled, "objects")[0].rotation = 45.;
+        match error(&tiled) {
+            TiledError::BadObject { id: 1, msg } => assert!(msg.contains("rotated"), "{}", msg),
+            other => panic!("expected a bad object, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn teams_have_to_be_on_the_map() {
+        // a flag stand of a third team on a two team map
+        let mut tiled = tiled_map();
+        let stand = &mut objects(&mut tiled, "objects")[1];
+        stand.properties.retain(|p| p.name != "team");
+        stand.properties.push(TiledProperty::int("team", 2));
+        match error(&tiled) {
+            TiledError::BadObject { id: 2, msg } => assert!(msg.contains("team 2"), "{}", msg),
+            other => panic!("expected a bad object, got {:?}", other),
+        }
+
+        // and a region of one
+        let mut tiled = tiled_map();
+        let region = &mut objects(&mut tiled, "regions")[0];
+        let id = region.id;
+        region.properties.retain(|p| p.name != "team");
+        region.properties.push(TiledProperty::int("team", 5));
+        match error(&tiled) {
+            TiledError::BadObject { id: bad, .. } => assert_eq!(bad, id),
+            other => panic!("expected a bad object, got {:?}", other),
+        }
+    }
+}
\ No newline at end of file
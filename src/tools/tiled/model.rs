@@ -0,0 +1,201 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+// the parts of a Tiled map that can be converted, laid out like Tiled's json format
+// .tmx maps are read into and written from the same structs
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TiledMap {
+    #[serde(rename = "type", default = "map_type")]
+    pub kind: String,
+    pub orientation: String,
+    #[serde(default = "right_down")]
+    pub renderorder: String,
+    pub width: usize,
+    pub height: usize,
+    pub tilewidth: u32,
+    pub tileheight: u32,
+    #[serde(default)]
+    pub infinite: bool,
+    #[serde(default)]
+    pub nextlayerid: u32,
+    #[serde(default)]
+    pub nextobjectid: u32,
+    #[serde(default)]
+    pub layers: Vec<TiledLayer>,
+    #[serde(default)]
+    pub tilesets: Vec<TiledTileset>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<TiledProperty>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TiledLayer {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub width: usize,
+    #[serde(default)]
+    pub height: usize,
+    #[serde(default)]
+    pub offsetx: f32,
+    #[serde(default)]
+    pub offsety: f32,
+    #[serde(default = "visible")]
+    pub visible: bool,
+    #[serde(default = "opaque")]
+    pub opacity: f32,
+    // row by row global tile ids, 0 for no tile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<TiledData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    // only infinite maps have chunks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub objects: Option<Vec<TiledObject>>,
+    // only group layers have layers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layers: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<TiledProperty>,
+}
+
+// tile data is either a list of ids, or a base64 string
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum TiledData {
+    Ids(Vec<u32>),
+    Encoded(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TiledObject {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    // what the object is, called class in some versions of Tiled
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    #[serde(default, skip_serializing)]
+    pub class: String,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub width: f32,
+    #[serde(default)]
+    pub height: f32,
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default = "visible")]
+    pub visible: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub point: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ellipse: bool,
+    // tile objects, shapes, text and templates can't be converted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub polygon: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub polyline: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<TiledProperty>,
+}
+
+impl TiledObject {
+    // the object's type, whichever version of Tiled saved it
+    pub fn object_type(&self) -> &str {
+        if self.kind.is_empty() { &self.class } else { &self.kind }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TiledTileset {
+    pub firstgid: u32,
+    // tilesets saved in their own file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub image: String,
+    #[serde(default)]
+    pub imagewidth: u32,
+    #[serde(default)]
+    pub imageheight: u32,
+    #[serde(default)]
+    pub tilewidth: u32,
+    #[serde(default)]
+    pub tileheight: u32,
+    #[serde(default)]
+    pub tilecount: u32,
+    #[serde(default)]
+    pub columns: u32,
+    #[serde(default)]
+    pub margin: u32,
+    #[serde(default)]
+    pub spacing: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TiledProperty {
+    pub name: String,
+    #[serde(rename = "type", default = "string_type")]
+    pub kind: String,
+    pub value: Value,
+}
+
+impl TiledProperty {
+    pub fn int(name: &str, value: usize) -> TiledProperty {
+        TiledProperty { name: name.to_owned(), kind: "int".to_owned(), value: Value::from(value) }
+    }
+
+    pub fn string(name: &str, value: &str) -> TiledProperty {
+        TiledProperty { name: name.to_owned(), kind: "string".to_owned(), value: Value::from(value) }
+    }
+
+    pub fn bool(name: &str, value: bool) -> TiledProperty {
+        TiledProperty { name: name.to_owned(), kind: "bool".to_owned(), value: Value::from(value) }
+    }
+}
+
+// finds a property by name
+pub fn property<'a>(properties: &'a [TiledProperty], name: &str) -> Option<&'a Value> {
+    properties.iter().find(|p| p.name == name).map(|p| &p.value)
+}
+
+fn map_type() -> String {
+    "map".to_owned()
+}
+
+fn right_down() -> String {
+    "right-down".to_owned()
+}
+
+fn string_type() -> String {
+    "string".to_owned()
+}
+
+fn visible() -> bool {
+    true
+}
+
+fn opaque() -> f32 {
+    1.
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
@@ -0,0 +1,341 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use serde_json::Value;
+use xml::{
+    attribute::OwnedAttribute,
+    reader::{EventReader, XmlEvent as ReadEvent},
+    writer::{EmitterConfig, EventWriter, XmlEvent as WriteEvent},
+};
+
+use super::{TiledData, TiledError, TiledLayer, TiledMap, TiledObject, TiledProperty, TiledTileset};
+
+// what the properties being read belong to
+enum Owner {
+    Map,
+    Layer,
+    Object,
+    Other,
+}
+
+// reads a .tmx map
+pub fn read_tmx(path: &Path) -> Result<TiledMap, TiledError> {
+    let file = File::open(path).map_err(|source| TiledError::Io { path: path.to_owned(), source })?;
+    let malformed = |msg: String| TiledError::Malformed { path: path.to_owned(), msg };
+    let mut map = TiledMap::default();
+    let mut layer: Option<TiledLayer> = None;
+    let mut object: Option<TiledObject> = None;
+    let mut owners = vec![];
+    let mut text = String::new();
+    let mut tile_ids: Vec<u32> = vec![];
+
+    for event in EventReader::new(BufReader::new(file)) {
+        let event = event.map_err(|source| TiledError::Xml { path: path.to_owned(), source })?;
+        match event {
+            ReadEvent::StartElement { name, attributes, .. } => {
+                let attr = |key: &str| attributes.iter().find(|a| a.name.local_name == key).map(|a| a.value.clone());
+                match name.local_name.as_str() {
+                    "map" => {
+                        map.kind = "map".to_owned();
+                        map.orientation = attr("orientation").unwrap_or_default();
+                        map.renderorder = attr("renderorder").unwrap_or_else(|| "right-down".to_owned());
+                        map.width = parse(&attributes, "width").map_err(malformed)?;
+                        map.height = parse(&attributes, "height").map_err(malformed)?;
+                        map.tilewidth = parse(&attributes, "tilewidth").map_err(malformed)?;
+                        map.tileheight = parse(&attributes, "tileheight").map_err(malformed)?;
+                        map.infinite = attr("infinite").map_or(false, |infinite| infinite == "1");
+                        map.nextlayerid = parse_or(&attributes, "nextlayerid", 0).map_err(malformed)?;
+                        map.nextobjectid = parse_or(&attributes, "nextobjectid", 0).map_err(malformed)?;
+                        owners.push(Owner::Map);
+                    },
+                    "tileset" => {
+                        map.tilesets.push(TiledTileset {
+                            firstgid: parse(&attributes, "firstgid").map_err(malformed)?,
+                            source: attr("source"),
+                            name: attr("name").unwrap_or_default(),
+                            tilewidth: parse_or(&attributes, "tilewidth", 0).map_err(malformed)?,
+                            tileheight: parse_or(&attributes, "tileheight", 0).map_err(malformed)?,
+                            tilecount: parse_or(&attributes, "tilecount", 0).map_err(malformed)?,
+                            columns: parse_or(&attributes, "columns", 0).map_err(malformed)?,
+                            margin: parse_or(&attributes, "margin", 0).map_err(malformed)?,
+                            spacing: parse_or(&attributes, "spacing", 0).map_err(malformed)?,
+                            ..TiledTileset::default()
+                        });
+                        owners.push(Owner::Other);
+                    },
+                    "image" => {
+                        // image layers are reported when they end, so only tileset images are kept
+                        if let (Some(tileset), None) = (map.tilesets.last_mut(), &layer) {
+                            tileset.image = attr("source").unwrap_or_default();
+                            tileset.imagewidth = parse_or(&attributes, "width", 0).map_err(malformed)?;
+                            tileset.imageheight = parse_or(&attributes, "height", 0).map_err(malformed)?;
+                        }
+                    },
+                    "group" => {
+                        // kept so converting can report it, its layers are read as if they weren't grouped
+                        map.layers.push(TiledLayer {
+                            kind: "group".to_owned(),
+                            name: attr("name").unwrap_or_default(),
+                            ..TiledLayer::default()
+                        });
+                        owners.push(Owner::Other);
+                    },
+                    "layer" | "objectgroup" | "imagelayer" => {
+                        let kind = match name.local_name.as_str() {
+                            "layer" => "tilelayer",
+                            other => other,
+                        };
+                        layer = Some(TiledLayer {
+                            kind: kind.to_owned(),
+                            id: parse_or(&attributes, "id", 0).map_err(malformed)?,
+                            name: attr("name").unwrap_or_default(),
+                            width: parse_or(&attributes, "width", 0).map_err(malformed)?,
+                            height: parse_or(&attributes, "height", 0).map_err(malformed)?,
+                            offsetx: parse_or(&attributes, "offsetx", 0.).map_err(malformed)?,
+                            offsety: parse_or(&attributes, "offsety", 0.).map_err(malformed)?,
+                            visible: attr("visible").map_or(true, |visible| visible != "0"),
+                            opacity: parse_or(&attributes, "opacity", 1.).map_err(malformed)?,
+                            objects: if kind == "objectgroup" { Some(vec![]) } else { None },
+                            ..TiledLayer::default()
+                        });
+                        owners.push(Owner::Layer);
+                    },
+                    "data" => {
+                        if let Some(layer) = layer.as_mut() {
+                            layer.encoding = attr("encoding");
+                            layer.compression = attr("compression");
+                        }
+                        text.clear();
+                        tile_ids.clear();
+                    },
+                    "tile" => {
+                        // tiles of a layer written without an encoding, tiles of a tileset have ids instead
+                        if layer.is_some() {
+                            tile_ids.push(parse_or(&attributes, "gid", 0).map_err(malformed)?);
+                        }
+                        owners.push(Owner::Other);
+                    },
+                    "chunk" => {
+                        if let Some(layer) = layer.as_mut() {
+                            layer.chunks = Some(Value::Bool(true));
+                        }
+                    },
+                    "object" => {
+                        object = Some(TiledObject {
+                            id: parse(&attributes, "id").map_err(malformed)?,
+                            name: attr("name").unwrap_or_default(),
+                            kind: attr("type").unwrap_or_default(),
+                            class: attr("class").unwrap_or_default(),
+                            x: parse_or(&attributes, "x", 0.).map_err(malformed)?,
+                            y: parse_or(&attributes, "y", 0.).map_err(malformed)?,
+                            width: parse_or(&attributes, "width", 0.).map_err(malformed)?,
+                            height: parse_or(&attributes, "height", 0.).map_err(malformed)?,
+                            rotation: parse_or(&attributes, "rotation", 0.).map_err(malformed)?,
+                            visible: attr("visible").map_or(true, |visible| visible != "0"),
+                            gid: attr("gid").map(|gid| gid.parse()).transpose().map_err(|_| malformed("bad gid".to_owned()))?,
+                            template: attr("template"),
+                            ..TiledObject::default()
+                        });
+                        owners.push(Owner::Object);
+                    },
+                    "point" | "ellipse" | "polygon" | "polyline" | "text" => {
+                        if let Some(object) = object.as_mut() {
+                            match name.local_name.as_str() {
+                                "point" => object.point = true,
+                                "ellipse" => object.ellipse = true,
+                                "polygon" => object.polygon = Some(Value::Bool(true)),
+                                "polyline" => object.polyline = Some(Value::Bool(true)),
+                                _ => object.text = Some(Value::Bool(true)),
+                            }
+                        }
+                    },
+                    "property" => {
+                        let kind = attr("type").unwrap_or_else(|| "string".to_owned());
+                        let raw = attr("value").unwrap_or_default();
+                        let value = match kind.as_str() {
+                            "int" => raw.parse::<i64>().map(Value::from).map_err(|_| malformed(format!("bad int property '{}'", raw)))?,
+                            "float" => raw.parse::<f64>().map(Value::from).map_err(|_| malformed(format!("bad float property '{}'", raw)))?,
+                            "bool" => Value::Bool(raw == "true"),
+                            _ => Value::String(raw),
+                        };
+                        let property = TiledProperty { name: attr("name").unwrap_or_default(), kind, value };
+                        match owners.last() {
+                            Some(Owner::Map) => map.properties.push(property),
+                            Some(Owner::Layer) => if let Some(layer) = layer.as_mut() { layer.properties.push(property) },
+                            Some(Owner::Object) => if let Some(object) = object.as_mut() { object.properties.push(property) },
+                            _ => {},
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            ReadEvent::Characters(chars) => text.push_str(&chars),
+            ReadEvent::EndElement { name } => match name.local_name.as_str() {
+                "data" => {
+                    if let Some(layer) = layer.as_mut() {
+                        layer.data = Some(match layer.encoding.as_ref().map(|e| e.as_str()) {
+                            Some("csv") => TiledData::Ids(text.split(',')
+                                .map(|id| id.trim())
+                                .filter(|id| !id.is_empty())
+                                .map(|id| id.parse().map_err(|_| malformed(format!("bad tile id '{}' in layer '{}'", id, layer.name))))
+                                .collect::<Result<Vec<u32>, TiledError>>()?),
+                            Some(_) => TiledData::Encoded(text.trim().to_owned()),
+                            None => TiledData::Ids(tile_ids.clone()),
+                        });
+                    }
+                },
+                "object" => {
+                    if let (Some(object), Some(layer)) = (object.take(), layer.as_mut()) {
+                        layer.objects.get_or_insert_with(Vec::new).push(object);
+                    }
+                    owners.pop();
+                },
+                "layer" | "objectgroup" | "imagelayer" => {
+                    if let Some(layer) = layer.take() {
+                        map.layers.push(layer);
+                    }
+                    owners.pop();
+                },
+                "map" | "group" | "tileset" | "tile" => {
+                    owners.pop();
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+    Ok(map)
+}
+
+// writes a .tmx map, with its tiles as csv
+pub fn write_tmx(map: &TiledMap, path: &Path) -> Result<(), TiledError> {
+    let file = File::create(path).map_err(|source| TiledError::Io { path: path.to_owned(), source })?;
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(BufWriter::new(file));
+    write_map(&mut writer, map).map_err(|source| TiledError::XmlWrite { path: path.to_owned(), source })
+}
+
+fn write_map<W: Write>(writer: &mut EventWriter<W>, map: &TiledMap) -> Result<(), xml::writer::Error> {
+    start(writer, "map", &[
+        ("version", "1.2".to_owned()),
+        ("orientation", map.orientation.clone()),
+        ("renderorder", map.renderorder.clone()),
+        ("width", map.width.to_string()),
+        ("height", map.height.to_string()),
+        ("tilewidth", map.tilewidth.to_string()),
+        ("tileheight", map.tileheight.to_string()),
+        ("infinite", if map.infinite { "1" } else { "0" }.to_owned()),
+        ("nextlayerid", map.nextlayerid.to_string()),
+        ("nextobjectid", map.nextobjectid.to_string()),
+    ])?;
+    write_properties(writer, &map.properties)?;
+    for tileset in map.tilesets.iter() {
+        start(writer, "tileset", &[
+            ("firstgid", tileset.firstgid.to_string()),
+            ("name", tileset.name.clone()),
+            ("tilewidth", tileset.tilewidth.to_string()),
+            ("tileheight", tileset.tileheight.to_string()),
+            ("tilecount", tileset.tilecount.to_string()),
+            ("columns", tileset.columns.to_string()),
+        ])?;
+        start(writer, "image", &[
+            ("source", tileset.image.clone()),
+            ("width", tileset.imagewidth.to_string()),
+            ("height", tileset.imageheight.to_string()),
+        ])?;
+        writer.write(WriteEvent::end_element())?;
+        writer.write(WriteEvent::end_element())?;
+    }
+    for layer in map.layers.iter() {
+        let element = if layer.kind == "objectgroup" { "objectgroup" } else { "layer" };
+        let mut attributes = vec![("id", layer.id.to_string()), ("name", layer.name.clone())];
+        if element == "layer" {
+            attributes.push(("width", layer.width.to_string()));
+            attributes.push(("height", layer.height.to_string()));
+        }
+        if layer.offsety != 0. {
+            attributes.push(("offsety", layer.offsety.to_string()));
+        }
+        start(writer, element, &attributes)?;
+        write_properties(writer, &layer.properties)?;
+        if let Some(TiledData::Ids(ids)) = &layer.data {
+            start(writer, "data", &[("encoding", "csv".to_owned())])?;
+            let rows: Vec<String> = ids.chunks(layer.width.max(1))
+                .map(|row| row.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(","))
+                .collect();
+            writer.write(WriteEvent::characters(&format!("\n{}\n", rows.join(",\n"))))?;
+            writer.write(WriteEvent::end_element())?;
+        }
+        for object in layer.objects.iter().flat_map(|objects| objects.iter()) {
+            let mut attributes = vec![
+                ("id", object.id.to_string()),
+                ("type", object.kind.clone()),
+                ("x", object.x.to_string()),
+                ("y", object.y.to_string()),
+            ];
+            if !object.point {
+                attributes.push(("width", object.width.to_string()));
+                attributes.push(("height", object.height.to_string()));
+            }
+            start(writer, "object", &attributes)?;
+            write_properties(writer, &object.properties)?;
+            if object.point {
+                start(writer, "point", &[])?;
+                writer.write(WriteEvent::end_element())?;
+            }
+            writer.write(WriteEvent::end_element())?;
+        }
+        writer.write(WriteEvent::end_element())?;
+    }
+    writer.write(WriteEvent::end_element())
+}
+
+fn write_properties<W: Write>(writer: &mut EventWriter<W>, properties: &[TiledProperty]) -> Result<(), xml::writer::Error> {
+    if properties.is_empty() {
+        return Ok(());
+    }
+    start(writer, "properties", &[])?;
+    for property in properties.iter() {
+        let value = match &property.value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        start(writer, "property", &[
+            ("name", property.name.clone()),
+            ("type", property.kind.clone()),
+            ("value", value),
+        ])?;
+        writer.write(WriteEvent::end_element())?;
+    }
+    writer.write(WriteEvent::end_element())
+}
+
+// starts an element with some attributes
+fn start<W: Write>(writer: &mut EventWriter<W>, name: &str, attributes: &[(&str, String)]) -> Result<(), xml::writer::Error> {
+    let mut element = WriteEvent::start_element(name);
+    for (key, value) in attributes.iter() {
+        element = element.attr(*key, value.as_str());
+    }
+    writer.write(element)
+}
+
+// reads a required attribute
+fn parse<T: FromStr>(attributes: &[OwnedAttribute], key: &str) -> Result<T, String> {
+    let value = attributes.iter()
+        .find(|a| a.name.local_name == key)
+        .ok_or_else(|| format!("missing attribute '{}'", key))?;
+    value.value.parse().map_err(|_| format!("bad value '{}' for attribute '{}'", value.value, key))
+}
+
+// reads an optional attribute
+fn parse_or<T: FromStr>(attributes: &[OwnedAttribute], key: &str, default: T) -> Result<T, String> {
+    match attributes.iter().find(|a| a.name.local_name == key) {
+        Some(value) => value.value.parse().map_err(|_| format!("bad value '{}' for attribute '{}'", value.value, key)),
+        _ => Ok(default),
+    }
+}
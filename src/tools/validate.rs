@@ -0,0 +1,390 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use crate::components::{ObjectKind, Region};
+use crate::resources::{Map, TerrainSet};
+use crate::util::{can_cross, connected_areas, path_costs, NavGrid, PathOptions};
+
+// how much longer one team's route can be than another team's before it's reported
+pub const ASYMMETRY_TOLERANCE: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    // the map can't be played
+    Error,
+    // the map can be played, but probably shouldn't be
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteKind {
+    // from a team's spawn area to the closest enemy flag
+    SpawnToFlag,
+    // from the closest enemy flag back to the team's own flag stand
+    FlagToBase,
+}
+
+impl fmt::Display for RouteKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteKind::SpawnToFlag => write!(f, "the route from spawn to enemy flag"),
+            RouteKind::FlagToBase => write!(f, "the route from enemy flag to base"),
+        }
+    }
+}
+
+// a problem found with a map
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    // the teams, flag stands or spawns aren't set up
+    Setup(String),
+    // some of a team's spawn tiles can't reach an enemy flag
+    UnreachableFlag { team: usize, enemy: usize, spawns: usize, example: (usize, usize) },
+    // one team's route costs a lot more than another team's
+    Asymmetry { route: RouteKind, shortest: (usize, f32), longest: (usize, f32) },
+    // walkable tiles that can't be reached from the spawns
+    Island { size: usize, example: (usize, usize) },
+    // neighbouring walkable tiles too far apart in elevation to move between
+    Cliff { a: (usize, usize), b: (usize, usize), height: usize },
+}
+
+impl Issue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Issue::Setup(_) | Issue::UnreachableFlag { .. } => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Setup(msg) => write!(f, "{}", msg),
+            Issue::UnreachableFlag { team, enemy, spawns, example } =>
+                write!(f, "{} spawn tiles of team {} can't reach the flag of team {}, like ({}, {})", spawns, team, enemy, example.0, example.1),
+            Issue::Asymmetry { route, shortest, longest } =>
+                write!(f, "{} costs {:.1} for team {}, but only {:.1} for team {}", route, longest.1, longest.0, shortest.1, shortest.0),
+            Issue::Island { size, example } =>
+                write!(f, "{} walkable tiles around ({}, {}) can't be reached", size, example.0, example.1),
+            Issue::Cliff { a, b, height } =>
+                write!(f, "a cliff {} levels high between ({}, {}) and ({}, {})", height, a.0, a.1, b.0, b.1),
+        }
+    }
+}
+
+// how much it costs a team to move along its routes, if it can at all
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeamRoutes {
+    pub team: usize,
+    pub spawn_to_flag: Option<f32>,
+    pub flag_to_base: Option<f32>,
+}
+
+impl TeamRoutes {
+    pub fn cost(&self, route: RouteKind) -> Option<f32> {
+        match route {
+            RouteKind::SpawnToFlag => self.spawn_to_flag,
+            RouteKind::FlagToBase => self.flag_to_base,
+        }
+    }
+}
+
+// everything the validator found out about a map
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub routes: Vec<TeamRoutes>,
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn errors(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.severity() == Severity::Error).count()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.severity() == Severity::Warning).count()
+    }
+
+    // checks if nothing stops the map from being played
+    pub fn is_playable(&self) -> bool {
+        self.errors() == 0
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cost = |cost: Option<f32>| cost.map_or("unreachable".to_owned(), |c| format!("{:.1}", c));
+        for routes in self.routes.iter() {
+            writeln!(f, "team {}: spawn to flag {}, flag to base {}", routes.team, cost(routes.spawn_to_flag), cost(routes.flag_to_base))?;
+        }
+        for issue in self.issues.iter() {
+            let severity = match issue.severity() {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            writeln!(f, "{}: {}", severity, issue)?;
+        }
+        write!(f, "{} errors, {} warnings", self.errors(), self.warnings())
+    }
+}
+
+// checks that a map is playable and fair, using the pathfinding rules units move by
+// the map's tiles have to be built first
+pub fn validate_map(map: &Map, terrain: &TerrainSet, options: &PathOptions) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    if let Err(e) = map.validate_teams(terrain) {
+        report.issues.push(Issue::Setup(e.to_string()));
+    }
+    let mut grid = match NavGrid::from_tiles(&map.tiles, terrain) {
+        Ok(grid) => grid,
+        Err(e) => {
+            report.issues.push(Issue::Setup(e.to_string()));
+            return report;
+        },
+    };
+    for object in map.objects.iter().filter(|object| object.kind.blocks_movement()) {
+        grid.block(object.x, object.y);
+    }
+    let (w, h) = (grid.width, grid.height);
+    let index = |(x, y): (usize, usize)| y * w + x;
+
+    // the spawn tiles and flag stand of each team
+    let spawns: Vec<Vec<(usize, usize)>> = (0..map.teams)
+        .map(|team| map.tiles.iter()
+            .flat_map(|row| row.iter())
+            .filter(|tile| tile.region == Some(Region::Spawn(team)))
+            .map(|tile| (tile.x, tile.y))
+            .collect())
+        .collect();
+    let stands: Vec<Option<(usize, usize)>> = (0..map.teams)
+        .map(|team| map.objects.iter()
+            .find(|o| o.kind == ObjectKind::FlagStand && o.team == Some(team))
+            .map(|o| (o.x, o.y)))
+        .collect();
+
+    // every spawn tile has to be able to reach every enemy flag
+    let areas = connected_areas(&grid, options);
+    for team in 0..map.teams {
+        for enemy in (0..map.teams).filter(|enemy| *enemy != team) {
+            let stand = match stands[enemy] {
+                Some(stand) => stand,
+                _ => continue,
+            };
+            let stuck: Vec<(usize, usize)> = spawns[team].iter()
+                .filter(|spawn| areas[index(**spawn)].is_none() || areas[index(**spawn)] != areas[index(stand)])
+                .cloned()
+                .collect();
+            if let Some(example) = stuck.first() {
+                report.issues.push(Issue::UnreachableFlag { team, enemy, spawns: stuck.len(), example: *example });
+            }
+        }
+    }
+
+    // the routes of each team should cost about the same
+    for team in 0..map.teams {
+        let enemy_stands: Vec<(usize, usize)> = (0..map.teams)
+            .filter(|enemy| *enemy != team)
+            .filter_map(|enemy| stands[enemy])
+            .collect();
+        let spawn_to_flag = if spawns[team].is_empty() {
+            None
+        } else {
+            let costs = path_costs(&grid, &spawns[team], options);
+            enemy_stands.iter()
+                .filter_map(|stand| costs[index(*stand)])
+                .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        };
+        let flag_to_base = stands[team]
+            .and_then(|stand| path_costs(&grid, &enemy_stands, options)[index(stand)]);
+        report.routes.push(TeamRoutes { team, spawn_to_flag, flag_to_base });
+    }
+    for route in [RouteKind::SpawnToFlag, RouteKind::FlagToBase].iter() {
+        let costs: Vec<(usize, f32)> = report.routes.iter()
+            .filter_map(|routes| routes.cost(*route).map(|cost| (routes.team, cost)))
+            .collect();
+        let shortest = costs.iter().cloned().min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        let longest = costs.iter().cloned().max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        if let (Some(shortest), Some(longest)) = (shortest, longest) {
+            if longest.1 > shortest.1 * (1. + ASYMMETRY_TOLERANCE) {
+                report.issues.push(Issue::Asymmetry { route: *route, shortest, longest });
+            }
+        }
+    }
+
+    // walkable tiles outside the areas the spawns are in are islands
+    // maps without spawns yet are measured from their largest area
+    let mut sizes: BTreeMap<usize, (usize, (usize, usize))> = BTreeMap::new();
+    for (i, area) in areas.iter().enumerate() {
+        if let Some(area) = area {
+            sizes.entry(*area).or_insert((0, (i % w, i / w))).0 += 1;
+        }
+    }
+    let mut reached: HashSet<usize> = spawns.iter()
+        .flat_map(|team| team.iter())
+        .filter_map(|spawn| areas[index(*spawn)])
+        .collect();
+    if reached.is_empty() {
+        if let Some((area, _)) = sizes.iter().max_by_key(|(_, (size, _))| *size) {
+            reached.insert(*area);
+        }
+    }
+    for (area, (size, example)) in sizes.into_iter() {
+        if !reached.contains(&area) {
+            report.issues.push(Issue::Island { size, example });
+        }
+    }
+
+    // steps between walkable tiles that are too high to climb
+    for y in 0..h {
+        for x in 0..w {
+            for (nx, ny) in [(x + 1, y), (x, y + 1)].iter().cloned() {
+                if nx >= w || ny >= h {
+                    continue;
+                }
+                let (a, b) = (grid.cell(x, y), grid.cell(nx, ny));
+                if a.cost.is_none() || b.cost.is_none() || can_cross(&grid, (x, y), (nx, ny), options) {
+                    continue;
+                }
+                let height = if a.elevation > b.elevation { a.elevation - b.elevation } else { b.elevation - a.elevation };
+                report.issues.push(Issue::Cliff { a: (x, y), b: (nx, ny), height });
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{MapObject, ObjectSlot};
+    use crate::resources::{load_terrain_pack, MapDimensions, TileRecord};
+
+    fn terrain() -> TerrainSet {
+        load_terrain_pack("256color".to_owned()).unwrap()
+    }
+
+    // a two team map of grass from rows of tiles: a digit is the tile's elevation, # is a wall,
+    // s and S are spawn tiles of teams 0 and 1, f and F their flag stands, all at elevation 0
+    fn map(rows: &[&str], terrain: &TerrainSet) -> Map {
+        let (width, height) = (rows[0].len(), rows.len());
+        let mut records = vec![];
+        let mut objects = vec![];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let mut record = TileRecord::new('g', c.to_digit(10).unwrap_or(0) as usize);
+                let placed = match c {
+                    '#' => Some((ObjectKind::Wall, None)),
+                    'f' => Some((ObjectKind::FlagStand, Some(0))),
+                    'F' => Some((ObjectKind::FlagStand, Some(1))),
+                    _ => None,
+                };
+                if let Some((kind, team)) = placed {
+                    record.objects.push(objects.len());
+                    objects.push(MapObject { kind, x, y, slot: ObjectSlot::C, team });
+                }
+                record.region = match c {
+                    's' => Some(Region::Spawn(0)),
+                    'S' => Some(Region::Spawn(1)),
+                    _ => None,
+                };
+                records.push(record);
+            }
+        }
+        let mut map = Map {
+            dimensions: MapDimensions { width, height },
+            terrain_file: "256color".to_owned(),
+            teams: 2,
+            records,
+            objects,
+            ..Map::default()
+        };
+        map.build_tiles(terrain).unwrap();
+        map
+    }
+
+    fn validate(rows: &[&str]) -> ValidationReport {
+        let terrain = terrain();
+        validate_map(&map(rows, &terrain), &terrain, &PathOptions::default())
+    }
+
+    #[test]
+    fn fair_maps_have_no_issues() {
+        let report = validate(&["fs0SF"]);
+        assert_eq!(report.issues, vec![]);
+        assert!(report.is_playable());
+        assert_eq!(report.routes, vec![
+            TeamRoutes { team: 0, spawn_to_flag: Some(3.), flag_to_base: Some(4.) },
+            TeamRoutes { team: 1, spawn_to_flag: Some(3.), flag_to_base: Some(4.) },
+        ]);
+    }
+
+    #[test]
+    fn missing_flag_stands_are_setup_errors() {
+        let report = validate(&["fs0S0"]);
+        assert_eq!(report.issues.len(), 1);
+        match &report.issues[0] {
+            Issue::Setup(_) => {},
+            other => panic!("expected a setup issue, got {:?}", other),
+        }
+        assert!(!report.is_playable());
+        // the team without a stand can still reach the other team's flag
+        assert_eq!(report.routes[1].spawn_to_flag, Some(3.));
+        assert_eq!(report.routes[0].spawn_to_flag, None);
+    }
+
+    #[test]
+    fn walled_off_flags_are_unreachable() {
+        let report = validate(&["fs#SF"]);
+        assert_eq!(report.issues, vec![
+            Issue::UnreachableFlag { team: 0, enemy: 1, spawns: 1, example: (1, 0) },
+            Issue::UnreachableFlag { team: 1, enemy: 0, spawns: 1, example: (3, 0) },
+        ]);
+        assert!(!report.is_playable());
+        assert!(report.routes.iter().all(|routes| routes.spawn_to_flag.is_none() && routes.flag_to_base.is_none()));
+    }
+
+    #[test]
+    fn uneven_routes_are_reported_past_the_tolerance() {
+        // 10 and 11 tiles from spawn to flag is within the tolerance
+        let report = validate(&["f0s00000000SF"]);
+        assert_eq!(report.routes[0].spawn_to_flag, Some(10.));
+        assert_eq!(report.routes[1].spawn_to_flag, Some(11.));
+        assert_eq!(report.issues, vec![]);
+
+        // 9 and 11 isn't
+        let report = validate(&["f00s0000000SF"]);
+        assert_eq!(report.issues, vec![
+            Issue::Asymmetry { route: RouteKind::SpawnToFlag, shortest: (0, 9.), longest: (1, 11.) },
+        ]);
+        assert_eq!(report.warnings(), 1);
+        assert!(report.is_playable());
+    }
+
+    #[test]
+    fn unreachable_ground_is_an_island() {
+        let report = validate(&[
+            "fs0SF",
+            "#####",
+            "00###",
+        ]);
+        assert_eq!(report.issues, vec![Issue::Island { size: 2, example: (0, 2) }]);
+        assert!(report.is_playable());
+    }
+
+    #[test]
+    fn steps_too_high_to_climb_are_cliffs() {
+        let report = validate(&[
+            "fs0SF",
+            "00120",
+        ]);
+        assert_eq!(report.issues, vec![
+            Issue::Cliff { a: (3, 0), b: (3, 1), height: 2 },
+            Issue::Cliff { a: (3, 1), b: (4, 1), height: 2 },
+        ]);
+        assert!(report.is_playable());
+    }
+}
@@ -5,14 +5,21 @@ use amethyst::{
     },
     ecs::{
         Join,
-        prelude::WriteStorage
+        storage::{MaskedStorage, Storage},
     },
     renderer::camera::Camera,
     window::ScreenDimensions,
 };
-use crate::components::Tile;
+use std::{collections::VecDeque, ops::Deref};
+use crate::components::{MapObject, Tile};
 use crate::resources::{MapDimensions, TileMap};
 
+mod pathfinding;
+mod slope;
+
+pub use self::pathfinding::{can_cross, connected_areas, find_path, path_costs, NavCell, NavGrid, Neighbourhood, PathOptions};
+pub use self::slope::{infer_slope, infer_slopes, slope_lift};
+
 pub const TILE_SIZE: f32 = 64.;
 pub const HALF_TILE: f32 = 32.;
 pub const QUARTER_TILE: f32 = 16.;
@@ -41,7 +48,8 @@ pub fn mouse_to_map_iso_simple(mouse_x: f32, mouse_y: f32, screen_dim: &ScreenDi
 }
 
 // takes mouse coordinates and converts them to map coordinates, taking elevation into account
-pub fn mouse_to_map_iso(mouse_x: f32, mouse_y: f32, map_dim: &MapDimensions, screen_dim: &ScreenDimensions, camera: &Camera, camera_transform: &Transform, tile_map: &TileMap, tiles: &WriteStorage<Tile>) -> Option<(usize, usize)> {
+pub fn mouse_to_map_iso<D>(mouse_x: f32, mouse_y: f32, map_dim: &MapDimensions, screen_dim: &ScreenDimensions, camera: &Camera, camera_transform: &Transform, tile_map: &TileMap, tiles: &Storage<Tile, D>) -> Option<(usize, usize)>
+    where D: Deref<Target = MaskedStorage<Tile>> {
     let diagonal = Vector2::new(screen_dim.width(), screen_dim.height());
     let world_point = camera.projection().screen_to_world_point(
         Point3::new(mouse_x, mouse_y, 0.),
@@ -65,6 +73,46 @@ pub fn map_to_world_iso(map_x: f32, map_y: f32, elevation: f32, height: f32) ->
     (world_x, world_y)
 }
 
+// finds the translation of a tile entity, taking elevation into account
+pub fn tile_translation(tile: &Tile) -> (f32, f32, f32) {
+    let (world_x, world_y) = map_to_world_iso(tile.x as f32, tile.y as f32, tile.surface_elevation(), tile.height as f32);
+    // reverse y to put origin at top of map
+    (world_x, -world_y, z_value_iso(tile.x as f32, tile.y as f32, 0., TileLayer::Base))
+}
+
+// finds the translation of a tile's ui element, taking elevation into account
+pub fn tile_ui_translation(tile_x: usize, tile_y: usize, tile_elevation: f32) -> (f32, f32, f32) {
+    let (world_x, world_y) = map_to_world_iso(tile_x as f32, tile_y as f32, tile_elevation, TILE_SIZE * 0.25);
+    (world_x, -world_y, z_value_iso(tile_x as f32, tile_y as f32, 0., TileLayer::BaseUI))
+}
+
+// finds the translation of an object in its slot on a tile
+pub fn object_translation(object: &MapObject, tile_elevation: f32) -> (f32, f32, f32) {
+    let (across, down) = object.slot.offset();
+    let map_x = object.x as f32 + (down + across) * 0.5;
+    let map_y = object.y as f32 + (down - across) * 0.5;
+    let (world_x, world_y) = map_to_world_iso(map_x, map_y, tile_elevation, QUARTER_TILE);
+    // sorted by the tile rather than the slot position, so objects stay between their tile and the tiles in front
+    (world_x, -world_y, z_value_iso(object.x as f32, object.y as f32, 0., object.slot.layer()))
+}
+
+// the colour a team is drawn in
+pub fn team_colour(team: usize) -> [f32; 4] {
+    match team % 4 {
+        0 => [1., 0.3, 0.3, 1.],
+        1 => [0.3, 0.5, 1., 1.],
+        2 => [0.3, 1., 0.4, 1.],
+        _ => [1., 0.9, 0.3, 1.],
+    }
+}
+
+// finds the translation of a unit standing in the middle of a tile, or between two tiles while moving
+pub fn unit_translation(map_x: f32, map_y: f32, elevation: f32) -> (f32, f32, f32) {
+    // the unit's sprite is centered half a tile above its feet
+    let (world_x, world_y) = map_to_world_iso(map_x, map_y, elevation, QUARTER_TILE - HALF_TILE);
+    (world_x, -world_y, z_value_iso(map_x, map_y, 0., TileLayer::ObjectC))
+}
+
 // takes world coordinates and converts them into base map coordinates
 pub fn world_to_map_iso_simple(world_x: f32, world_y: f32) -> (f32, f32) {
     let map_x = (world_x / (HALF_TILE) + world_y / (QUARTER_TILE)) * 0.5;
@@ -73,7 +121,8 @@ pub fn world_to_map_iso_simple(world_x: f32, world_y: f32) -> (f32, f32) {
 }
 
 // takes world coordinates and converts them into map coordinates, taking elevation into account
-pub fn world_to_map_iso(world_x: f32, world_y: f32, width: usize, height: usize, tile_map: &TileMap, tiles: &WriteStorage<Tile>) -> Option<(usize, usize)> {
+pub fn world_to_map_iso<D>(world_x: f32, world_y: f32, width: usize, height: usize, tile_map: &TileMap, tiles: &Storage<Tile, D>) -> Option<(usize, usize)>
+    where D: Deref<Target = MaskedStorage<Tile>> {
     // get map coords of base tile
     let (base_x_f, base_y_f) = world_to_map_iso_simple(world_x, world_y - HALF_TILE);
     let (mut base_x, mut base_y) = (base_x_f.floor() as isize, base_y_f.floor() as isize);
@@ -108,14 +157,16 @@ pub fn world_to_map_iso(world_x: f32, world_y: f32, width: usize, height: usize,
     for (x, y, is_left) in tiles_in_column.into_iter() {
         let entity = tile_map.get(x, y, width);
         if let Some(tile) = tiles.get(entity) {
-            let (tile_world_x, tile_world_y) = map_to_world_iso(tile.x as f32, tile.y as f32, tile.elevation as f32, tile.height as f32 / 2.);
+            let (tile_world_x, tile_world_y) = map_to_world_iso(tile.x as f32, tile.y as f32, tile.surface_elevation(), tile.height as f32 / 2.);
             let (above, below) = match is_left {
                 true => (0.5, -0.5),
                 false => (-0.5, 0.5),
             };
             let b = HALF_TILE;
             let test_x = world_x - tile_world_x;
-            let test_y = world_y - tile_world_y;if test_y > below * test_x && test_y <= above * test_x + b {
+            // the top face of a sloped tile is slanted, so flatten it out before testing
+            let test_y = world_y - tile_world_y + slope_lift(tile.slope, test_x, world_y - tile_world_y - QUARTER_TILE);
+            if test_y > below * test_x && test_y <= above * test_x + b {
                 return Some((tile.x, tile.y));
             }
         }
@@ -123,11 +174,60 @@ pub fn world_to_map_iso(world_x: f32, world_y: f32, width: usize, height: usize,
     None
 }
 
+// mixes a seed and a map coordinate into a well distributed number (splitmix64)
+pub fn tile_hash(seed: u64, x: usize, y: usize) -> u64 {
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^ (h >> 31)
+}
+
 // checks if a tile exists
 pub fn tile_exists(x: isize, y: isize, width: isize, height: isize) -> bool {
     return x >= 0 && y >= 0 && x < width && y < height;
 }
 
+// finds the tiles connected to (x,y) on the grid (not diagonally) that match, including (x,y)
+pub fn flood_fill<F>(x: usize, y: usize, width: usize, height: usize, matches: F) -> Vec<(usize, usize)>
+    where F: Fn(usize, usize) -> bool {
+    let mut found = vec![];
+    if x >= width || y >= height || !matches(x, y) {
+        return found;
+    }
+    let mut visited = vec![false; width * height];
+    let mut queue = VecDeque::new();
+    visited[y * width + x] = true;
+    queue.push_back((x, y));
+    while let Some((cx, cy)) = queue.pop_front() {
+        found.push((cx, cy));
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+            let (nx, ny) = (cx as isize + dx, cy as isize + dy);
+            if !tile_exists(nx, ny, width as isize, height as isize) {
+                continue;
+            }
+            let (nx, ny) = (nx as usize, ny as usize);
+            if !visited[ny * width + nx] {
+                visited[ny * width + nx] = true;
+                if matches(nx, ny) {
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+    }
+    found
+}
+
+// finds every tile in the box with corners a and b
+pub fn rect_area(a: (usize, usize), b: (usize, usize)) -> Vec<(usize, usize)> {
+    let (min_x, max_x) = (a.0.min(b.0), a.0.max(b.0));
+    let (min_y, max_y) = (a.1.min(b.1), a.1.max(b.1));
+    (min_y..=max_y)
+        .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+        .collect()
+}
+
 // finds the closest map coordinates when outside the map
 pub fn closest_point_in_map_iso(map_x: f32, map_y: f32, w: f32, h: f32, e: f32) -> (f32, f32) {
     let origin = e * -1.;
@@ -184,4 +284,69 @@ pub fn tile_layer_value(layer: TileLayer) -> f32 {
         TileLayer::ObjectDL | TileLayer::ObjectDR | TileLayer::ObjectCD => 0.4,
         TileLayer::ObjectUI => 0.45,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a map of terrain chars, and one of elevation digits, with a row per string
+    fn grid(rows: &[&str]) -> (usize, usize, Vec<char>) {
+        (rows[0].len(), rows.len(), rows.iter().flat_map(|row| row.chars()).collect())
+    }
+
+    fn sorted(mut tiles: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        tiles.sort_by_key(|(x, y)| (*y, *x));
+        tiles
+    }
+
+    #[test]
+    fn fills_stop_at_other_terrain() {
+        let (w, h, terrain) = grid(&[
+            "ggdg",
+            "gdgg",
+            "ggdd",
+        ]);
+        let fill = flood_fill(0, 0, w, h, |x, y| terrain[y * w + x] == 'g');
+        assert_eq!(sorted(fill), vec![(0, 0), (1, 0), (0, 1), (0, 2), (1, 2)]);
+        // tiles only touching at a corner aren't filled
+        let fill = flood_fill(2, 1, w, h, |x, y| terrain[y * w + x] == 'g');
+        assert_eq!(sorted(fill), vec![(3, 0), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn fills_stop_at_other_elevations() {
+        let (w, h, terrain) = grid(&[
+            "gggg",
+            "gggg",
+        ]);
+        let (_, _, elevation) = grid(&[
+            "0011",
+            "0101",
+        ]);
+        let matches = |x: usize, y: usize| terrain[y * w + x] == 'g' && elevation[y * w + x] == '0';
+        assert_eq!(sorted(flood_fill(0, 0, w, h, matches)), vec![(0, 0), (1, 0), (0, 1)]);
+        assert_eq!(flood_fill(2, 1, w, h, matches), vec![(2, 1)]);
+        // a tile that doesn't match fills nothing
+        assert!(flood_fill(3, 0, w, h, matches).is_empty());
+    }
+
+    #[test]
+    fn fills_reach_the_edges_of_the_map() {
+        let fill = flood_fill(2, 1, 3, 2, |_, _| true);
+        assert_eq!(sorted(fill), rect_area((0, 0), (2, 1)));
+        // starting off the map fills nothing
+        assert!(flood_fill(3, 0, 3, 2, |_, _| true).is_empty());
+        assert!(flood_fill(0, 2, 3, 2, |_, _| true).is_empty());
+    }
+
+    #[test]
+    fn rects_work_from_any_corner() {
+        let expected = vec![(1, 2), (2, 2), (3, 2), (1, 3), (2, 3), (3, 3)];
+        assert_eq!(rect_area((1, 2), (3, 3)), expected);
+        assert_eq!(rect_area((3, 3), (1, 2)), expected);
+        assert_eq!(rect_area((3, 2), (1, 3)), expected);
+        assert_eq!(rect_area((1, 3), (3, 2)), expected);
+        assert_eq!(rect_area((4, 4), (4, 4)), vec![(4, 4)]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,486 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    f32::consts::SQRT_2,
+};
+
+use amethyst::ecs::prelude::ReadStorage;
+use serde::{Serialize, Deserialize};
+
+use crate::components::{Slope, Tile};
+use crate::resources::{MapDimensions, MapLoadError, TerrainSet, TileMap};
+
+// a tile, as far as pathfinding is concerned
+#[derive(Debug, Clone, Copy)]
+pub struct NavCell {
+    // the cost of moving onto the tile, or None if it can't be walked on
+    pub cost: Option<f32>,
+    pub elevation: usize,
+    pub slope: Slope,
+}
+
+// the grid of tiles paths are found on, row by row
+pub struct NavGrid {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<NavCell>,
+    // the cheapest tile to move onto, which keeps the heuristic from overestimating
+    min_cost: f32,
+}
+
+impl NavGrid {
+    // fails if there isn't a cell for every tile
+    pub fn new(width: usize, height: usize, cells: Vec<NavCell>) -> Result<NavGrid, MapLoadError> {
+        if width * height != cells.len() {
+            return Err(MapLoadError::Dimensions { width, height, found: cells.len() });
+        }
+        Ok(NavGrid::with_cells(width, height, cells))
+    }
+
+    fn with_cells(width: usize, height: usize, cells: Vec<NavCell>) -> NavGrid {
+        let min_cost = cells.iter()
+            .filter_map(|cell| cell.cost)
+            .fold(std::f32::INFINITY, f32::min);
+        NavGrid {
+            width,
+            height,
+            cells,
+            min_cost: if min_cost.is_finite() { min_cost } else { 0. },
+        }
+    }
+
+    // builds a grid from rows of tiles, like Map.tiles, which all have to be the same length
+    pub fn from_tiles(tiles: &[Vec<Tile>], terrain: &TerrainSet) -> Result<NavGrid, MapLoadError> {
+        let height = tiles.len();
+        let width = tiles.first().map_or(0, |row| row.len());
+        if tiles.iter().any(|row| row.len() != width) {
+            let found = tiles.iter().map(|row| row.len()).sum();
+            return Err(MapLoadError::Dimensions { width, height, found });
+        }
+        let cells = tiles.iter()
+            .flat_map(|row| row.iter())
+            .map(|tile| NavCell {
+                cost: terrain.move_cost(tile),
+                elevation: tile.elevation,
+                slope: tile.slope,
+            })
+            .collect();
+        NavGrid::new(width, height, cells)
+    }
+
+    // builds a grid from the tile entities of the map
+    pub fn from_tile_map(dimensions: &MapDimensions, tile_map: &TileMap, tiles: &ReadStorage<Tile>, terrain: &TerrainSet) -> NavGrid {
+        let (w, h) = (dimensions.width, dimensions.height);
+        let cells = (0..w * h)
+            .map(|i| match tiles.get(tile_map.get(i % w, i / w, w)) {
+                Some(tile) => NavCell {
+                    cost: terrain.move_cost(tile),
+                    elevation: tile.elevation,
+                    slope: tile.slope,
+                },
+                _ => NavCell { cost: None, elevation: 0, slope: Slope::None },
+            })
+            .collect();
+        NavGrid::with_cells(w, h, cells)
+    }
+
+    // stops anything from moving onto a tile, like when a wall is on it
+    pub fn block(&mut self, x: usize, y: usize) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x].cost = None;
+        }
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> &NavCell {
+        &self.cells[y * self.width + x]
+    }
+}
+
+// which tiles count as neighbours
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighbourhood {
+    // the tiles sharing an edge
+    Four,
+    // the tiles sharing an edge or a corner
+    Eight,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PathOptions {
+    pub neighbourhood: Neighbourhood,
+    // the biggest elevation difference a unit can step up or down, ramps aside
+    pub max_climb: usize,
+}
+
+impl Default for PathOptions {
+    fn default() -> PathOptions {
+        PathOptions {
+            neighbourhood: Neighbourhood::Four,
+            max_climb: 1,
+        }
+    }
+}
+
+// the direction a slope rises towards, on the map grid
+fn uphill(slope: Slope) -> Option<(isize, isize)> {
+    match slope {
+        Slope::None => None,
+        Slope::NW => Some((-1, 0)),
+        Slope::NE => Some((0, -1)),
+        Slope::SE => Some((1, 0)),
+        Slope::SW => Some((0, 1)),
+    }
+}
+
+// checks if a unit can step from a tile onto the neighbour in direction (dx,dy)
+fn can_step(grid: &NavGrid, x: usize, y: usize, dx: isize, dy: isize, max_climb: usize) -> bool {
+    let (nx, ny) = (x as isize + dx, y as isize + dy);
+    if nx < 0 || ny < 0 || nx >= grid.width as isize || ny >= grid.height as isize {
+        return false;
+    }
+    let from = grid.cell(x, y);
+    let to = grid.cell(nx as usize, ny as usize);
+    if to.cost.is_none() {
+        return false;
+    }
+    let climb = if from.elevation > to.elevation {
+        from.elevation - to.elevation
+    } else {
+        to.elevation - from.elevation
+    };
+    if climb <= max_climb {
+        return true;
+    }
+    // a ramp joins two tiles one level apart, whatever the climb limit is
+    climb == 1
+        && ((to.elevation > from.elevation && uphill(from.slope) == Some((dx, dy)))
+            || (from.elevation > to.elevation && uphill(to.slope) == Some((-dx, -dy))))
+}
+
+// calls f with the index and cost of every tile a unit can step onto from (x,y)
+fn for_each_step<F>(grid: &NavGrid, x: usize, y: usize, options: &PathOptions, mut f: F)
+    where F: FnMut(usize, f32) {
+    let steps: &[(isize, isize)] = match options.neighbourhood {
+        Neighbourhood::Four => &[(-1, 0), (0, -1), (1, 0), (0, 1)],
+        Neighbourhood::Eight => &[(-1, 0), (0, -1), (1, 0), (0, 1), (-1, -1), (1, -1), (1, 1), (-1, 1)],
+    };
+    for (dx, dy) in steps.iter() {
+        if !can_step(grid, x, y, *dx, *dy, options.max_climb) {
+            continue;
+        }
+        let diagonal = *dx != 0 && *dy != 0;
+        // don't cut corners past tiles that can't be stepped onto
+        if diagonal && !(can_step(grid, x, y, *dx, 0, options.max_climb) && can_step(grid, x, y, 0, *dy, options.max_climb)) {
+            continue;
+        }
+        let next = (y as isize + dy) as usize * grid.width + (x as isize + dx) as usize;
+        let step_cost = grid.cells[next].cost.unwrap_or(0.) * if diagonal { SQRT_2 } else { 1. };
+        f(next, step_cost);
+    }
+}
+
+// checks if units can step between two neighbouring tiles in either direction
+pub fn can_cross(grid: &NavGrid, a: (usize, usize), b: (usize, usize), options: &PathOptions) -> bool {
+    let (dx, dy) = (b.0 as isize - a.0 as isize, b.1 as isize - a.1 as isize);
+    can_step(grid, a.0, a.1, dx, dy, options.max_climb) || can_step(grid, b.0, b.1, -dx, -dy, options.max_climb)
+}
+
+// an entry in the open set, ordered so the heap pops the lowest estimate first
+struct Open {
+    estimate: f32,
+    index: usize,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Open) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+    }
+}
+
+// finds the cheapest path between two tiles with A*, including both ends
+pub fn find_path(grid: &NavGrid, start: (usize, usize), goal: (usize, usize), options: &PathOptions) -> Option<Vec<(usize, usize)>> {
+    let (w, h) = (grid.width, grid.height);
+    if start.0 >= w || start.1 >= h || goal.0 >= w || goal.1 >= h || grid.cell(goal.0, goal.1).cost.is_none() {
+        return None;
+    }
+    let heuristic = |x: usize, y: usize| -> f32 {
+        let dx = (x as f32 - goal.0 as f32).abs();
+        let dy = (y as f32 - goal.1 as f32).abs();
+        let distance = match options.neighbourhood {
+            Neighbourhood::Four => dx + dy,
+            Neighbourhood::Eight => dx.max(dy) + (SQRT_2 - 1.) * dx.min(dy),
+        };
+        distance * grid.min_cost
+    };
+
+    let start_index = start.1 * w + start.0;
+    let goal_index = goal.1 * w + goal.0;
+    let mut costs = vec![std::f32::INFINITY; w * h];
+    let mut came_from: Vec<Option<usize>> = vec![None; w * h];
+    let mut open = BinaryHeap::new();
+    costs[start_index] = 0.;
+    open.push(Open { estimate: heuristic(start.0, start.1), index: start_index });
+
+    while let Some(Open { estimate, index }) = open.pop() {
+        let (x, y) = (index % w, index / w);
+        if index == goal_index {
+            let mut path = vec![(x, y)];
+            let mut current = index;
+            while let Some(previous) = came_from[current] {
+                path.push((previous % w, previous / w));
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        // skip entries that were found again more cheaply
+        if estimate > costs[index] + heuristic(x, y) {
+            continue;
+        }
+        for_each_step(grid, x, y, options, |next, step_cost| {
+            let cost = costs[index] + step_cost;
+            if cost < costs[next] {
+                costs[next] = cost;
+                came_from[next] = Some(index);
+                open.push(Open { estimate: cost + heuristic(next % w, next / w), index: next });
+            }
+        });
+    }
+    None
+}
+
+// finds the cost of the cheapest path from any of the starts to every tile, or None where there's no path
+pub fn path_costs(grid: &NavGrid, starts: &[(usize, usize)], options: &PathOptions) -> Vec<Option<f32>> {
+    let w = grid.width;
+    let mut costs = vec![std::f32::INFINITY; w * grid.height];
+    let mut open = BinaryHeap::new();
+    for (x, y) in starts.iter().filter(|(x, y)| *x < w && *y < grid.height) {
+        costs[y * w + x] = 0.;
+        open.push(Open { estimate: 0., index: y * w + x });
+    }
+    while let Some(Open { estimate, index }) = open.pop() {
+        if estimate > costs[index] {
+            continue;
+        }
+        for_each_step(grid, index % w, index / w, options, |next, step_cost| {
+            let cost = costs[index] + step_cost;
+            if cost < costs[next] {
+                costs[next] = cost;
+                open.push(Open { estimate: cost, index: next });
+            }
+        });
+    }
+    costs.into_iter()
+        .map(|cost| if cost.is_finite() { Some(cost) } else { None })
+        .collect()
+}
+
+// labels every tile that can be walked on with the group of tiles units can move between
+// tiles that can't be walked on have no group
+pub fn connected_areas(grid: &NavGrid, options: &PathOptions) -> Vec<Option<usize>> {
+    let w = grid.width;
+    let mut areas = vec![None; w * grid.height];
+    let mut next_area = 0;
+    for start in 0..areas.len() {
+        if areas[start].is_some() || grid.cells[start].cost.is_none() {
+            continue;
+        }
+        areas[start] = Some(next_area);
+        let mut stack = vec![start];
+        while let Some(index) = stack.pop() {
+            for_each_step(grid, index % w, index / w, options, |next, _| {
+                if areas[next].is_none() {
+                    areas[next] = Some(next_area);
+                    stack.push(next);
+                }
+            });
+        }
+        next_area += 1;
+    }
+    areas
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    // a grid from rows of elevation digits, where # is a tile that can't be walked on
+    fn grid(rows: &[&str]) -> NavGrid {
+        let cells = rows.iter()
+            .flat_map(|row| row.chars())
+            .map(|c| match c {
+                '#' => NavCell { cost: None, elevation: 0, slope: Slope::None },
+                _ => NavCell { cost: Some(1.), elevation: c.to_digit(10).unwrap() as usize, slope: Slope::None },
+            })
+            .collect();
+        NavGrid::new(rows[0].len(), rows.len(), cells).unwrap()
+    }
+
+    fn options(neighbourhood: Neighbourhood, max_climb: usize) -> PathOptions {
+        PathOptions { neighbourhood, max_climb }
+    }
+
+    #[test]
+    fn cells_have_to_fill_the_grid() {
+        let cell = NavCell { cost: Some(1.), elevation: 0, slope: Slope::None };
+        assert!(NavGrid::new(2, 2, vec![cell; 3]).is_err());
+        assert!(NavGrid::new(2, 2, vec![cell; 4]).is_ok());
+    }
+
+    #[test]
+    fn climbs_up_to_the_limit() {
+        let steps = grid(&["0124"]);
+        assert!(can_step(&steps, 0, 0, 1, 0, 1));
+        assert!(can_step(&steps, 1, 0, -1, 0, 1));
+        assert!(!can_step(&steps, 2, 0, 1, 0, 1));
+        assert!(!can_step(&steps, 3, 0, -1, 0, 1));
+        assert!(can_step(&steps, 2, 0, 1, 0, 2));
+        assert!(!can_step(&steps, 0, 0, 1, 0, 0));
+        // off the map
+        assert!(!can_step(&steps, 0, 0, -1, 0, 9));
+        assert!(!can_step(&steps, 0, 0, 0, 1, 9));
+    }
+
+    #[test]
+    fn ramps_join_levels_whatever_the_limit() {
+        let mut ramp = grid(&["01"]);
+        assert!(!can_step(&ramp, 0, 0, 1, 0, 0));
+        // a ramp rising towards the higher tile works both ways
+        ramp.cells[0].slope = Slope::SE;
+        assert!(can_step(&ramp, 0, 0, 1, 0, 0));
+        assert!(can_step(&ramp, 1, 0, -1, 0, 0));
+        // a ramp rising the other way doesn't help
+        ramp.cells[0].slope = Slope::NW;
+        assert!(!can_step(&ramp, 0, 0, 1, 0, 0));
+        assert!(!can_step(&ramp, 1, 0, -1, 0, 0));
+
+        // a ramp only climbs one level
+        let mut cliff = grid(&["02"]);
+        cliff.cells[0].slope = Slope::SE;
+        assert!(!can_step(&cliff, 0, 0, 1, 0, 0));
+        assert!(!can_step(&cliff, 0, 0, 1, 0, 1));
+
+        // ramps run along the map axes, in either direction
+        let mut down = grid(&["1", "0"]);
+        down.cells[1].slope = Slope::NE;
+        assert!(can_step(&down, 0, 1, 0, -1, 0));
+        assert!(can_cross(&down, (0, 0), (0, 1), &options(Neighbourhood::Four, 0)));
+    }
+
+    #[test]
+    fn eight_neighbours_cut_across() {
+        let open = grid(&["000", "000", "000"]);
+        let four = find_path(&open, (0, 0), (2, 2), &options(Neighbourhood::Four, 1)).unwrap();
+        let eight = find_path(&open, (0, 0), (2, 2), &options(Neighbourhood::Eight, 1)).unwrap();
+        assert_eq!(four.len(), 5);
+        assert_eq!(eight, vec![(0, 0), (1, 1), (2, 2)]);
+        for pair in four.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!((a.0 as isize - b.0 as isize).abs() + (a.1 as isize - b.1 as isize).abs(), 1);
+        }
+
+        let costs = path_costs(&open, &[(0, 0)], &options(Neighbourhood::Four, 1));
+        assert_eq!(costs[8], Some(4.));
+        let costs = path_costs(&open, &[(0, 0)], &options(Neighbourhood::Eight, 1));
+        assert!((costs[8].unwrap() - 2. * SQRT_2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn diagonals_dont_cut_corners() {
+        let corner = grid(&["0#", "00"]);
+        let path = find_path(&corner, (0, 0), (1, 1), &options(Neighbourhood::Eight, 1)).unwrap();
+        assert_eq!(path, vec![(0, 0), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn paths_go_around_walls_and_cliffs() {
+        let walled = grid(&["0#0", "0#0", "000"]);
+        let path = find_path(&walled, (0, 0), (2, 0), &options(Neighbourhood::Four, 1)).unwrap();
+        assert_eq!(path.len(), 7);
+        assert_eq!((path[0], path[6]), ((0, 0), (2, 0)));
+    }
+
+    #[test]
+    fn unreachable_goals_have_no_path() {
+        let four = options(Neighbourhood::Four, 1);
+        let walled = grid(&["0#0"]);
+        assert_eq!(find_path(&walled, (0, 0), (2, 0), &four), None);
+        assert_eq!(find_path(&walled, (0, 0), (1, 0), &four), None);
+        assert_eq!(find_path(&walled, (0, 0), (3, 0), &four), None);
+        let cliff = grid(&["090"]);
+        assert_eq!(find_path(&cliff, (0, 0), (2, 0), &four), None);
+        assert_eq!(path_costs(&cliff, &[(0, 0)], &four), vec![Some(0.), None, None]);
+        // a start is always reachable from itself
+        assert_eq!(find_path(&cliff, (2, 0), (2, 0), &four), Some(vec![(2, 0)]));
+    }
+
+    #[test]
+    fn areas_are_split_by_walls_and_cliffs() {
+        let four = options(Neighbourhood::Four, 1);
+        let split = grid(&["00#00", "00#55"]);
+        let areas = connected_areas(&split, &four);
+        assert_eq!(areas[..5], [Some(0), Some(0), None, Some(1), Some(1)]);
+        assert_eq!(areas[5..], [Some(0), Some(0), None, Some(2), Some(2)]);
+
+        // a ramp joins the cliff back up
+        let mut ramped = grid(&["012"]);
+        assert_eq!(connected_areas(&ramped, &options(Neighbourhood::Four, 0)), vec![Some(0), Some(1), Some(2)]);
+        ramped.cells[0].slope = Slope::SE;
+        ramped.cells[1].slope = Slope::SE;
+        assert_eq!(connected_areas(&ramped, &options(Neighbourhood::Four, 0)), vec![Some(0); 3]);
+    }
+
+    // a grid of walls with gaps at alternating ends, so paths have to wind through all of it
+    fn maze(size: usize) -> NavGrid {
+        let cells = (0..size * size)
+            .map(|i| {
+                let (x, y) = (i % size, i / size);
+                let gap = if (x / 4) % 2 == 0 { size - 1 } else { 0 };
+                let wall = x % 4 == 1 && y != gap;
+                NavCell {
+                    cost: if wall { None } else { Some(1. + (x * y % 3) as f32 * 0.5) },
+                    elevation: (x + y) / 16 % 2,
+                    slope: Slope::None,
+                }
+            })
+            .collect();
+        NavGrid::new(size, size, cells).unwrap()
+    }
+
+    // cargo test --release benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark_find_path() {
+        let runs = 10;
+        for size in [64, 256].iter() {
+            let open = NavGrid::new(*size, *size, vec![NavCell { cost: Some(1.), elevation: 0, slope: Slope::None }; size * size]).unwrap();
+            let maze = maze(*size);
+            for (name, grid) in [("open", &open), ("maze", &maze)].iter() {
+                for neighbourhood in [Neighbourhood::Four, Neighbourhood::Eight].iter() {
+                    let options = options(*neighbourhood, 1);
+                    let start = Instant::now();
+                    for _ in 0..runs {
+                        assert!(find_path(grid, (0, 0), (size - 1, size - 1), &options).is_some());
+                    }
+                    println!("{}x{} {} {:?}: {:?} per path", size, size, name, neighbourhood, start.elapsed() / runs);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::components::Slope;
+use crate::util::{tile_exists, world_to_map_iso_simple, ELEVATION};
+
+// how far the top face of a sloped tile is above its middle, at an offset (in world coordinates)
+// from the middle of the face
+pub fn slope_lift(slope: Slope, offset_x: f32, offset_y: f32) -> f32 {
+    // measure the offset along the map axes, which is the direction the slope runs in
+    let (map_x, map_y) = world_to_map_iso_simple(offset_x, offset_y);
+    let uphill = match slope {
+        Slope::None => 0.,
+        Slope::NW => -map_x,
+        Slope::NE => -map_y,
+        Slope::SE => map_x,
+        Slope::SW => map_y,
+    };
+    uphill.max(-0.5).min(0.5) * ELEVATION
+}
+
+// picks the slope for a tile from the elevations of its neighbours, in the order NW, NE, SE, SW
+// a tile slopes up towards a neighbour one level higher, as long as that's the only way it could slope
+pub fn infer_slope(elevation: usize, neighbours: [Option<usize>; 4]) -> Slope {
+    let directions = [Slope::NW, Slope::NE, Slope::SE, Slope::SW];
+    let higher: Vec<usize> = (0..4)
+        .filter(|i| neighbours[*i] == Some(elevation + 1))
+        .collect();
+    if higher.len() != 1 {
+        return Slope::None;
+    }
+    let uphill = higher[0];
+    // a ramp can't come up out of a hole
+    match neighbours[(uphill + 2) % 4] {
+        Some(e) if e > elevation => Slope::None,
+        _ => directions[uphill],
+    }
+}
+
+// works out the slope of every tile in a grid of elevations, row by row
+// tiles that can't ramp are left flat
+pub fn infer_slopes(elevations: &[usize], can_ramp: &[bool], width: usize, height: usize) -> Vec<Slope> {
+    let (w, h) = (width as isize, height as isize);
+    (0..width * height)
+        .map(|i| {
+            if !can_ramp[i] {
+                return Slope::None;
+            }
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let mut neighbours = [None; 4];
+            for (n, (dx, dy)) in [(-1, 0), (0, -1), (1, 0), (0, 1)].iter().enumerate() {
+                let (nx, ny) = (x + dx, y + dy);
+                if tile_exists(nx, ny, w, h) {
+                    neighbours[n] = Some(elevations[(ny * w + nx) as usize]);
+                }
+            }
+            infer_slope(elevations[i], neighbours)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_ground_stays_flat() {
+        assert_eq!(infer_slope(2, [Some(2), Some(2), Some(2), Some(2)]), Slope::None);
+        assert_eq!(infer_slope(2, [Some(1), Some(1), Some(1), Some(1)]), Slope::None);
+        assert_eq!(infer_slope(0, [None, None, None, None]), Slope::None);
+    }
+
+    #[test]
+    fn slopes_up_towards_the_one_higher_neighbour() {
+        assert_eq!(infer_slope(1, [Some(2), Some(1), Some(1), Some(1)]), Slope::NW);
+        assert_eq!(infer_slope(1, [Some(1), Some(2), Some(1), Some(1)]), Slope::NE);
+        assert_eq!(infer_slope(1, [Some(1), Some(1), Some(2), Some(1)]), Slope::SE);
+        assert_eq!(infer_slope(1, [Some(1), Some(1), Some(1), Some(2)]), Slope::SW);
+        // a lower neighbour on the other side is the bottom of the ramp
+        assert_eq!(infer_slope(1, [Some(2), Some(1), Some(0), Some(1)]), Slope::NW);
+    }
+
+    #[test]
+    fn steps_of_more_than_one_level_stay_flat() {
+        assert_eq!(infer_slope(0, [Some(2), Some(0), Some(0), Some(0)]), Slope::None);
+    }
+
+    #[test]
+    fn ambiguous_tiles_stay_flat() {
+        // an inside corner, higher on two sides
+        assert_eq!(infer_slope(0, [Some(1), Some(1), Some(0), Some(0)]), Slope::None);
+        // a trench between two higher sides
+        assert_eq!(infer_slope(0, [Some(1), Some(0), Some(1), Some(0)]), Slope::None);
+        // a hole, one level up on one side and further up on the other
+        assert_eq!(infer_slope(0, [None, Some(1), None, Some(3)]), Slope::None);
+    }
+
+    #[test]
+    fn map_edges_count_as_not_higher() {
+        assert_eq!(infer_slope(0, [None, None, Some(1), None]), Slope::SE);
+        assert_eq!(infer_slope(0, [Some(1), None, None, None]), Slope::NW);
+    }
+
+    #[test]
+    fn grid_tiles_look_at_their_own_neighbours() {
+        // a raised row down the middle of the map, running from its north east edge to its south west edge
+        let elevations = [
+            0, 1, 0,
+            0, 1, 0,
+            0, 1, 0,
+        ];
+        let slopes = infer_slopes(&elevations, &[true; 9], 3, 3);
+        for y in 0..3 {
+            assert_eq!(slopes[y * 3], Slope::SE);
+            assert_eq!(slopes[y * 3 + 1], Slope::None);
+            assert_eq!(slopes[y * 3 + 2], Slope::NW);
+        }
+    }
+
+    #[test]
+    fn grid_edges_and_corners() {
+        let elevations = [
+            1, 0,
+            0, 0,
+        ];
+        let slopes = infer_slopes(&elevations, &[true; 4], 2, 2);
+        assert_eq!(slopes, vec![Slope::None, Slope::NW, Slope::NE, Slope::None]);
+
+        // a single row, where the tiles above and below are off the map
+        let slopes = infer_slopes(&[0, 1, 2], &[true; 3], 3, 1);
+        assert_eq!(slopes, vec![Slope::SE, Slope::SE, Slope::None]);
+    }
+
+    #[test]
+    fn tiles_that_cant_ramp_stay_flat() {
+        let slopes = infer_slopes(&[0, 1], &[false, true], 2, 1);
+        assert_eq!(slopes, vec![Slope::None, Slope::None]);
+    }
+}